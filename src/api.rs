@@ -0,0 +1,162 @@
+use crate::constants;
+
+use log::{debug, info};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Resources of the Horizon REST API available in several versions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Resource {
+    DesktopPools,
+    Machines,
+    Sessions,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Resource::DesktopPools => "desktop pools",
+            Resource::Machines => "machines",
+            Resource::Sessions => "sessions",
+        })
+    }
+}
+
+// Endpoint and the first Horizon version (major, minor) providing it
+struct Endpoint {
+    path: &'static str,
+    since: (u32, u32),
+}
+
+// Supported endpoints of a resource, highest version first. The last entry is available in
+// all Horizon versions.
+fn endpoints(resource: Resource) -> &'static [Endpoint] {
+    match resource {
+        Resource::DesktopPools => &[
+            Endpoint {
+                path: constants::REST_DESKTOP_POOLS_V3,
+                since: (8, 8),
+            },
+            Endpoint {
+                path: constants::REST_DESKTOP_POOLS_V2,
+                since: (8, 3),
+            },
+            Endpoint {
+                path: constants::REST_DESKTOP_POOLS_V1,
+                since: (0, 0),
+            },
+        ],
+        Resource::Machines => &[
+            Endpoint {
+                path: constants::REST_MACHINES_V3,
+                since: (8, 13),
+            },
+            Endpoint {
+                path: constants::REST_MACHINES_V2,
+                since: (8, 3),
+            },
+            Endpoint {
+                path: constants::REST_MACHINES_V1,
+                since: (0, 0),
+            },
+        ],
+        Resource::Sessions => &[
+            Endpoint {
+                path: constants::REST_SESSIONS_V2,
+                since: (8, 12),
+            },
+            Endpoint {
+                path: constants::REST_SESSIONS_V1,
+                since: (0, 0),
+            },
+        ],
+    }
+}
+
+// Parse the major and minor number of a version like 8.10.0
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.trim().split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next().unwrap_or("0").parse().ok()?;
+    Some((major, minor))
+}
+
+/// Endpoints selected for the Horizon version
+///
+/// Without a known Horizon version the oldest endpoints are used.
+#[derive(Clone, Debug, Default)]
+pub struct ApiVersions {
+    version: Option<String>,
+    // index into the list of supported endpoints
+    selected: HashMap<Resource, usize>,
+}
+
+impl ApiVersions {
+    /// Select the highest supported endpoints for a Horizon version. Endpoints selected by
+    /// a fallback are kept if the version didn't change.
+    pub fn select(&mut self, version: &str) {
+        if self.version.as_deref() == Some(version) {
+            return;
+        }
+
+        self.selected.clear();
+        self.version = Some(version.to_string());
+
+        let parsed = match parse_version(version) {
+            Some(v) => v,
+            None => {
+                info!(
+                    "can't parse Horizon version {}, using oldest REST API endpoints",
+                    version
+                );
+                return;
+            }
+        };
+
+        for resource in [
+            Resource::DesktopPools,
+            Resource::Machines,
+            Resource::Sessions,
+        ] {
+            let list = endpoints(resource);
+            let idx = list
+                .iter()
+                .position(|e| e.since <= parsed)
+                .unwrap_or(list.len() - 1);
+            debug!(
+                "api.rs:select: using {} for {} on Horizon {}",
+                list[idx].path, resource, version
+            );
+            self.selected.insert(resource, idx);
+        }
+    }
+
+    pub fn path(&self, resource: Resource) -> &'static str {
+        let list = endpoints(resource);
+        match self.selected.get(&resource) {
+            Some(idx) => list[*idx].path,
+            None => list[list.len() - 1].path,
+        }
+    }
+
+    /// Switch to the next lower version of an endpoint, returns false if there is none
+    pub fn fall_back(&mut self, resource: Resource) -> bool {
+        let list = endpoints(resource);
+        let idx = self
+            .selected
+            .get(&resource)
+            .copied()
+            .unwrap_or(list.len() - 1);
+        if idx + 1 >= list.len() {
+            return false;
+        }
+
+        info!(
+            "{} is not available, falling back to {}",
+            list[idx].path,
+            list[idx + 1].path
+        );
+        self.selected.insert(resource, idx + 1);
+        true
+    }
+}
@@ -0,0 +1,103 @@
+use crate::configuration;
+use crate::horizon::HorizonClient;
+use crate::RecorderMode;
+
+use std::error::Error;
+
+pub fn run(config_file: Option<&str>, online: bool, recorder: &RecorderMode) -> bool {
+    match config_file {
+        Some(v) => println!("Checking configuration file {}", v),
+        None => println!("Checking configuration from environment variables"),
+    };
+
+    let unknown_keys = match configuration::find_unknown_keys(config_file) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Error: can't parse configuration: {}", e);
+            return false;
+        }
+    };
+
+    if !unknown_keys.is_empty() {
+        for k in unknown_keys.iter() {
+            println!("Error: unknown configuration key {}", k);
+        }
+        return false;
+    }
+
+    let config = match configuration::parse_config_file(config_file) {
+        Ok(v) => v,
+        Err(e) => {
+            println!("Error: {}", e);
+            return false;
+        }
+    };
+
+    println!("Configuration is valid");
+
+    if online {
+        if let Err(e) = check_online(&config, recorder) {
+            println!("Error: {}", e);
+            return false;
+        }
+    }
+
+    true
+}
+
+fn check_online(
+    cfg: &configuration::Configuration,
+    recorder: &RecorderMode,
+) -> Result<(), Box<dyn Error>> {
+    let mut client = HorizonClient::new(cfg)?;
+    client.set_recorder(recorder.clone())?;
+
+    println!("Logging in to {}", cfg.horizon_api.url_description());
+    client.login()?;
+    println!("Login to {} successful", client.endpoint());
+
+    let result = list_pools(cfg, &mut client);
+
+    if let Err(e) = client.logout() {
+        println!("Warning: logout failed: {}", e);
+    }
+
+    result
+}
+
+fn list_pools(
+    cfg: &configuration::Configuration,
+    client: &mut HorizonClient,
+) -> Result<(), Box<dyn Error>> {
+    match client.environment_properties() {
+        Ok(v) => println!(
+            "Horizon version {}, build {}",
+            v.local_connection_server_version
+                .unwrap_or_else(|| "unknown".to_string()),
+            v.local_connection_server_build
+                .unwrap_or_else(|| "unknown".to_string())
+        ),
+        Err(e) => println!("Warning: can't get Horizon version: {}", e),
+    };
+
+    let pools = client.desktop_pools()?;
+    println!("Desktop pools:");
+    for dp in pools.iter() {
+        let exported = cfg.horizon_api.exports_pool(&dp.id);
+        println!(
+            "    {} {} (name: {}, label: {})",
+            if exported { "export" } else { "skip  " },
+            dp.id,
+            dp.name.as_deref().unwrap_or("-"),
+            cfg.horizon_api.user_defined_pool_uuid_resolve(&dp.id)
+        );
+    }
+
+    for p in cfg.horizon_api.only_pools_set.iter() {
+        if !pools.iter().any(|dp| &dp.id == p) {
+            println!("Warning: pool {} from only_pools doesn't exist", p);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,480 @@
+use crate::api::ApiVersions;
+use crate::configuration;
+use crate::constants;
+use crate::data;
+use crate::horizon::HorizonClient;
+use crate::machines;
+use crate::recorder;
+use crate::sessions;
+use crate::status::Status;
+
+use log::{debug, error, info, warn};
+use prometheus::{GaugeVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry};
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Metrics updated by the collector
+pub struct Metrics {
+    pub sessions: IntGaugeVec,
+    pub agent_versions: IntGaugeVec,
+    pub agent_version_info: IntGaugeVec,
+    pub session_protocols: IntGaugeVec,
+    pub session_types: IntGaugeVec,
+    pub machine_states: IntGaugeVec,
+    pub machine_os: IntGaugeVec,
+    pub machine_os_info: IntGaugeVec,
+    pub machine_arch: IntGaugeVec,
+    pub machine_arch_info: IntGaugeVec,
+    pub build_info: IntGaugeVec,
+    pub active_endpoint: IntGaugeVec,
+    pub endpoint_failovers: IntCounter,
+    pub new_connections: IntCounter,
+    pub collector_duration: GaugeVec,
+    pub unknown_enum_values: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        Ok(Metrics {
+            sessions: IntGaugeVec::new(
+                Opts::new(constants::SESSIONS_NAME, constants::SESSIONS_HELP),
+                &["pool", "state"],
+            )?,
+            agent_versions: IntGaugeVec::new(
+                Opts::new(
+                    constants::AGENT_VERSIONS_NAME,
+                    constants::AGENT_VERSIONS_HELP,
+                ),
+                &["pool", "version"],
+            )?,
+            agent_version_info: IntGaugeVec::new(
+                Opts::new(
+                    constants::AGENT_VERSION_INFO_NAME,
+                    constants::AGENT_VERSION_INFO_HELP,
+                ),
+                &["pool", "version"],
+            )?,
+            session_protocols: IntGaugeVec::new(
+                Opts::new(
+                    constants::SESSION_PROTOCOLS_NAME,
+                    constants::SESSION_PROTOCOLS_HELP,
+                ),
+                &["pool", "protocol"],
+            )?,
+            session_types: IntGaugeVec::new(
+                Opts::new(constants::SESSION_TYPES_NAME, constants::SESSION_TYPES_HELP),
+                &["pool", "type"],
+            )?,
+            machine_states: IntGaugeVec::new(
+                Opts::new(
+                    constants::MACHINE_STATES_NAME,
+                    constants::MACHINE_STATES_HELP,
+                ),
+                &["pool", "state"],
+            )?,
+            machine_os: IntGaugeVec::new(
+                Opts::new(constants::MACHINE_OS_NAME, constants::MACHINE_OS_HELP),
+                &["pool", "operating_system"],
+            )?,
+            machine_os_info: IntGaugeVec::new(
+                Opts::new(
+                    constants::MACHINE_OS_INFO_NAME,
+                    constants::MACHINE_OS_INFO_HELP,
+                ),
+                &["pool", "operating_system"],
+            )?,
+            machine_arch: IntGaugeVec::new(
+                Opts::new(constants::MACHINE_ARCH_NAME, constants::MACHINE_ARCH_HELP),
+                &["pool", "architecture"],
+            )?,
+            machine_arch_info: IntGaugeVec::new(
+                Opts::new(
+                    constants::MACHINE_ARCH_INFO_NAME,
+                    constants::MACHINE_ARCH_INFO_HELP,
+                ),
+                &["pool", "architecture"],
+            )?,
+            build_info: IntGaugeVec::new(
+                Opts::new(constants::BUILD_INFO_NAME, constants::BUILD_INFO_HELP),
+                &["version", "build"],
+            )?,
+            active_endpoint: IntGaugeVec::new(
+                Opts::new(
+                    constants::ACTIVE_ENDPOINT_NAME,
+                    constants::ACTIVE_ENDPOINT_HELP,
+                ),
+                &["url"],
+            )?,
+            endpoint_failovers: IntCounter::new(
+                constants::ENDPOINT_FAILOVERS_NAME,
+                constants::ENDPOINT_FAILOVERS_HELP,
+            )?,
+            new_connections: IntCounter::new(
+                constants::NEW_CONNECTIONS_NAME,
+                constants::NEW_CONNECTIONS_HELP,
+            )?,
+            collector_duration: GaugeVec::new(
+                Opts::new(
+                    constants::COLLECTOR_DURATION_NAME,
+                    constants::COLLECTOR_DURATION_HELP,
+                ),
+                &["collector"],
+            )?,
+            unknown_enum_values: IntCounterVec::new(
+                Opts::new(
+                    constants::UNKNOWN_ENUM_VALUES_NAME,
+                    constants::UNKNOWN_ENUM_VALUES_HELP,
+                ),
+                &["field", "value"],
+            )?,
+        })
+    }
+
+    fn register(&self, registry: &Registry) -> Result<(), prometheus::Error> {
+        registry.register(Box::new(self.sessions.clone()))?;
+        registry.register(Box::new(self.agent_versions.clone()))?;
+        registry.register(Box::new(self.agent_version_info.clone()))?;
+        registry.register(Box::new(self.session_protocols.clone()))?;
+        registry.register(Box::new(self.session_types.clone()))?;
+        registry.register(Box::new(self.machine_states.clone()))?;
+        registry.register(Box::new(self.machine_os.clone()))?;
+        registry.register(Box::new(self.machine_os_info.clone()))?;
+        registry.register(Box::new(self.machine_arch.clone()))?;
+        registry.register(Box::new(self.machine_arch_info.clone()))?;
+        registry.register(Box::new(self.build_info.clone()))?;
+        registry.register(Box::new(self.active_endpoint.clone()))?;
+        registry.register(Box::new(self.endpoint_failovers.clone()))?;
+        registry.register(Box::new(self.new_connections.clone()))?;
+        registry.register(Box::new(self.collector_duration.clone()))?;
+        registry.register(Box::new(self.unknown_enum_values.clone()))?;
+        Ok(())
+    }
+
+    // Values unknown to the exporter are still counted in the metrics, but should be added to
+    // the enumerations in data.rs
+    pub(crate) fn unknown_enum_value(&self, field: &str, value: &str, id: &str) {
+        warn!(
+            "unknown value {} of field {} for id {}, please report it to the exporter developers",
+            value, field, id
+        );
+        self.unknown_enum_values
+            .with_label_values(&[field, value])
+            .inc();
+    }
+}
+
+// Info metrics have the value 1 and only exist for label combinations with at least one
+// session or machine, the number is kept in a separate gauge
+pub(crate) fn set_info(info: &IntGaugeVec, labels: &[&str], count: i64) {
+    if count > 0 {
+        info.with_label_values(labels).set(1);
+    } else {
+        let _ = info.remove_label_values(labels);
+    }
+}
+
+// State kept between collections: the counters of the previous collection to reset stale
+// values to zero, the REST API endpoints selected for the Horizon version and the client
+// keeping the connections to Horizon open
+#[derive(Default)]
+struct Counters {
+    sessions: sessions::SessionCounters,
+    machines: machines::MachineCounters,
+    api: ApiVersions,
+    client: Option<HorizonClient>,
+    recorder: recorder::Mode,
+}
+
+// Inventory of the exported pools from the last successful collection
+#[derive(Default)]
+struct Snapshot {
+    desktop_pools: Vec<data::DesktopPool>,
+    sessions: Vec<data::Session>,
+    machines: Vec<data::Machine>,
+}
+
+/// Collects the Horizon metrics into the gauges registered in a `prometheus::Registry`
+pub struct Collector {
+    metrics: Metrics,
+    counters: Mutex<Counters>,
+    snapshot: Mutex<Snapshot>,
+    status: Mutex<Status>,
+}
+
+impl Collector {
+    /// Create a collector and register its metrics in `registry`
+    pub fn new(registry: &Registry) -> Result<Self, prometheus::Error> {
+        let metrics = Metrics::new()?;
+        metrics.register(registry)?;
+
+        Ok(Collector {
+            metrics,
+            counters: Mutex::new(Counters::default()),
+            snapshot: Mutex::new(Snapshot::default()),
+            status: Mutex::new(Status::default()),
+        })
+    }
+
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Time of the last collection and login and the result of the single collectors
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    /// Save the responses of Horizon to a directory or replay saved responses instead of
+    /// sending requests
+    pub fn set_recorder(&self, mode: recorder::Mode) -> Result<(), Box<dyn Error>> {
+        mode.prepare()?;
+        let mut counters = self.counters.lock().unwrap();
+        if let Some(c) = counters.client.as_mut() {
+            c.set_recorder(mode.clone())?;
+        }
+        counters.recorder = mode;
+        Ok(())
+    }
+
+    /// Desktop pools returned by Horizon in the last collection
+    pub fn desktop_pools(&self) -> Vec<data::DesktopPool> {
+        self.snapshot.lock().unwrap().desktop_pools.clone()
+    }
+
+    /// Sessions of the exported pools from the last successful session collection
+    pub fn sessions(&self) -> Vec<data::Session> {
+        self.snapshot.lock().unwrap().sessions.clone()
+    }
+
+    /// Machines of the exported pools from the last successful machine collection
+    pub fn machines(&self) -> Vec<data::Machine> {
+        self.snapshot.lock().unwrap().machines.clone()
+    }
+
+    /// Login to Horizon, update the metrics and logout
+    ///
+    /// Returns false if the login or one of the collectors failed. Concurrent calls
+    /// are serialised.
+    pub fn collect(&self, cfg: &configuration::Configuration) -> bool {
+        let mut counters = self.counters.lock().unwrap();
+
+        // the client is rebuilt after a configuration reload or if a certificate file changed
+        let mut client = match counters.client.take() {
+            Some(v) if v.is_current(cfg) => v,
+            previous => {
+                if previous.is_some() {
+                    info!("Horizon configuration or certificate files changed, creating new HTTP client");
+                }
+                match HorizonClient::new(cfg).and_then(|mut c| {
+                    c.set_recorder(counters.recorder.clone())?;
+                    Ok(c)
+                }) {
+                    Ok(v) => v,
+                    Err(e) => {
+                        error!("can't create HTTP client structure: {}", e);
+                        self.status.lock().unwrap().set_refresh(false);
+                        return false;
+                    }
+                }
+            }
+        };
+
+        client.set_api_versions(counters.api.clone());
+        let success = self.update(cfg, &mut client, &mut counters);
+        counters.api = client.api_versions().clone();
+        self.metrics
+            .new_connections
+            .inc_by(client.take_new_connections());
+        counters.client = Some(client);
+        self.status.lock().unwrap().set_refresh(success);
+
+        success
+    }
+
+    fn update(
+        &self,
+        cfg: &configuration::Configuration,
+        client: &mut HorizonClient,
+        counters: &mut Counters,
+    ) -> bool {
+        let mut success = true;
+
+        debug!("collector.rs:update: login to horizon");
+        let start = Instant::now();
+        let login = client.login();
+        self.metrics
+            .endpoint_failovers
+            .inc_by(client.failovers() as u64);
+        // servers removed by a configuration reload must not stay active
+        self.metrics.active_endpoint.reset();
+        for url in cfg.horizon_api.url_list.iter() {
+            let active = login.is_ok() && url == client.endpoint();
+            self.metrics
+                .active_endpoint
+                .with_label_values(&[url])
+                .set(active as i64);
+        }
+
+        if let Err(e) = login {
+            error!(
+                collector = constants::COLLECTOR_LOGIN, duration_ms = duration_ms(start);
+                "horizon login failed: {}",
+                e
+            );
+            self.collector_failed(constants::COLLECTOR_LOGIN, start, &e.to_string());
+            return false;
+        }
+        self.status.lock().unwrap().set_login_success();
+        self.collector_finished(constants::COLLECTOR_LOGIN, start);
+
+        debug!("collector.rs:update: getting Horizon version information");
+        let start = Instant::now();
+        match client.environment_properties() {
+            Ok(v) => {
+                self.metrics.build_info.reset();
+                self.metrics
+                    .build_info
+                    .with_label_values(&[
+                        v.local_connection_server_version
+                            .as_deref()
+                            .unwrap_or("unknown"),
+                        v.local_connection_server_build
+                            .as_deref()
+                            .unwrap_or("unknown"),
+                    ])
+                    .set(1);
+                self.status.lock().unwrap().set_horizon_version(
+                    v.local_connection_server_version,
+                    v.local_connection_server_build,
+                );
+                self.collector_finished(constants::COLLECTOR_VERSION, start);
+            }
+            Err(e) => {
+                warn!(
+                    collector = constants::COLLECTOR_VERSION, duration_ms = duration_ms(start);
+                    "can't get Horizon version information: {}",
+                    e
+                );
+                self.collector_failed(constants::COLLECTOR_VERSION, start, &e.to_string());
+            }
+        };
+
+        // fetch pool data only once
+        debug!("collector.rs:update: getting list of desktop pools");
+        let start = Instant::now();
+        let desktop_pools = match client.desktop_pools() {
+            Ok(v) => {
+                self.collector_finished(constants::COLLECTOR_DESKTOP_POOLS, start);
+                v
+            }
+            Err(e) => {
+                error!(
+                    collector = constants::COLLECTOR_DESKTOP_POOLS, duration_ms = duration_ms(start);
+                    "can't get list of desktop pools: {}",
+                    e
+                );
+                self.collector_failed(constants::COLLECTOR_DESKTOP_POOLS, start, &e.to_string());
+                self.logout(client);
+                return false;
+            }
+        };
+        self.snapshot.lock().unwrap().desktop_pools = desktop_pools.clone();
+
+        let start = Instant::now();
+        match sessions::session_metric_update(
+            cfg,
+            client,
+            &self.metrics,
+            &mut counters.sessions,
+            &desktop_pools,
+        ) {
+            Ok(v) => {
+                self.snapshot.lock().unwrap().sessions = v;
+                self.collector_finished(constants::COLLECTOR_SESSIONS, start);
+            }
+            Err(e) => {
+                error!(
+                    collector = constants::COLLECTOR_SESSIONS, duration_ms = duration_ms(start);
+                    "session metric update failed: {}",
+                    e
+                );
+                self.collector_failed(constants::COLLECTOR_SESSIONS, start, &e.to_string());
+                success = false;
+            }
+        };
+
+        let start = Instant::now();
+        match machines::machine_metric_update(
+            cfg,
+            client,
+            &self.metrics,
+            &mut counters.machines,
+            &desktop_pools,
+        ) {
+            Ok(v) => {
+                self.snapshot.lock().unwrap().machines = v;
+                self.collector_finished(constants::COLLECTOR_MACHINES, start);
+            }
+            Err(e) => {
+                error!(
+                    collector = constants::COLLECTOR_MACHINES, duration_ms = duration_ms(start);
+                    "machine metric update failed: {}",
+                    e
+                );
+                self.collector_failed(constants::COLLECTOR_MACHINES, start, &e.to_string());
+                success = false;
+            }
+        };
+
+        self.logout(client);
+
+        success
+    }
+
+    fn collector_finished(&self, collector: &str, start: Instant) {
+        debug!(
+            collector = collector, duration_ms = duration_ms(start);
+            "collector.rs:collector_finished: {} finished in {} ms",
+            collector,
+            duration_ms(start)
+        );
+        self.observe_duration(collector, start);
+        self.status.lock().unwrap().collector_success(collector);
+    }
+
+    fn collector_failed(&self, collector: &str, start: Instant, err: &str) {
+        self.observe_duration(collector, start);
+        self.status
+            .lock()
+            .unwrap()
+            .collector_failure(collector, err);
+    }
+
+    fn observe_duration(&self, collector: &str, start: Instant) {
+        self.metrics
+            .collector_duration
+            .with_label_values(&[collector])
+            .set(start.elapsed().as_secs_f64());
+    }
+
+    fn logout(&self, client: &mut HorizonClient) {
+        debug!("collector.rs:logout: logout from horizon");
+        let start = Instant::now();
+        match client.logout() {
+            Ok(_) => self.collector_finished(constants::COLLECTOR_LOGOUT, start),
+            Err(e) => {
+                warn!(
+                    collector = constants::COLLECTOR_LOGOUT, duration_ms = duration_ms(start);
+                    "horizon logout failed: {}",
+                    e
+                );
+                self.collector_failed(constants::COLLECTOR_LOGOUT, start, &e.to_string());
+            }
+        };
+    }
+}
+
+fn duration_ms(start: Instant) -> u64 {
+    start.elapsed().as_millis() as u64
+}
@@ -1,35 +1,330 @@
-use serde::Deserialize;
+use crate::constants;
+use crate::tls;
+
+use log::warn;
+use serde::de::DeserializeSeed;
+use serde::{de, Deserialize, Deserializer};
 use simple_error::bail;
 use std::collections::{HashMap, HashSet};
+use std::env;
 use std::error::Error;
+use std::fmt;
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+
+pub type SharedConfiguration = Arc<RwLock<Arc<Configuration>>>;
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Configuration {
     pub horizon_api: HorizonAPIConfig,
+    #[serde(default)]
+    pub exporter: ExporterConfig,
+    pub pushgateway: Option<PushgatewayConfig>,
+    pub remote_write: Option<RemoteWriteConfig>,
+    pub influxdb: Option<InfluxDBConfig>,
+    pub graphite: Option<GraphiteConfig>,
+    pub otlp: Option<OtlpConfig>,
+    #[serde(skip)]
+    pub sources: HashMap<String, String>,
+}
+
+/// User and password for basic authentication, the `auth` dictionary of the outputs and the proxy
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BasicAuthConfig {
+    pub user: Option<String>,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+}
+
+/// HTTP client settings, the `http` dictionary of the outputs sending metrics by HTTP
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpSinkConfig {
+    pub headers: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub insecure_ssl: Option<bool>,
+    pub ca_file: Option<String>,
+    pub ca_dir: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_pkcs12: Option<String>,
+    pub client_pkcs12_password: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub timeout: Option<u64>,
+}
+
+impl fmt::Debug for HttpSinkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpSinkConfig")
+            .field("headers", &self.headers)
+            .field("insecure_ssl", &self.insecure_ssl)
+            .field("ca_file", &self.ca_file)
+            .field("ca_dir", &self.ca_dir)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("client_pkcs12", &self.client_pkcs12)
+            .field(
+                "client_pkcs12_password",
+                &redacted(&self.client_pkcs12_password),
+            )
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PushgatewayConfig {
+    pub url: String,
+    pub job: Option<String>,
+    pub instance: Option<String>,
+    pub grouping: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub auth: BasicAuthConfig,
+    #[serde(default)]
+    pub http: HttpSinkConfig,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub url: Option<String>,
+    #[serde(default)]
+    pub auth: BasicAuthConfig,
+    pub no_proxy: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub disable: Option<bool>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteWriteConfig {
+    pub url: String,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub interval: Option<u64>,
+    pub external_labels: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub queue_size: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub min_backoff: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub max_backoff: Option<u64>,
+    #[serde(default)]
+    pub auth: BasicAuthConfig,
+    #[serde(default)]
+    pub http: HttpSinkConfig,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDBConfig {
+    pub url: String,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub api_version: Option<u8>,
+    pub database: Option<String>,
+    pub retention_policy: Option<String>,
+    pub org: Option<String>,
+    pub bucket: Option<String>,
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    pub tags: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub auth: BasicAuthConfig,
+    #[serde(default)]
+    pub http: HttpSinkConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphiteConfig {
+    pub address: String,
+    pub prefix: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub tagged: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub interval: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub timeout: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpConfig {
+    pub url: String,
+    pub service_name: Option<String>,
+    pub resource_attributes: Option<HashMap<String, String>>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub interval: Option<u64>,
+    #[serde(default)]
+    pub auth: BasicAuthConfig,
+    #[serde(default)]
+    pub http: HttpSinkConfig,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExporterConfig {
+    pub listen: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub scrape_interval: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub ready_intervals: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub http_threads: Option<usize>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub scrape_wait_timeout: Option<u64>,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
 pub struct HorizonAPIConfig {
+    #[serde(default)]
     pub url: String,
+    pub urls: Option<Vec<String>>,
+    pub failover: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub failover_holdoff: Option<u64>,
     pub user: String,
     pub domain: String,
-    pub password: String,
+    pub password: Option<String>,
+    pub password_file: Option<String>,
+    pub password_env: Option<String>,
+    pub password_command: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
     pub insecure_ssl: Option<bool>,
     pub ca_file: Option<String>,
+    pub ca_dir: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub client_pkcs12: Option<String>,
+    pub client_pkcs12_password: Option<String>,
+    pub min_tls_version: Option<String>,
+    pub pinned_certificates: Option<Vec<String>>,
+    pub tls_server_name: Option<String>,
+    pub proxy: Option<ProxyConfig>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
     pub timeout: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub pool_idle_timeout: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub retries: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub min_backoff: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub max_backoff: Option<u64>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub max_login_failures: Option<u32>,
+    #[serde(default, deserialize_with = "deserialize_from_str")]
+    pub login_suspend: Option<u64>,
     pub only_pools: Option<Vec<String>>,
     pub skip_pools: Option<Vec<String>>,
     pub pool_uuid_map: Option<HashMap<String, String>>,
     #[serde(skip)]
+    pub url_list: Vec<String>,
+    #[serde(skip)]
+    pub pinned_fingerprints: Vec<String>,
+    #[serde(skip)]
     pub only_pools_set: HashSet<String>,
     #[serde(skip)]
     pub skip_pools_set: HashSet<String>,
 }
 
+// Secrets are replaced in debug output, because the configuration is logged
+fn redacted(v: &Option<String>) -> Option<&'static str> {
+    v.as_ref().map(|_| constants::REDACTED)
+}
+
+impl fmt::Debug for BasicAuthConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BasicAuthConfig")
+            .field("user", &self.user)
+            .field("password", &redacted(&self.password))
+            .field("password_file", &self.password_file)
+            .finish()
+    }
+}
+
+impl fmt::Debug for InfluxDBConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InfluxDBConfig")
+            .field("url", &self.url)
+            .field("api_version", &self.api_version)
+            .field("database", &self.database)
+            .field("retention_policy", &self.retention_policy)
+            .field("org", &self.org)
+            .field("bucket", &self.bucket)
+            .field("token", &redacted(&self.token))
+            .field("token_file", &self.token_file)
+            .field("tags", &self.tags)
+            .field("interval", &self.interval)
+            .field("auth", &self.auth)
+            .field("http", &self.http)
+            .finish()
+    }
+}
+
+impl fmt::Debug for HorizonAPIConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HorizonAPIConfig")
+            .field("url", &self.url)
+            .field("urls", &self.urls)
+            .field("failover", &self.failover)
+            .field("failover_holdoff", &self.failover_holdoff)
+            .field("user", &self.user)
+            .field("domain", &self.domain)
+            .field("password", &redacted(&self.password))
+            .field("password_file", &self.password_file)
+            .field("password_env", &self.password_env)
+            .field("password_command", &self.password_command)
+            .field("insecure_ssl", &self.insecure_ssl)
+            .field("ca_file", &self.ca_file)
+            .field("ca_dir", &self.ca_dir)
+            .field("client_cert", &self.client_cert)
+            .field("client_key", &self.client_key)
+            .field("client_pkcs12", &self.client_pkcs12)
+            .field(
+                "client_pkcs12_password",
+                &redacted(&self.client_pkcs12_password),
+            )
+            .field("min_tls_version", &self.min_tls_version)
+            .field("pinned_certificates", &self.pinned_certificates)
+            .field("tls_server_name", &self.tls_server_name)
+            .field("proxy", &self.proxy)
+            .field("timeout", &self.timeout)
+            .field("pool_idle_timeout", &self.pool_idle_timeout)
+            .field("retries", &self.retries)
+            .field("min_backoff", &self.min_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("max_login_failures", &self.max_login_failures)
+            .field("login_suspend", &self.login_suspend)
+            .field("only_pools", &self.only_pools)
+            .field("skip_pools", &self.skip_pools)
+            .field("pool_uuid_map", &self.pool_uuid_map)
+            .field("url_list", &self.url_list)
+            .field("pinned_fingerprints", &self.pinned_fingerprints)
+            .field("only_pools_set", &self.only_pools_set)
+            .field("skip_pools_set", &self.skip_pools_set)
+            .finish()
+    }
+}
+
 impl HorizonAPIConfig {
-    pub fn user_defined_pool_uuid_resolve(self, uuid: &str) -> String {
-        match self.pool_uuid_map {
+    /// URLs of all configured connection servers, comma separated
+    pub fn url_description(&self) -> String {
+        self.url_list.join(",")
+    }
+
+    pub fn user_defined_pool_uuid_resolve(&self, uuid: &str) -> String {
+        match &self.pool_uuid_map {
             // Some(m) => uuid.to_string(),
             Some(m) => match m.get(uuid) {
                 Some(v) => v.to_string(),
@@ -38,11 +333,166 @@ impl HorizonAPIConfig {
             None => uuid.to_string(),
         }
     }
+
+    pub fn exports_pool(&self, pool_id: &str) -> bool {
+        !self.skip_pools_set.contains(pool_id)
+            && (self.only_pools_set.is_empty() || self.only_pools_set.contains(pool_id))
+    }
+
+    // The password is read every time it is requested so rotated secrets are used on the next login
+    pub fn get_password(&self) -> Result<String, Box<dyn Error>> {
+        let (source, password) = self.read_password()?;
+        if password.is_empty() {
+            bail!("empty password from {}", source);
+        }
+        Ok(password)
+    }
+
+    fn read_password(&self) -> Result<(String, String), Box<dyn Error>> {
+        if let Some(v) = &self.password {
+            return Ok(("password".to_string(), v.clone()));
+        }
+
+        if let Some(v) = &self.password_file {
+            return Ok((
+                format!("password file {}", v),
+                read_secret_file("password_file", v)?,
+            ));
+        }
+
+        if let Some(v) = &self.password_env {
+            return match env::var(v) {
+                Ok(p) => Ok((
+                    format!("environment variable {}", v),
+                    p.trim_end_matches(['\r', '\n']).to_string(),
+                )),
+                Err(e) => bail!("can't read password from environment variable {}: {}", v, e),
+            };
+        }
+
+        if let Some(v) = &self.password_command {
+            let output = match Command::new("/bin/sh").arg("-c").arg(v).output() {
+                Ok(v) => v,
+                Err(e) => bail!("can't run password command: {}", e),
+            };
+            if !output.status.success() {
+                bail!(
+                    "password command failed with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            let raw = String::from_utf8(output.stdout)?;
+            return Ok((
+                "password command".to_string(),
+                raw.trim_end_matches(['\r', '\n']).to_string(),
+            ));
+        }
+
+        bail!("no password source configured");
+    }
+}
+
+impl BasicAuthConfig {
+    pub fn is_set(&self) -> bool {
+        self.password.is_some() || self.password_file.is_some()
+    }
+
+    // The password file is read on every request, so rotated secrets are used
+    pub fn get_password(&self) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(v) = &self.password {
+            return Ok(Some(v.clone()));
+        }
+
+        if let Some(v) = &self.password_file {
+            return Ok(Some(read_secret_file("password_file", v)?));
+        }
+
+        Ok(None)
+    }
+}
+
+impl InfluxDBConfig {
+    pub fn get_token(&self) -> Result<Option<String>, Box<dyn Error>> {
+        if let Some(v) = &self.token {
+            return Ok(Some(v.clone()));
+        }
+
+        if let Some(v) = &self.token_file {
+            return Ok(Some(read_secret_file("token_file", v)?));
+        }
+
+        Ok(None)
+    }
+}
+
+/// Content of a password or token file, `key` is the configuration key used in error messages
+pub fn read_secret_file(key: &str, f: &str) -> Result<String, Box<dyn Error>> {
+    let path = credential_path(f);
+    let raw = match fs::read_to_string(&path) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read {} {}: {}", key, path.display(), e),
+    };
+    Ok(raw.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Relative paths are looked up in the credential directory of systemd's LoadCredential if set
+fn credential_path(f: &str) -> PathBuf {
+    let path = Path::new(f);
+    if path.is_relative() {
+        if let Ok(d) = env::var("CREDENTIALS_DIRECTORY") {
+            return Path::new(&d).join(path);
+        }
+    }
+    path.to_path_buf()
 }
 
-pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
-    let raw = fs::read_to_string(f)?;
-    let mut config: Configuration = serde_yaml::from_str(raw.as_str())?;
+// Precedence of configuration values: defaults < configuration file < environment < command line
+pub fn parse_config_file(f: Option<&str>) -> Result<Configuration, Box<dyn Error>> {
+    let (mut raw_config, mut sources) = read_raw_config(f)?;
+
+    let mut config: Configuration = loop {
+        let e = match serde_path_to_error::deserialize(raw_config.clone()) {
+            Ok(v) => break v,
+            Err(e) => e,
+        };
+        let key = e.path().to_string();
+
+        // variables with the prefix of the exporter could be meant for other programs
+        if let Some(var) = unknown_environment_key(&e, &sources, &key) {
+            warn!(
+                "ignoring environment variable {}, {} is not a configuration key",
+                var, key
+            );
+            let path: Vec<&str> = key.split('.').collect();
+            if !remove_key(&mut raw_config, &path) {
+                bail!("can't ignore environment variable {}", var);
+            }
+            sources.retain(|k, _| k != &key && !k.starts_with(&format!("{}.", key)));
+            continue;
+        }
+
+        if key == "." {
+            bail!("{}", e.inner());
+        }
+        bail!("{}: {}", describe_source(&sources, &key), e.inner());
+    };
+    config.sources = sources;
+
+    config.horizon_api.url_list = match &config.horizon_api.urls {
+        Some(v) => v.clone(),
+        None if !config.horizon_api.url.is_empty() => vec![config.horizon_api.url.clone()],
+        None => Vec::new(),
+    };
+
+    // invalid fingerprints are reported by validate_config
+    config.horizon_api.pinned_fingerprints = config
+        .horizon_api
+        .pinned_certificates
+        .iter()
+        .flatten()
+        .filter_map(|p| tls::parse_fingerprint(p).ok())
+        .collect();
 
     config.horizon_api.only_pools_set = HashSet::new();
     if let Some(v) = &config.horizon_api.only_pools {
@@ -63,27 +513,878 @@ pub fn parse_config_file(f: &str) -> Result<Configuration, Box<dyn Error>> {
     Ok(config)
 }
 
+fn read_raw_config(
+    f: Option<&str>,
+) -> Result<(serde_yaml::Value, HashMap<String, String>), Box<dyn Error>> {
+    let mut sources = HashMap::new();
+
+    let mut raw_config = match f {
+        Some(f) => {
+            let raw = fs::read_to_string(f)?;
+            let value: serde_yaml::Value = serde_yaml::from_str(raw.as_str())?;
+            record_sources(
+                &value,
+                "",
+                &format!("configuration file {}", f),
+                &mut sources,
+            );
+            for (key, source) in sources.iter_mut() {
+                if let Some(line) = find_key_line(&raw, key) {
+                    source.push_str(&format!(", line {}", line));
+                }
+            }
+            value
+        }
+        None => serde_yaml::Value::Mapping(serde_yaml::Mapping::new()),
+    };
+
+    merge_environment(&mut raw_config, &mut sources)?;
+
+    Ok((raw_config, sources))
+}
+
+// Report all configuration keys that are not known, including the location where they were set
+pub fn find_unknown_keys(f: Option<&str>) -> Result<Vec<String>, Box<dyn Error>> {
+    let (mut raw_config, sources) = read_raw_config(f)?;
+    let mut result = Vec::new();
+
+    // deserialization stops at the first unknown key, so remove it and try again
+    while let Err(e) = serde_path_to_error::deserialize::<_, Configuration>(raw_config.clone()) {
+        if !is_unknown_key(&e) {
+            break;
+        }
+        let key = e.path().to_string();
+        result.push(describe_source(&sources, &key));
+
+        let path: Vec<&str> = key.split('.').collect();
+        if !remove_key(&mut raw_config, &path) {
+            break;
+        }
+    }
+
+    Ok(result)
+}
+
+// Unknown keys are reported by serde::de::Error::unknown_field, which serde_yaml doesn't
+// distinguish from other errors
+fn is_unknown_key(e: &serde_path_to_error::Error<serde_yaml::Error>) -> bool {
+    e.inner().to_string().starts_with("unknown field")
+}
+
+// Names of the environment variables if an unknown key was only set by the environment
+fn unknown_environment_key(
+    e: &serde_path_to_error::Error<serde_yaml::Error>,
+    sources: &HashMap<String, String>,
+    key: &str,
+) -> Option<String> {
+    if !is_unknown_key(e) {
+        return None;
+    }
+
+    let prefix = format!("{}.", key);
+    let vars: Option<Vec<&str>> = sources
+        .iter()
+        .filter(|(k, _)| k.as_str() == key || k.starts_with(&prefix))
+        .map(|(_, v)| v.strip_prefix("environment variable "))
+        .collect();
+    let mut vars = vars?;
+    if vars.is_empty() {
+        return None;
+    }
+    vars.sort();
+    vars.dedup();
+    Some(vars.join(", "))
+}
+
+fn remove_key(value: &mut serde_yaml::Value, path: &[&str]) -> bool {
+    match path {
+        [] => false,
+        [last] => match value.as_mapping_mut() {
+            Some(m) => m.remove(*last).is_some(),
+            None => false,
+        },
+        [first, rest @ ..] => match value.get_mut(*first) {
+            Some(v) => remove_key(v, rest),
+            None => false,
+        },
+    }
+}
+
+// Line number of the value of a dotted key in the YAML configuration file, used for error
+// messages. serde_yaml only reports locations with errors, so the file is parsed again and
+// the value of the key is rejected. The file was already parsed successfully, so every error
+// is the rejection of the value.
+fn find_key_line(raw: &str, key: &str) -> Option<usize> {
+    let path: Vec<&str> = key.split('.').collect();
+    match KeySeeker(&path).deserialize(serde_yaml::Deserializer::from_str(raw)) {
+        Ok(_) => None,
+        Err(e) => e.location().map(|l| l.line()),
+    }
+}
+
+struct KeySeeker<'a>(&'a [&'a str]);
+
+impl<'de, 'a> de::DeserializeSeed<'de> for KeySeeker<'a> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        if self.0.is_empty() {
+            return deserializer.deserialize_any(Reject);
+        }
+        deserializer.deserialize_map(self)
+    }
+}
+
+// The default methods of a visitor reject every value, the error has the location of the value
+struct Reject;
+
+impl<'de> de::Visitor<'de> for Reject {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("no value")
+    }
+}
+
+impl<'de, 'a> de::Visitor<'de> for KeySeeker<'a> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a dictionary")
+    }
+
+    fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(k) = map.next_key::<serde_yaml::Value>()? {
+            if k.as_str() == Some(self.0[0]) {
+                return map.next_value_seed(KeySeeker(&self.0[1..]));
+            }
+            map.next_value::<de::IgnoredAny>()?;
+        }
+        Ok(())
+    }
+}
+
+fn record_sources(
+    value: &serde_yaml::Value,
+    prefix: &str,
+    source: &str,
+    sources: &mut HashMap<String, String>,
+) {
+    match value {
+        serde_yaml::Value::Mapping(m) => {
+            for (k, v) in m.iter() {
+                if let Some(k) = k.as_str() {
+                    let key = if prefix.is_empty() {
+                        k.to_string()
+                    } else {
+                        format!("{}.{}", prefix, k)
+                    };
+                    record_sources(v, &key, source, sources);
+                }
+            }
+        }
+        _ => {
+            sources.insert(prefix.to_string(), source.to_string());
+        }
+    };
+}
+
+// HORIZON_EXPORTER_HORIZON_API__URL sets the key url in the horizon_api dictionary
+fn merge_environment(
+    config: &mut serde_yaml::Value,
+    sources: &mut HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut env_vars: Vec<(String, String)> = env::vars()
+        .filter(|(k, _)| k.starts_with(constants::ENV_PREFIX))
+        .collect();
+    env_vars.sort();
+
+    for (name, raw) in env_vars {
+        let path: Vec<String> = name[constants::ENV_PREFIX.len()..]
+            .split("__")
+            .map(|p| p.to_lowercase())
+            .collect();
+        if path.iter().any(|p| p.is_empty()) {
+            bail!("invalid configuration key in environment variable {}", name);
+        }
+
+        let value = parse_environment_value(&raw);
+
+        let mut node = &mut *config;
+        for (i, key) in path.iter().enumerate() {
+            let map = match node.as_mapping_mut() {
+                Some(v) => v,
+                None => bail!(
+                    "environment variable {} sets a key below {} which is not a dictionary",
+                    name,
+                    path[..i].join(".")
+                ),
+            };
+            if i == path.len() - 1 {
+                map.insert(serde_yaml::Value::String(key.clone()), value.clone());
+                break;
+            }
+            node = map
+                .entry(serde_yaml::Value::String(key.clone()))
+                .or_insert_with(|| serde_yaml::Value::Mapping(serde_yaml::Mapping::new()));
+        }
+
+        let key = path.join(".");
+        // a list or dictionary from the environment replaces the whole value from the file
+        sources.retain(|k, _| !k.starts_with(&format!("{}.", key)));
+        record_sources(
+            &value,
+            &key,
+            &format!("environment variable {}", name),
+            sources,
+        );
+    }
+
+    Ok(())
+}
+
+fn parse_environment_value(raw: &str) -> serde_yaml::Value {
+    // Lists and dictionaries are parsed as YAML, scalars are always passed as string and
+    // converted by the deserializer of the configuration key
+    if raw.trim_start().starts_with(['[', '{']) {
+        if let Ok(v) = serde_yaml::from_str::<serde_yaml::Value>(raw) {
+            if v.is_sequence() || v.is_mapping() {
+                return v;
+            }
+        }
+    }
+    serde_yaml::Value::String(raw.to_string())
+}
+
+// Numeric and boolean options can be set as string, e.g. by environment variables
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr + Deserialize<'de>,
+    T::Err: fmt::Display,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum NativeOrString<T> {
+        Native(T),
+        String(String),
+    }
+
+    match Option::<NativeOrString<T>>::deserialize(deserializer)? {
+        Some(NativeOrString::Native(v)) => Ok(Some(v)),
+        Some(NativeOrString::String(v)) => match v.trim().parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => Err(de::Error::custom(format!("invalid value {}: {}", v, e))),
+        },
+        None => Ok(None),
+    }
+}
+
+// Describe where the value of a configuration key came from, used in error messages
+fn describe_source(sources: &HashMap<String, String>, key: &str) -> String {
+    if let Some(v) = sources.get(key) {
+        return format!("{} (set by {})", key, v);
+    }
+
+    // dictionaries are only recorded by their keys
+    let prefix = format!("{}.", key);
+    let mut children: Vec<(&String, &String)> = sources
+        .iter()
+        .filter(|(k, _)| k.starts_with(&prefix))
+        .collect();
+    children.sort();
+    match children.first() {
+        Some((_, v)) => format!("{} (set by {})", key, v),
+        None => key.to_string(),
+    }
+}
+
+impl Configuration {
+    pub fn source_of(&self, key: &str) -> String {
+        describe_source(&self.sources, key)
+    }
+}
+
+pub fn current(shared: &SharedConfiguration) -> Arc<Configuration> {
+    shared.read().unwrap().clone()
+}
+
+pub fn reload(shared: &SharedConfiguration, f: Option<&str>) -> Result<(), Box<dyn Error>> {
+    // the old configuration is kept if the new one can't be parsed or is invalid
+    let config = parse_config_file(f)?;
+    *shared.write().unwrap() = Arc::new(config);
+    Ok(())
+}
+
 fn validate_config(cfg: &Configuration) -> Result<(), Box<dyn Error>> {
-    if cfg.horizon_api.url.is_empty() {
-        bail!("Missing URL");
+    if !cfg.horizon_api.url.is_empty() && cfg.horizon_api.urls.is_some() {
+        bail!(
+            "Only one of url or urls can be set: {}, {}",
+            cfg.source_of("horizon_api.url"),
+            cfg.source_of("horizon_api.urls")
+        );
+    }
+
+    if cfg.horizon_api.url_list.is_empty() {
+        bail!(
+            "Missing URL, one of url or urls is required: {}",
+            cfg.source_of("horizon_api.url")
+        );
+    }
+
+    let url_key = if cfg.horizon_api.urls.is_some() {
+        "horizon_api.urls"
+    } else {
+        "horizon_api.url"
+    };
+    for url in cfg.horizon_api.url_list.iter() {
+        validate_url(cfg, url_key, url)?;
+    }
+
+    if let Some(v) = &cfg.horizon_api.failover {
+        if v != constants::FAILOVER_ORDERED && v != constants::FAILOVER_ROUND_ROBIN {
+            bail!(
+                "Invalid failover {}, only {} and {} are supported: {}",
+                v,
+                constants::FAILOVER_ORDERED,
+                constants::FAILOVER_ROUND_ROBIN,
+                cfg.source_of("horizon_api.failover")
+            );
+        }
+    }
+
+    validate_certificates(
+        cfg,
+        "horizon_api",
+        &tls::Certificates::from(&cfg.horizon_api),
+    )?;
+    validate_horizon_tls(cfg)?;
+
+    if let Some(proxy) = &cfg.horizon_api.proxy {
+        validate_proxy_config(cfg, proxy)?;
     }
 
     if cfg.horizon_api.user.is_empty() {
-        bail!("Missing user for authentication");
+        bail!(
+            "Missing user for authentication: {}",
+            cfg.source_of("horizon_api.user")
+        );
+    }
+
+    let password_sources: Vec<(&str, &String)> = [
+        ("horizon_api.password", &cfg.horizon_api.password),
+        ("horizon_api.password_file", &cfg.horizon_api.password_file),
+        ("horizon_api.password_env", &cfg.horizon_api.password_env),
+        (
+            "horizon_api.password_command",
+            &cfg.horizon_api.password_command,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(k, v)| v.as_ref().map(|v| (k, v)))
+    .collect();
+
+    if password_sources.is_empty() {
+        bail!("Missing password for authentication, one of password, password_file, password_env or password_command is required");
     }
 
-    if cfg.horizon_api.password.is_empty() {
-        bail!("Missing password for authentication");
+    if password_sources.len() > 1 {
+        let used: Vec<String> = password_sources
+            .iter()
+            .map(|(k, _)| cfg.source_of(k))
+            .collect();
+        bail!(
+            "Only one of password, password_file, password_env or password_command can be used, found {}",
+            used.join(", ")
+        );
+    }
+
+    for (k, v) in password_sources {
+        if v.is_empty() {
+            bail!("Missing password for authentication: {}", cfg.source_of(k));
+        }
     }
 
     if cfg.horizon_api.domain.is_empty() {
-        bail!("Missing login domain for authentication");
+        bail!(
+            "Missing login domain for authentication: {}",
+            cfg.source_of("horizon_api.domain")
+        );
+    }
+
+    let min_backoff = cfg
+        .horizon_api
+        .min_backoff
+        .unwrap_or(constants::DEFAULT_HORIZON_MIN_BACKOFF);
+    let max_backoff = cfg
+        .horizon_api
+        .max_backoff
+        .unwrap_or(constants::DEFAULT_HORIZON_MAX_BACKOFF);
+    if min_backoff > max_backoff {
+        bail!(
+            "min_backoff ({}) is greater than max_backoff ({}): {}, {}",
+            min_backoff,
+            max_backoff,
+            cfg.source_of("horizon_api.min_backoff"),
+            cfg.source_of("horizon_api.max_backoff")
+        );
+    }
+
+    if cfg.horizon_api.login_suspend == Some(0) {
+        bail!(
+            "login_suspend must be greater than 0: {}",
+            cfg.source_of("horizon_api.login_suspend")
+        );
+    }
+
+    if cfg.exporter.scrape_interval == Some(0) {
+        bail!(
+            "scrape_interval must be greater than 0: {}",
+            cfg.source_of("exporter.scrape_interval")
+        );
+    }
+
+    if cfg.exporter.ready_intervals == Some(0) {
+        bail!(
+            "ready_intervals must be greater than 0: {}",
+            cfg.source_of("exporter.ready_intervals")
+        );
+    }
+
+    if cfg.exporter.http_threads == Some(0) {
+        bail!(
+            "http_threads must be greater than 0: {}",
+            cfg.source_of("exporter.http_threads")
+        );
+    }
+
+    if cfg.exporter.scrape_wait_timeout == Some(0) {
+        bail!(
+            "scrape_wait_timeout must be greater than 0: {}",
+            cfg.source_of("exporter.scrape_wait_timeout")
+        );
+    }
+
+    if let Some(pgw) = &cfg.pushgateway {
+        validate_pushgateway_config(cfg, pgw)?;
+    }
+
+    if let Some(rw) = &cfg.remote_write {
+        validate_remote_write_config(cfg, rw)?;
+    }
+
+    if let Some(influx) = &cfg.influxdb {
+        validate_influxdb_config(cfg, influx)?;
+    }
+
+    if let Some(graphite) = &cfg.graphite {
+        validate_graphite_config(cfg, graphite)?;
+    }
+
+    if let Some(otlp) = &cfg.otlp {
+        validate_otlp_config(cfg, otlp)?;
     }
 
     for op in cfg.horizon_api.only_pools_set.iter() {
         if cfg.horizon_api.skip_pools_set.contains(op) {
-            bail!("pool {} is in only_pools and skip_pools", op);
+            bail!(
+                "pool {} is in {} and {}",
+                op,
+                cfg.source_of("horizon_api.only_pools"),
+                cfg.source_of("horizon_api.skip_pools")
+            );
+        }
+    }
+    Ok(())
+}
+
+fn validate_horizon_tls(cfg: &Configuration) -> Result<(), Box<dyn Error>> {
+    let api = &cfg.horizon_api;
+
+    if let Some(v) = &api.min_tls_version {
+        if let Err(e) = tls::parse_tls_version(v) {
+            bail!("{}: {}", cfg.source_of("horizon_api.min_tls_version"), e);
+        }
+    }
+
+    for p in api.pinned_certificates.iter().flatten() {
+        if let Err(e) = tls::parse_fingerprint(p) {
+            bail!(
+                "{}: {}",
+                cfg.source_of("horizon_api.pinned_certificates"),
+                e
+            );
+        }
+    }
+
+    if api.tls_server_name.as_deref() == Some("") {
+        bail!(
+            "tls_server_name must not be empty: {}",
+            cfg.source_of("horizon_api.tls_server_name")
+        );
+    }
+
+    Ok(())
+}
+
+fn validate_proxy_config(cfg: &Configuration, proxy: &ProxyConfig) -> Result<(), Box<dyn Error>> {
+    if proxy.disable.unwrap_or_default() {
+        if proxy.url.is_some() {
+            bail!(
+                "Only one of url or disable can be set: {}, {}",
+                cfg.source_of("horizon_api.proxy.url"),
+                cfg.source_of("horizon_api.proxy.disable")
+            );
+        }
+        return Ok(());
+    }
+
+    let url = match &proxy.url {
+        Some(v) if !v.is_empty() => v,
+        _ => bail!(
+            "Missing proxy URL, set url or disable: {}",
+            cfg.source_of("horizon_api.proxy.url")
+        ),
+    };
+    match reqwest::Url::parse(url) {
+        Ok(v) => {
+            if !["http", "https", "socks5", "socks5h"].contains(&v.scheme()) {
+                bail!(
+                    "Invalid proxy URL scheme {}, only http, https, socks5 and socks5h are supported: {}",
+                    v.scheme(),
+                    cfg.source_of("horizon_api.proxy.url")
+                );
+            }
+        }
+        Err(e) => bail!(
+            "Invalid proxy URL: {}: {}",
+            cfg.source_of("horizon_api.proxy.url"),
+            e
+        ),
+    };
+
+    validate_basic_auth(cfg, "horizon_api.proxy.auth", &proxy.auth)
+}
+
+// section is the dotted path of the dictionary containing the settings, e.g. pushgateway.auth
+fn validate_basic_auth(
+    cfg: &Configuration,
+    section: &str,
+    auth: &BasicAuthConfig,
+) -> Result<(), Box<dyn Error>> {
+    if auth.password.is_some() && auth.password_file.is_some() {
+        bail!(
+            "Only one of password or password_file can be used, found {}, {}",
+            cfg.source_of(&format!("{}.password", section)),
+            cfg.source_of(&format!("{}.password_file", section))
+        );
+    }
+
+    if auth.is_set() && auth.user.is_none() {
+        bail!(
+            "Missing user for basic authentication: {}",
+            cfg.source_of(&format!("{}.user", section))
+        );
+    }
+
+    Ok(())
+}
+
+fn validate_http_sink(
+    cfg: &Configuration,
+    section: &str,
+    http: &HttpSinkConfig,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(headers) = &http.headers {
+        validate_headers(cfg, &format!("{}.headers", section), headers)?;
+    }
+
+    if http.timeout == Some(0) {
+        bail!(
+            "timeout must be greater than 0: {}",
+            cfg.source_of(&format!("{}.timeout", section))
+        );
+    }
+
+    validate_certificates(cfg, section, &tls::Certificates::from(http))
+}
+
+// CA and client certificates of the Horizon REST API or the http dictionary of an output
+fn validate_certificates(
+    cfg: &Configuration,
+    section: &str,
+    certs: &tls::Certificates,
+) -> Result<(), Box<dyn Error>> {
+    let key = |k: &str| cfg.source_of(&format!("{}.{}", section, k));
+
+    if let Some(ca_file) = certs.ca_file {
+        if let Err(e) = tls::read_ca_bundle(ca_file) {
+            bail!("{}: {}", key("ca_file"), e);
         }
     }
+
+    if let Some(ca_dir) = certs.ca_dir {
+        if let Err(e) = tls::read_ca_dir(ca_dir) {
+            bail!("{}: {}", key("ca_dir"), e);
+        }
+    }
+
+    if certs.client_cert.is_some() != certs.client_key.is_some() {
+        bail!(
+            "client_cert and client_key must be set together: {}, {}",
+            key("client_cert"),
+            key("client_key")
+        );
+    }
+
+    if certs.client_pkcs12.is_some() && certs.client_cert.is_some() {
+        bail!(
+            "Only one of client_cert or client_pkcs12 can be set: {}, {}",
+            key("client_cert"),
+            key("client_pkcs12")
+        );
+    }
+
+    if certs.client_pkcs12_password.is_some() && certs.client_pkcs12.is_none() {
+        bail!(
+            "client_pkcs12_password requires client_pkcs12: {}",
+            key("client_pkcs12_password")
+        );
+    }
+
+    if let Err(e) = tls::read_identity(certs) {
+        let k = if certs.client_pkcs12.is_some() {
+            "client_pkcs12"
+        } else {
+            "client_cert"
+        };
+        bail!("{}: {}", key(k), e);
+    }
+
     Ok(())
 }
+
+fn validate_url(cfg: &Configuration, key: &str, url: &str) -> Result<(), Box<dyn Error>> {
+    match reqwest::Url::parse(url) {
+        Ok(v) => {
+            if v.scheme() != "https" && v.scheme() != "http" {
+                bail!(
+                    "Invalid URL scheme {}, only https and http are supported: {}",
+                    v.scheme(),
+                    cfg.source_of(key)
+                );
+            }
+        }
+        Err(e) => bail!("Invalid URL: {}: {}", cfg.source_of(key), e),
+    };
+    Ok(())
+}
+
+fn validate_headers(
+    cfg: &Configuration,
+    key: &str,
+    headers: &HashMap<String, String>,
+) -> Result<(), Box<dyn Error>> {
+    for (name, value) in headers.iter() {
+        if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err()
+            || reqwest::header::HeaderValue::from_str(value).is_err()
+        {
+            bail!("Invalid HTTP header {}: {}", name, cfg.source_of(key));
+        }
+    }
+    Ok(())
+}
+
+fn validate_pushgateway_config(
+    cfg: &Configuration,
+    pgw: &PushgatewayConfig,
+) -> Result<(), Box<dyn Error>> {
+    if pgw.url.is_empty() {
+        bail!("Missing URL: {}", cfg.source_of("pushgateway.url"));
+    }
+    validate_url(cfg, "pushgateway.url", &pgw.url)?;
+
+    if let Some(job) = &pgw.job {
+        if job.is_empty() {
+            bail!("Empty job name: {}", cfg.source_of("pushgateway.job"));
+        }
+    }
+
+    if pgw.interval == Some(0) {
+        bail!(
+            "interval must be greater than 0: {}",
+            cfg.source_of("pushgateway.interval")
+        );
+    }
+
+    validate_basic_auth(cfg, "pushgateway.auth", &pgw.auth)?;
+    validate_http_sink(cfg, "pushgateway.http", &pgw.http)
+}
+
+fn validate_remote_write_config(
+    cfg: &Configuration,
+    rw: &RemoteWriteConfig,
+) -> Result<(), Box<dyn Error>> {
+    if rw.url.is_empty() {
+        bail!("Missing URL: {}", cfg.source_of("remote_write.url"));
+    }
+    validate_url(cfg, "remote_write.url", &rw.url)?;
+
+    if rw.interval == Some(0) {
+        bail!(
+            "interval must be greater than 0: {}",
+            cfg.source_of("remote_write.interval")
+        );
+    }
+
+    if rw.queue_size == Some(0) {
+        bail!(
+            "queue_size must be greater than 0: {}",
+            cfg.source_of("remote_write.queue_size")
+        );
+    }
+
+    if rw.min_backoff == Some(0) {
+        bail!(
+            "min_backoff must be greater than 0: {}",
+            cfg.source_of("remote_write.min_backoff")
+        );
+    }
+
+    let min_backoff = rw
+        .min_backoff
+        .unwrap_or(constants::DEFAULT_REMOTE_WRITE_MIN_BACKOFF);
+    let max_backoff = rw
+        .max_backoff
+        .unwrap_or(constants::DEFAULT_REMOTE_WRITE_MAX_BACKOFF);
+    if min_backoff > max_backoff {
+        bail!(
+            "min_backoff ({}) is greater than max_backoff ({})",
+            min_backoff,
+            max_backoff
+        );
+    }
+
+    validate_basic_auth(cfg, "remote_write.auth", &rw.auth)?;
+    validate_http_sink(cfg, "remote_write.http", &rw.http)
+}
+
+fn validate_influxdb_config(
+    cfg: &Configuration,
+    influx: &InfluxDBConfig,
+) -> Result<(), Box<dyn Error>> {
+    if influx.url.is_empty() {
+        bail!("Missing URL: {}", cfg.source_of("influxdb.url"));
+    }
+    validate_url(cfg, "influxdb.url", &influx.url)?;
+
+    if influx.interval == Some(0) {
+        bail!(
+            "interval must be greater than 0: {}",
+            cfg.source_of("influxdb.interval")
+        );
+    }
+
+    if influx.token.is_some() && influx.token_file.is_some() {
+        bail!(
+            "Only one of token or token_file can be used, found {}, {}",
+            cfg.source_of("influxdb.token"),
+            cfg.source_of("influxdb.token_file")
+        );
+    }
+
+    match influx
+        .api_version
+        .unwrap_or(constants::DEFAULT_INFLUXDB_API_VERSION)
+    {
+        1 => {
+            if influx.database.as_deref().unwrap_or_default().is_empty() {
+                bail!("Missing database for InfluxDB API version 1");
+            }
+        }
+        2 => {
+            if influx.org.as_deref().unwrap_or_default().is_empty() {
+                bail!("Missing org for InfluxDB API version 2");
+            }
+            if influx.bucket.as_deref().unwrap_or_default().is_empty() {
+                bail!("Missing bucket for InfluxDB API version 2");
+            }
+            if influx.token.is_none() && influx.token_file.is_none() {
+                bail!("Missing token or token_file for InfluxDB API version 2");
+            }
+        }
+        v => bail!(
+            "Invalid InfluxDB API version {}, only 1 and 2 are supported: {}",
+            v,
+            cfg.source_of("influxdb.api_version")
+        ),
+    };
+
+    validate_basic_auth(cfg, "influxdb.auth", &influx.auth)?;
+    validate_http_sink(cfg, "influxdb.http", &influx.http)
+}
+
+fn validate_graphite_config(
+    cfg: &Configuration,
+    graphite: &GraphiteConfig,
+) -> Result<(), Box<dyn Error>> {
+    match graphite.address.rsplit_once(':') {
+        Some((host, port)) => {
+            if host.is_empty() || port.parse::<u16>().is_err() {
+                bail!(
+                    "Invalid address {}, expected <host>:<port>: {}",
+                    graphite.address,
+                    cfg.source_of("graphite.address")
+                );
+            }
+        }
+        None => bail!(
+            "Invalid address {}, expected <host>:<port>: {}",
+            graphite.address,
+            cfg.source_of("graphite.address")
+        ),
+    };
+
+    if let Some(prefix) = &graphite.prefix {
+        if prefix.contains(char::is_whitespace) || prefix.starts_with('.') || prefix.ends_with('.')
+        {
+            bail!(
+                "Invalid prefix {}: {}",
+                prefix,
+                cfg.source_of("graphite.prefix")
+            );
+        }
+    }
+
+    if graphite.interval == Some(0) {
+        bail!(
+            "interval must be greater than 0: {}",
+            cfg.source_of("graphite.interval")
+        );
+    }
+
+    Ok(())
+}
+
+fn validate_otlp_config(cfg: &Configuration, otlp: &OtlpConfig) -> Result<(), Box<dyn Error>> {
+    if otlp.url.is_empty() {
+        bail!("Missing URL: {}", cfg.source_of("otlp.url"));
+    }
+    validate_url(cfg, "otlp.url", &otlp.url)?;
+
+    if let Some(service_name) = &otlp.service_name {
+        if service_name.is_empty() {
+            bail!("Empty service name: {}", cfg.source_of("otlp.service_name"));
+        }
+    }
+
+    if otlp.interval == Some(0) {
+        bail!(
+            "interval must be greater than 0: {}",
+            cfg.source_of("otlp.interval")
+        );
+    }
+
+    validate_basic_auth(cfg, "otlp.auth", &otlp.auth)?;
+    validate_http_sink(cfg, "otlp.http", &otlp.http)
+}
@@ -6,160 +6,140 @@ pub fn generate_default_user_agent() -> String {
     format!("{}/{} ({})", NAME, VERSION, REPO_URL)
 }
 
-pub const ROOT_HTML: &str = "<html>\n<head><title>VMWare Horizon exporter</title></head>\n<body>\n<h1>VMWare Horizon exporter</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n</body>\n</html>\n";
+pub const ROOT_HTML: &str = "<html>\n<head><title>VMWare Horizon exporter</title></head>\n<body>\n<h1>VMWare Horizon exporter</h1>\n<p><a href=\"/metrics\">Metrics</a></p>\n<p><a href=\"/status\">Status</a></p>\n</body>\n</html>\n";
 pub const METRICS_PATH: &str = "/metrics";
+pub const HEALTH_PATH: &str = "/-/healthy";
+pub const READY_PATH: &str = "/-/ready";
+pub const STATUS_PATH: &str = "/status";
+pub const API_POOLS_PATH: &str = "/api/v1/pools";
+pub const API_MACHINES_PATH: &str = "/api/v1/machines";
+pub const API_SESSIONS_PATH: &str = "/api/v1/sessions";
+pub const RELOAD_PATH: &str = "/-/reload";
+
+pub const ENV_PREFIX: &str = "HORIZON_EXPORTER_";
 
 pub const DEFAULT_LISTEN_ADDR: &str = "localhost:9133";
 pub const DEFAULT_TIMEOUT: u64 = 60;
+pub const DEFAULT_HORIZON_RETRIES: u32 = 2;
+pub const DEFAULT_HORIZON_MIN_BACKOFF: u64 = 1;
+pub const DEFAULT_HORIZON_MAX_BACKOFF: u64 = 10;
+pub const DEFAULT_MAX_LOGIN_FAILURES: u32 = 3;
+pub const DEFAULT_LOGIN_SUSPEND: u64 = 300;
+pub const DEFAULT_FAILOVER_HOLDOFF: u64 = 60;
+pub const DEFAULT_POOL_IDLE_TIMEOUT: u64 = 90;
+pub const DEFAULT_MIN_TLS_VERSION: &str = "1.2";
+pub const FAILOVER_ORDERED: &str = "ordered";
+pub const FAILOVER_ROUND_ROBIN: &str = "round_robin";
+pub const DEFAULT_SCRAPE_INTERVAL: u64 = 60;
+pub const DEFAULT_READY_INTERVALS: u64 = 3;
+pub const DEFAULT_HTTP_THREADS: usize = 4;
+pub const DEFAULT_SCRAPE_WAIT_TIMEOUT: u64 = 30;
+pub const HTTP_RECV_TIMEOUT: u64 = 1;
+pub const SCHEDULER_TICK: u64 = 1;
+pub const DEFAULT_PUSHGATEWAY_JOB: &str = "vmware_horizon";
+pub const DEFAULT_REMOTE_WRITE_QUEUE_SIZE: usize = 10;
+pub const DEFAULT_REMOTE_WRITE_MIN_BACKOFF: u64 = 1;
+pub const DEFAULT_REMOTE_WRITE_MAX_BACKOFF: u64 = 30;
+pub const DEFAULT_INFLUXDB_API_VERSION: u8 = 1;
+pub const DEFAULT_GRAPHITE_PREFIX: &str = "horizon";
+pub const GRAPHITE_EMPTY_VALUE: &str = "none";
+pub const DEFAULT_OTLP_SERVICE_NAME: &str = "vmware_horizon-exporter";
 
-pub const REPLY_METHOD_NOT_ALLOWED: &str = "Method not allowed";
-pub const REPLY_NOT_FOUND: &str = "Not found";
-
-pub const SESSION_CONNECTED: &str = "CONNECTED";
-pub const SESSION_DISCONNECTED: &str = "DISCONNECTED";
-pub const SESSION_PENDING: &str = "PENDING";
-pub const LC_SESSION_CONNECTED: &str = "connected";
-pub const LC_SESSION_DISCONNECTED: &str = "disconnected";
-pub const LC_SESSION_PENDING: &str = "pending";
+pub const REPORT_COMMAND: &str = "report";
+pub const REPORT_FORMAT_CSV: &str = "csv";
+pub const REPORT_FORMAT_JSON: &str = "json";
 
-pub const PROTOCOL_PCOIP: &str = "PCOIP";
-pub const PROTOCOL_RDP: &str = "RDP";
-pub const PROTOCOL_BLAST: &str = "BLAST";
-pub const PROTOCOL_CONSOLE: &str = "CONSOLE";
-pub const PROTOCOL_UNKNOWN: &str = "UNKNOWN";
-pub const LC_PROTOCOL_PCOIP: &str = "pcoip";
-pub const LC_PROTOCOL_RDP: &str = "rdp";
-pub const LC_PROTOCOL_BLAST: &str = "blast";
-pub const LC_PROTOCOL_CONSOLE: &str = "console";
-pub const LC_PROTOCOL_UNKNOWN: &str = "unknown";
+pub const CRATE_NAME: &str = "vmware_horizon_exporter";
+pub const REDACTED: &str = "REDACTED";
+pub const LOG_FORMAT_TEXT: &str = "text";
+pub const LOG_FORMAT_JSON: &str = "json";
+pub const DEFAULT_LOG_MAX_SIZE: u64 = 10;
+pub const DEFAULT_LOG_MAX_FILES: usize = 5;
 
-pub const TYPE_APPLICATION: &str = "APPLICATION";
-pub const TYPE_DESKTOP: &str = "DESKTOP";
-pub const LC_TYPE_APPLICATION: &str = "application";
-pub const LC_TYPE_DESKTOP: &str = "desktop";
+pub const MIME_TEXT: &str = "text/plain";
+pub const MIME_HTML: &str = "text/html";
+pub const MIME_JSON: &str = "application/json";
+pub const MIME_PROMETHEUS_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+pub const MIME_OPENMETRICS: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
 
-pub const MSTATE_AGENT_CONFIG_ERROR: &str = "AGENT_CONFIG_ERROR";
-pub const MSTATE_AGENT_DRAIN_MODE: &str = "AGENT_DRAIN_MODE";
-pub const MSTATE_AGENT_DRAIN_UNTIL_RESTART: &str = "AGENT_DRAIN_UNTIL_RESTART";
-pub const MSTATE_AGENT_ERROR_DISABLED: &str = "AGENT_ERROR_DISABLED";
-pub const MSTATE_AGENT_ERROR_INVALID_IP: &str = "AGENT_ERROR_INVALID_IP";
-pub const MSTATE_AGENT_ERROR_NEEDS_REBOOT: &str = "AGENT_ERROR_NEEDS_REBOOT";
-pub const MSTATE_AGENT_ERROR_PROTOCOL_FAILURE: &str = "AGENT_ERROR_PROTOCOL_FAILURE";
-pub const MSTATE_AGENT_ERROR_STARTUP_IN_PROGRESS: &str = "AGENT_ERROR_STARTUP_IN_PROGRESS";
-pub const MSTATE_AGENT_ERROR_DOMAIN_FAILURE: &str = "AGENT_ERROR_DOMAIN_FAILURE";
-pub const MSTATE_AGENT_UNREACHABLE: &str = "AGENT_UNREACHABLE";
-pub const MSTATE_ALREADY_USED: &str = "ALREADY_USED";
-pub const MSTATE_AVAILABLE: &str = "AVAILABLE";
-pub const MSTATE_CONNECTED: &str = "CONNECTED";
-pub const MSTATE_CUSTOMIZING: &str = "CUSTOMIZING";
-pub const MSTATE_DELETING: &str = "DELETING";
-pub const MSTATE_DISABLED: &str = "DISABLED";
-pub const MSTATE_DISABLE_IN_PROGRESS: &str = "DISABLE_IN_PROGRESS";
-pub const MSTATE_DISCONNECTED: &str = "DISCONNECTED";
-pub const MSTATE_ERROR: &str = "ERROR";
-pub const MSTATE_IN_PROGRESS: &str = "IN_PROGRESS";
-pub const MSTATE_MAINTENANCE: &str = "MAINTENANCE";
-pub const MSTATE_PROVISIONED: &str = "PROVISIONED";
-pub const MSTATE_PROVISIONING: &str = "PROVISIONING";
-pub const MSTATE_PROVISIONING_ERROR: &str = "PROVISIONING_ERROR";
-pub const MSTATE_UNASSIGNED_USER_CONNECTED: &str = "UNASSIGNED_USER_CONNECTED";
-pub const MSTATE_UNASSIGNED_USER_DISCONNECTED: &str = "UNASSIGNED_USER_DISCONNECTED";
-pub const MSTATE_UNKNOWN: &str = "UNKNOWN";
-pub const MSTATE_VALIDATING: &str = "VALIDATING";
-pub const MSTATE_WAITING_FOR_AGENT: &str = "WAITING_FOR_AGENT";
-pub const LC_MSTATE_AGENT_CONFIG_ERROR: &str = "agent_config_error";
-pub const LC_MSTATE_AGENT_DRAIN_MODE: &str = "agent_drain_mode";
-pub const LC_MSTATE_AGENT_DRAIN_UNTIL_RESTART: &str = "agent_drain_until_restart";
-pub const LC_MSTATE_AGENT_ERROR_DISABLED: &str = "agent_error_disabled";
-pub const LC_MSTATE_AGENT_ERROR_INVALID_IP: &str = "agent_error_invalid_ip";
-pub const LC_MSTATE_AGENT_ERROR_NEEDS_REBOOT: &str = "agent_error_needs_reboot";
-pub const LC_MSTATE_AGENT_ERROR_PROTOCOL_FAILURE: &str = "agent_error_protocol_failure";
-pub const LC_MSTATE_AGENT_ERROR_STARTUP_IN_PROGRESS: &str = "agent_error_startup_in_progress";
-pub const LC_MSTATE_AGENT_ERROR_DOMAIN_FAILURE: &str = "agent_error_domain_failure";
-pub const LC_MSTATE_AGENT_UNREACHABLE: &str = "agent_unreachable";
-pub const LC_MSTATE_ALREADY_USED: &str = "already_used";
-pub const LC_MSTATE_AVAILABLE: &str = "available";
-pub const LC_MSTATE_CONNECTED: &str = "connected";
-pub const LC_MSTATE_CUSTOMIZING: &str = "customizing";
-pub const LC_MSTATE_DELETING: &str = "deleting";
-pub const LC_MSTATE_DISABLED: &str = "disabled";
-pub const LC_MSTATE_DISABLE_IN_PROGRESS: &str = "disable_in_progress";
-pub const LC_MSTATE_DISCONNECTED: &str = "disconnected";
-pub const LC_MSTATE_ERROR: &str = "error";
-pub const LC_MSTATE_IN_PROGRESS: &str = "in_progress";
-pub const LC_MSTATE_MAINTENANCE: &str = "maintenance";
-pub const LC_MSTATE_PROVISIONED: &str = "provisioned";
-pub const LC_MSTATE_PROVISIONING: &str = "provisioning";
-pub const LC_MSTATE_PROVISIONING_ERROR: &str = "provisioning_error";
-pub const LC_MSTATE_UNASSIGNED_USER_CONNECTED: &str = "unassigned_user_connected";
-pub const LC_MSTATE_UNASSIGNED_USER_DISCONNECTED: &str = "unassigned_user_disconnected";
-pub const LC_MSTATE_UNKNOWN: &str = "unknown";
-pub const LC_MSTATE_VALIDATING: &str = "validating";
-pub const LC_MSTATE_WAITING_FOR_AGENT: &str = "waiting_for_agent";
+pub const REPLY_METHOD_NOT_ALLOWED: &str = "Method not allowed";
+pub const REPLY_NOT_FOUND: &str = "Not found";
+pub const REPLY_HEALTHY: &str = "Healthy";
+pub const REPLY_READY: &str = "Ready";
+pub const REPLY_NOT_READY: &str = "Not ready";
+pub const REPLY_RELOADED: &str = "Configuration reloaded";
+pub const REPLY_RELOAD_DISABLED: &str = "Reload by HTTP is disabled, use --enable-reload";
 
-pub const OS_LINUX_CENTOS: &str = "LINUX_CENTOS";
-pub const OS_LINUX_OTHER: &str = "LINUX_OTHER";
-pub const OS_LINUX_RHEL: &str = "LINUX_RHEL";
-pub const OS_LINUX_SERVER_OTHER: &str = "LINUX_SERVER_OTHER";
-pub const OS_LINUX_SUSE: &str = "LINUX_SUSE";
-pub const OS_LINUX_UBUNTU: &str = "LINUX_UBUNTU";
-pub const OS_UNKNOWN: &str = "UNKNOWN";
-pub const OS_WINDOWS_10: &str = "WINDOWS_10";
-pub const OS_WINDOWS_11: &str = "WINDOWS_11";
-pub const OS_WINDOWS_7: &str = "WINDOWS_7";
-pub const OS_WINDOWS_8: &str = "WINDOWS_8";
-pub const OS_WINDOWS_SERVER_2003: &str = "WINDOWS_SERVER_2003";
-pub const OS_WINDOWS_SERVER_2008: &str = "WINDOWS_SERVER_2008";
-pub const OS_WINDOWS_SERVER_2008_R2: &str = "WINDOWS_SERVER_2008_R2";
-pub const OS_WINDOWS_SERVER_2012: &str = "WINDOWS_SERVER_2012";
-pub const OS_WINDOWS_SERVER_2012_R2: &str = "WINDOWS_SERVER_2012_R2";
-pub const OS_WINDOWS_SERVER_2016_OR_ABOVE: &str = "WINDOWS_SERVER_2016_OR_ABOVE";
-pub const OS_WINDOWS_VISTA: &str = "WINDOWS_VISTA";
-pub const OS_WINDOWS_XP: &str = "WINDOWS_XP";
-pub const LC_OS_LINUX_CENTOS: &str = "linux_centos";
-pub const LC_OS_LINUX_OTHER: &str = "linux_other";
-pub const LC_OS_LINUX_RHEL: &str = "linux_rhel";
-pub const LC_OS_LINUX_SERVER_OTHER: &str = "linux_server_other";
-pub const LC_OS_LINUX_SUSE: &str = "linux_suse";
-pub const LC_OS_LINUX_UBUNTU: &str = "linux_ubuntu";
-pub const LC_OS_UNKNOWN: &str = "unknown";
-pub const LC_OS_WINDOWS_10: &str = "windows_10";
-pub const LC_OS_WINDOWS_11: &str = "windows_11";
-pub const LC_OS_WINDOWS_7: &str = "windows_7";
-pub const LC_OS_WINDOWS_8: &str = "windows_8";
-pub const LC_OS_WINDOWS_SERVER_2003: &str = "windows_server_2003";
-pub const LC_OS_WINDOWS_SERVER_2008: &str = "windows_server_2008";
-pub const LC_OS_WINDOWS_SERVER_2008_R2: &str = "windows_server_2008_r2";
-pub const LC_OS_WINDOWS_SERVER_2012: &str = "windows_server_2012";
-pub const LC_OS_WINDOWS_SERVER_2012_R2: &str = "windows_server_2012_r2";
-pub const LC_OS_WINDOWS_SERVER_2016_OR_ABOVE: &str = "windows_server_2016_or_above";
-pub const LC_OS_WINDOWS_VISTA: &str = "windows_vista";
-pub const LC_OS_WINDOWS_XP: &str = "windows_xp";
+pub const COLLECTOR_LOGIN: &str = "login";
+pub const COLLECTOR_VERSION: &str = "version";
+pub const COLLECTOR_DESKTOP_POOLS: &str = "desktop_pools";
+pub const COLLECTOR_SESSIONS: &str = "sessions";
+pub const COLLECTOR_MACHINES: &str = "machines";
+pub const COLLECTOR_LOGOUT: &str = "logout";
 
-pub const ARCH_BIT_64: &str = "BIT_64";
-pub const ARCH_BIT_32: &str = "BIT_32";
-pub const ARCH_UNKNOWN: &str = "UNKNOWN";
-pub const LC_ARCH_BIT_64: &str = "bit_64";
-pub const LC_ARCH_BIT_32: &str = "bit_32";
-pub const LC_ARCH_UNKNOWN: &str = "unknown";
+pub const OUTPUT_PUSHGATEWAY: &str = "pushgateway";
+pub const OUTPUT_REMOTE_WRITE: &str = "remote_write";
+pub const OUTPUT_INFLUXDB: &str = "influxdb";
+pub const OUTPUT_GRAPHITE: &str = "graphite";
+pub const OUTPUT_OTLP: &str = "otlp";
 
 pub const REST_LOGIN: &str = "/rest/login";
 pub const REST_LOGOUT: &str = "/rest/logout";
-pub const REST_SESSIONS: &str = "/rest/inventory/v1/sessions";
-pub const REST_DESKTOP_POOLS: &str = "/rest/inventory/v1/desktop-pools";
-pub const REST_MACHINES: &str = "/rest/inventory/v1/machines";
+pub const REST_REFRESH: &str = "/rest/refresh";
+pub const REST_ENVIRONMENT_PROPERTIES: &str = "/rest/config/v1/environment-properties";
+pub const REST_SESSIONS_V1: &str = "/rest/inventory/v1/sessions";
+pub const REST_SESSIONS_V2: &str = "/rest/inventory/v2/sessions";
+pub const REST_DESKTOP_POOLS_V1: &str = "/rest/inventory/v1/desktop-pools";
+pub const REST_DESKTOP_POOLS_V2: &str = "/rest/inventory/v2/desktop-pools";
+pub const REST_DESKTOP_POOLS_V3: &str = "/rest/inventory/v3/desktop-pools";
+pub const REST_MACHINES_V1: &str = "/rest/inventory/v1/machines";
+pub const REST_MACHINES_V2: &str = "/rest/inventory/v2/machines";
+pub const REST_MACHINES_V3: &str = "/rest/inventory/v3/machines";
 
 pub const SESSIONS_NAME: &str = "horizon_sessions";
 pub const SESSIONS_HELP: &str = "Horizon sessions";
-pub const AGENT_VERSIONS_NAME: &str = "horizon_agent_version_info";
-pub const AGENT_VERSIONS_HELP: &str = "Version of horizon agent";
+pub const AGENT_VERSIONS_NAME: &str = "horizon_agent_versions";
+pub const AGENT_VERSIONS_HELP: &str = "Number of sessions per version of horizon agent";
+pub const AGENT_VERSION_INFO_NAME: &str = "horizon_agent_version_info";
+pub const AGENT_VERSION_INFO_HELP: &str = "Version of horizon agent";
 pub const SESSION_PROTOCOLS_NAME: &str = "horizon_session_protocols";
 pub const SESSION_PROTOCOLS_HELP: &str = "Horizon session protocols";
 pub const SESSION_TYPES_NAME: &str = "horizon_session_types";
 pub const SESSION_TYPES_HELP: &str = "Horizon session type";
 pub const MACHINE_STATES_NAME: &str = "horizon_machine_states";
 pub const MACHINE_STATES_HELP: &str = "State of horizon virtual machine";
-pub const MACHINE_OS_NAME: &str = "horizon_machine_os_info";
-pub const MACHINE_OS_HELP: &str = "Operating system on virtual machines";
-pub const MACHINE_ARCH_NAME: &str = "horizon_machine_os_arch_info";
-pub const MACHINE_ARCH_HELP: &str = "Architecture of operating system on virtual machine";
+pub const MACHINE_OS_NAME: &str = "horizon_machine_operating_systems";
+pub const MACHINE_OS_HELP: &str = "Number of virtual machines per operating system";
+pub const MACHINE_OS_INFO_NAME: &str = "horizon_machine_os_info";
+pub const MACHINE_OS_INFO_HELP: &str = "Operating system on virtual machines";
+pub const MACHINE_ARCH_NAME: &str = "horizon_machine_os_architectures";
+pub const MACHINE_ARCH_HELP: &str =
+    "Number of virtual machines per architecture of operating system";
+pub const MACHINE_ARCH_INFO_NAME: &str = "horizon_machine_os_arch_info";
+pub const MACHINE_ARCH_INFO_HELP: &str = "Architecture of operating system on virtual machine";
+pub const BUILD_INFO_NAME: &str = "horizon_build_info";
+pub const BUILD_INFO_HELP: &str = "Version and build of the Horizon connection server";
+pub const ACTIVE_ENDPOINT_NAME: &str = "horizon_exporter_active_endpoint";
+pub const ACTIVE_ENDPOINT_HELP: &str = "Horizon connection server used by the last login";
+pub const ENDPOINT_FAILOVERS_NAME: &str = "horizon_exporter_endpoint_failovers_total";
+pub const ENDPOINT_FAILOVERS_HELP: &str =
+    "Number of failovers to another Horizon connection server";
+pub const NEW_CONNECTIONS_NAME: &str = "horizon_exporter_new_connections_total";
+pub const NEW_CONNECTIONS_HELP: &str =
+    "Estimated number of new connections to Horizon connection servers";
+pub const COLLECTOR_DURATION_NAME: &str = "horizon_exporter_collector_duration_seconds";
+pub const COLLECTOR_DURATION_HELP: &str = "Duration of the last run of a collector";
+pub const REMOTE_WRITE_DROPPED_SAMPLES_NAME: &str =
+    "horizon_exporter_remote_write_dropped_samples_total";
+pub const REMOTE_WRITE_DROPPED_SAMPLES_HELP: &str =
+    "Number of samples dropped because the remote_write queue was full or the endpoint rejected them";
+pub const UNKNOWN_ENUM_VALUES_NAME: &str = "horizon_unknown_enum_values_total";
+pub const UNKNOWN_ENUM_VALUES_HELP: &str =
+    "Number of values in Horizon REST API responses unknown to the exporter";
+
+pub const FIELD_SESSION_STATE: &str = "session_state";
+pub const FIELD_SESSION_PROTOCOL: &str = "session_protocol";
+pub const FIELD_SESSION_TYPE: &str = "session_type";
+pub const FIELD_MACHINE_STATE: &str = "state";
+pub const FIELD_OPERATING_SYSTEM: &str = "operating_system";
+pub const FIELD_OPERATING_SYSTEM_ARCHITECTURE: &str = "operating_system_architecture";
@@ -1,8 +1,155 @@
-use serde::{Deserialize, Serialize};
+use crate::constants;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+// Enumeration of the values of a field in the Horizon REST API. Values unknown to the exporter,
+// e.g. introduced by a newer Horizon version, are kept as Other
+macro_rules! horizon_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $value:literal,)+ }) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($variant,)+
+            Other(String),
+        }
+
+        impl $name {
+            /// All values known to the exporter
+            pub const KNOWN: &'static [$name] = &[$($name::$variant,)+];
+
+            /// Value as used by the Horizon REST API
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $value,)+
+                    $name::Other(v) => v,
+                }
+            }
+
+            /// Value as used for metric labels
+            pub fn label(&self) -> String {
+                self.as_str().to_lowercase()
+            }
+
+            pub fn is_other(&self) -> bool {
+                matches!(self, $name::Other(_))
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(s: &str) -> Self {
+                match s {
+                    $($value => $name::$variant,)+
+                    _ => $name::Other(s.to_string()),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                Ok($name::from(s.as_str()))
+            }
+        }
+    };
+}
+
+horizon_enum!(SessionState {
+    Connected => "CONNECTED",
+    Disconnected => "DISCONNECTED",
+    Pending => "PENDING",
+});
+
+horizon_enum!(SessionProtocol {
+    Pcoip => "PCOIP",
+    Rdp => "RDP",
+    Blast => "BLAST",
+    Console => "CONSOLE",
+    Unknown => "UNKNOWN",
+});
+
+horizon_enum!(SessionType {
+    Application => "APPLICATION",
+    Desktop => "DESKTOP",
+});
+
+horizon_enum!(MachineState {
+    AgentConfigError => "AGENT_CONFIG_ERROR",
+    AgentDrainMode => "AGENT_DRAIN_MODE",
+    AgentDrainUntilRestart => "AGENT_DRAIN_UNTIL_RESTART",
+    AgentErrorDisabled => "AGENT_ERROR_DISABLED",
+    AgentErrorInvalidIp => "AGENT_ERROR_INVALID_IP",
+    AgentErrorNeedsReboot => "AGENT_ERROR_NEEDS_REBOOT",
+    AgentErrorProtocolFailure => "AGENT_ERROR_PROTOCOL_FAILURE",
+    AgentErrorStartupInProgress => "AGENT_ERROR_STARTUP_IN_PROGRESS",
+    AgentErrorDomainFailure => "AGENT_ERROR_DOMAIN_FAILURE",
+    AgentUnreachable => "AGENT_UNREACHABLE",
+    AlreadyUsed => "ALREADY_USED",
+    Available => "AVAILABLE",
+    Connected => "CONNECTED",
+    Customizing => "CUSTOMIZING",
+    Deleting => "DELETING",
+    Disabled => "DISABLED",
+    DisableInProgress => "DISABLE_IN_PROGRESS",
+    Disconnected => "DISCONNECTED",
+    Error => "ERROR",
+    InProgress => "IN_PROGRESS",
+    Maintenance => "MAINTENANCE",
+    Provisioned => "PROVISIONED",
+    Provisioning => "PROVISIONING",
+    ProvisioningError => "PROVISIONING_ERROR",
+    UnassignedUserConnected => "UNASSIGNED_USER_CONNECTED",
+    UnassignedUserDisconnected => "UNASSIGNED_USER_DISCONNECTED",
+    Unknown => "UNKNOWN",
+    Validating => "VALIDATING",
+    WaitingForAgent => "WAITING_FOR_AGENT",
+});
+
+horizon_enum!(OperatingSystem {
+    LinuxCentos => "LINUX_CENTOS",
+    LinuxOther => "LINUX_OTHER",
+    LinuxRhel => "LINUX_RHEL",
+    LinuxServerOther => "LINUX_SERVER_OTHER",
+    LinuxSuse => "LINUX_SUSE",
+    LinuxUbuntu => "LINUX_UBUNTU",
+    Unknown => "UNKNOWN",
+    Windows10 => "WINDOWS_10",
+    Windows11 => "WINDOWS_11",
+    Windows7 => "WINDOWS_7",
+    Windows8 => "WINDOWS_8",
+    WindowsServer2003 => "WINDOWS_SERVER_2003",
+    WindowsServer2008 => "WINDOWS_SERVER_2008",
+    WindowsServer2008R2 => "WINDOWS_SERVER_2008_R2",
+    WindowsServer2012 => "WINDOWS_SERVER_2012",
+    WindowsServer2012R2 => "WINDOWS_SERVER_2012_R2",
+    WindowsServer2016OrAbove => "WINDOWS_SERVER_2016_OR_ABOVE",
+    WindowsVista => "WINDOWS_VISTA",
+    WindowsXp => "WINDOWS_XP",
+});
+
+horizon_enum!(Architecture {
+    Bit64 => "BIT_64",
+    Bit32 => "BIT_32",
+    Unknown => "UNKNOWN",
+});
 
 #[derive(Deserialize, Clone, Debug)]
 pub struct ErrorResponse {
+    #[allow(dead_code)]
     pub status: String,
+    #[allow(dead_code)]
     pub timestamp: i64,
     pub errors: Vec<ErrorMessage>,
 }
@@ -13,51 +160,94 @@ pub struct ErrorMessage {
     pub error_message: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone)]
 pub struct LoginRequest {
     pub domain: String,
     pub password: String,
     pub username: String,
 }
 
+// The password is never written to the logs
+impl fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("domain", &self.domain)
+            .field("password", &constants::REDACTED)
+            .field("username", &self.username)
+            .finish()
+    }
+}
+
 #[derive(Deserialize, Clone, Debug)]
 pub struct LoginResponse {
     pub access_token: String,
     pub refresh_token: String,
 }
 
-#[derive(Serialize, Clone, Debug)]
+#[derive(Serialize, Clone)]
 pub struct LogoutRequest {
     pub refresh_token: String,
 }
 
+impl fmt::Debug for LogoutRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LogoutRequest")
+            .field("refresh_token", &constants::REDACTED)
+            .finish()
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
 #[derive(Deserialize, Clone, Debug)]
+pub struct RefreshResponse {
+    pub access_token: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EnvironmentProperties {
+    pub local_connection_server_version: Option<String>,
+    pub local_connection_server_build: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Session {
     pub id: String,
     pub user_id: String,
+    pub machine_id: Option<String>,
     pub desktop_pool_id: Option<String>,
     pub agent_version: String,
-    pub session_type: String,
-    pub session_state: String,
-    pub session_protocol: Option<String>,
+    pub session_type: SessionType,
+    pub session_state: SessionState,
+    pub session_protocol: Option<SessionProtocol>,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct DesktopPool {
     pub id: String,
+    pub name: Option<String>,
     pub enabled: bool,
 }
 
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct Machine {
     pub id: String,
+    pub name: Option<String>,
+    pub agent_version: Option<String>,
     pub desktop_pool_id: String,
-    pub operating_system: Option<String>,
-    pub operating_system_architecture: Option<String>,
-    pub state: String,
+    pub operating_system: Option<OperatingSystem>,
+    pub operating_system_architecture: Option<Architecture>,
+    pub state: MachineState,
     #[serde(rename = "type")]
     pub mtype: String,
     pub user_ids: Option<Vec<String>>,
+    /// State used for the metrics, UNASSIGNED_USER_* states of sessions of an assigned user are
+    /// counted as CONNECTED or DISCONNECTED. Set by the collector, not by Horizon.
+    #[serde(default, skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub effective_state: Option<MachineState>,
 }
 
 #[derive(Serialize, Clone, Debug)]
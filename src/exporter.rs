@@ -1,137 +1,149 @@
 use crate::configuration;
 use crate::constants;
-use crate::globals;
-use crate::horizon;
-use crate::http;
-use crate::machines;
-use crate::sessions;
+use crate::remote_write;
 
 use lazy_static::lazy_static;
 use log::{debug, error, warn};
-use prometheus::{IntGaugeVec, Opts, Registry, TextEncoder};
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use prometheus::{Registry, TextEncoder};
+use std::error::Error;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use vmware_horizon_exporter::Collector;
+
+// State of the Horizon refresh shared by concurrent scrapes
+#[derive(Default)]
+struct RefreshState {
+    in_progress: bool,
+    generation: u64,
+    result: Vec<MetricFamily>,
+    success: bool,
+}
 
 lazy_static! {
+    static ref REFRESH: (Mutex<RefreshState>, Condvar) =
+        (Mutex::new(RefreshState::default()), Condvar::new());
     pub static ref REGISTRY: Registry = Registry::new();
-    pub static ref SESSIONS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(constants::SESSIONS_NAME, constants::SESSIONS_HELP),
-        &["pool", "state"]
-    )
-    .unwrap();
-    pub static ref AGENT_VERSIONS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(
-            constants::AGENT_VERSIONS_NAME,
-            constants::AGENT_VERSIONS_HELP
-        ),
-        &["pool", "version"]
-    )
-    .unwrap();
-    pub static ref SESSION_PROTOCOLS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(
-            constants::SESSION_PROTOCOLS_NAME,
-            constants::SESSION_PROTOCOLS_HELP
-        ),
-        &["pool", "protocol"]
-    )
-    .unwrap();
-    pub static ref SESSION_TYPES: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(constants::SESSION_TYPES_NAME, constants::SESSION_TYPES_HELP),
-        &["pool", "type"]
-    )
-    .unwrap();
-    pub static ref MACHINE_STATES: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(
-            constants::MACHINE_STATES_NAME,
-            constants::MACHINE_STATES_HELP
-        ),
-        &["pool", "state"],
-    )
-    .unwrap();
-    pub static ref MACHINE_OS: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(constants::MACHINE_OS_NAME, constants::MACHINE_OS_HELP),
-        &["pool", "operating_system"]
-    )
-    .unwrap();
-    pub static ref MACHINE_ARCH: IntGaugeVec = IntGaugeVec::new(
-        Opts::new(constants::MACHINE_ARCH_NAME, constants::MACHINE_ARCH_HELP),
-        &["pool", "architecture"]
-    )
-    .unwrap();
+    pub static ref COLLECTOR: Collector = Collector::new(&REGISTRY).unwrap();
 }
 
 pub fn register_metrics() {
-    REGISTRY.register(Box::new(SESSIONS.clone())).unwrap();
-    REGISTRY.register(Box::new(AGENT_VERSIONS.clone())).unwrap();
-    REGISTRY
-        .register(Box::new(SESSION_PROTOCOLS.clone()))
-        .unwrap();
-    REGISTRY.register(Box::new(SESSION_TYPES.clone())).unwrap();
-    REGISTRY.register(Box::new(MACHINE_STATES.clone())).unwrap();
-    REGISTRY.register(Box::new(MACHINE_OS.clone())).unwrap();
-    REGISTRY.register(Box::new(MACHINE_ARCH.clone())).unwrap();
+    lazy_static::initialize(&COLLECTOR);
+    if let Err(e) = REGISTRY.register(Box::new(remote_write::DROPPED_SAMPLES.clone())) {
+        error!("can't register metrics of the remote_write output: {}", e);
+    }
 }
 
-fn metric_update(cfg: &configuration::Configuration, client: &mut reqwest::blocking::Client) {
-    debug!("exporter.rs:metric_update: login to horizon");
-    let tokens = match horizon::login(cfg, client) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("horizon login failed: {}", e);
-            return;
-        }
-    };
+// Value of single value metrics, summaries and histograms are not used by the exporter
+pub fn sample_value(mtype: MetricType, m: &Metric) -> Option<f64> {
+    match mtype {
+        MetricType::COUNTER => Some(m.get_counter().get_value()),
+        MetricType::GAUGE => Some(m.get_gauge().get_value()),
+        MetricType::UNTYPED => Some(m.get_untyped().get_value()),
+        _ => None,
+    }
+}
 
-    // fetch pool data only once
-    {
-        let mut desktop_pools = globals::DESKTOP_POOLS.lock().unwrap();
-        debug!("exporter.rs:metric_update: getting list of desktop pools");
-        *desktop_pools = match horizon::get_desktop_pools(cfg, client, &tokens.access_token) {
-            Ok(v) => v,
-            Err(e) => {
-                error!("can't get list of desktop pools: {}", e);
-                return;
+pub fn fetch(cfg: &configuration::Configuration) -> Vec<MetricFamily> {
+    fetch_with_result(cfg).0
+}
+
+// Marks the refresh as finished and wakes up the waiting requests, even if the refresh panics
+struct RefreshGuard {
+    done: Option<(Vec<MetricFamily>, bool)>,
+}
+
+impl Drop for RefreshGuard {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*REFRESH;
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_progress = false;
+        state.generation += 1;
+        match self.done.take() {
+            Some((result, success)) => {
+                state.result = result;
+                state.success = success;
+            }
+            None => {
+                error!("refresh of the Horizon metrics was aborted");
+                state.success = false;
             }
         };
+        cvar.notify_all();
     }
+}
 
-    if let Err(e) = sessions::session_metric_update(cfg, client, &tokens.access_token) {
-        error!("session metric update failed: {}", e);
-    }
+// Returns the metrics and false if the login or one of the collectors failed
+pub fn fetch_with_result(cfg: &configuration::Configuration) -> (Vec<MetricFamily>, bool) {
+    let (lock, cvar) = &*REFRESH;
 
-    if let Err(e) = machines::machine_metric_update(cfg, client, &tokens.access_token) {
-        error!("machine metric update failed: {}", e);
+    {
+        let mut state = lock.lock().unwrap_or_else(|e| e.into_inner());
+        if state.in_progress {
+            // another request is already talking to Horizon, wait for its result
+            debug!("exporter.rs:fetch: refresh already in progress, waiting for result");
+            let timeout = Duration::from_secs(
+                cfg.exporter
+                    .scrape_wait_timeout
+                    .unwrap_or(constants::DEFAULT_SCRAPE_WAIT_TIMEOUT),
+            );
+            let deadline = Instant::now() + timeout;
+            let generation = state.generation;
+            while state.generation == generation {
+                let now = Instant::now();
+                if now >= deadline {
+                    warn!(
+                        "refresh of the Horizon metrics takes longer than {} seconds, returning the previous metrics",
+                        timeout.as_secs()
+                    );
+                    return (state.result.clone(), false);
+                }
+                state = cvar
+                    .wait_timeout(state, deadline - now)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0;
+            }
+            return (state.result.clone(), state.success);
+        }
+        state.in_progress = true;
     }
 
-    debug!("exporter.rs:metric_update: logout from horizon");
-    if let Err(e) = horizon::logout(cfg, client, &tokens.refresh_token) {
-        warn!("horizon logout failed: {}", e);
-    }
+    let mut guard = RefreshGuard { done: None };
+    let (result, success) = refresh(cfg);
+    guard.done = Some((result.clone(), success));
+
+    (result, success)
 }
 
-pub fn fetch(cfg: &configuration::Configuration) -> String {
-    let insecure_ssl = cfg.horizon_api.insecure_ssl.unwrap_or_default();
-    let ca_file = match &cfg.horizon_api.ca_file {
-        Some(v) => v,
-        None => "",
-    };
-    let timeout = cfg
-        .horizon_api
-        .timeout
-        .unwrap_or(constants::DEFAULT_TIMEOUT);
-
-    let mut http_client = match http::build_client(insecure_ssl, ca_file, timeout) {
-        Ok(v) => v,
-        Err(e) => {
-            error!("can't create HTTP client structure: {}", e);
-            return String::new();
-        }
-    };
+/// Log in and collect the metrics once in the background, so readiness doesn't depend on the
+/// first scrape of `/metrics`
+pub fn start_initial_refresh(
+    shared_cfg: configuration::SharedConfiguration,
+) -> Result<(), Box<dyn Error>> {
+    thread::Builder::new()
+        .name("initial-refresh".to_string())
+        .spawn(move || {
+            let cfg = configuration::current(&shared_cfg);
+            if !fetch_with_result(&cfg).1 {
+                warn!("initial collection of the Horizon metrics failed");
+            }
+        })?;
+    Ok(())
+}
+
+fn refresh(cfg: &configuration::Configuration) -> (Vec<MetricFamily>, bool) {
+    let success = COLLECTOR.collect(cfg);
 
-    metric_update(cfg, &mut http_client);
+    (REGISTRY.gather(), success)
+}
 
+// Prometheus text format
+pub fn encode_text(metrics: &[MetricFamily]) -> String {
     let encoder = TextEncoder::new();
     let mut buffer = String::new();
 
-    if let Err(e) = encoder.encode_utf8(&REGISTRY.gather(), &mut buffer) {
+    if let Err(e) = encoder.encode_utf8(metrics, &mut buffer) {
         error!("unable to encode collected metrics as UTF-8 string: {}", e);
     }
 
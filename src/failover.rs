@@ -0,0 +1,85 @@
+use crate::configuration;
+use crate::constants;
+
+use log::{debug, info, warn};
+use std::collections::HashMap;
+
+// Failures of a connection server, servers failing recently are tried last
+#[derive(Clone, Debug, Default)]
+struct EndpointHealth {
+    failures: u32,
+    down_until: i64,
+}
+
+/// Health of the connection servers and position of the round-robin selection of a client
+#[derive(Debug, Default)]
+pub struct EndpointState {
+    health: HashMap<String, EndpointHealth>,
+    // start index of the next login for round-robin
+    next: usize,
+}
+
+impl EndpointState {
+    /// Connection servers in the order they should be tried for the next login
+    ///
+    /// With `ordered` failover the servers are tried in the configured order, with `round_robin`
+    /// each login starts at the next server. Servers which failed during the last
+    /// `failover_holdoff` seconds are moved to the end of the list.
+    pub fn candidates(&mut self, cfg: &configuration::HorizonAPIConfig) -> Vec<String> {
+        let urls = &cfg.url_list;
+        if urls.len() < 2 {
+            return urls.clone();
+        }
+
+        let start = match cfg.failover.as_deref() {
+            Some(constants::FAILOVER_ROUND_ROBIN) => {
+                let start = self.next % urls.len();
+                self.next = (start + 1) % urls.len();
+                start
+            }
+            _ => 0,
+        };
+
+        let now = chrono::Local::now().timestamp();
+        let (healthy, down): (Vec<String>, Vec<String>) = urls[start..]
+            .iter()
+            .chain(urls[..start].iter())
+            .cloned()
+            .partition(|u| match self.health.get(u) {
+                Some(h) => h.down_until <= now,
+                None => true,
+            });
+
+        debug!(
+            "failover.rs:candidates: healthy servers {:?}, failed servers {:?}",
+            healthy, down
+        );
+        healthy.into_iter().chain(down).collect()
+    }
+
+    pub fn endpoint_failed(&mut self, cfg: &configuration::HorizonAPIConfig, url: &str) {
+        if cfg.url_list.len() < 2 {
+            return;
+        }
+        let holdoff = cfg
+            .failover_holdoff
+            .unwrap_or(constants::DEFAULT_FAILOVER_HOLDOFF);
+
+        let h = self.health.entry(url.to_string()).or_default();
+        h.failures += 1;
+        h.down_until = chrono::Local::now().timestamp() + holdoff as i64;
+        warn!(
+            "connection server {} failed {} times in a row, trying it last for {} seconds",
+            url, h.failures, holdoff
+        );
+    }
+
+    pub fn endpoint_succeeded(&mut self, url: &str) {
+        if let Some(h) = self.health.remove(url) {
+            info!(
+                "connection server {} is available again after {} failures",
+                url, h.failures
+            );
+        }
+    }
+}
@@ -0,0 +1,32 @@
+use simple_error::bail;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+/// Write to a temporary file in the same directory and rename it, so readers like the
+/// textfile collector of node_exporter or a replay never see a partially written file
+pub fn write_atomic<P: AsRef<Path>>(f: P, content: &str) -> Result<(), Box<dyn Error>> {
+    let path = f.as_ref();
+    let file_name = match path.file_name() {
+        Some(v) => v.to_string_lossy(),
+        None => bail!("{} is not a file name", path.display()),
+    };
+    let tmp_path = path.with_file_name(format!(".{}.{}.tmp", file_name, process::id()));
+
+    let result = write_and_rename(&tmp_path, path, content);
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    result
+}
+
+fn write_and_rename(tmp_path: &Path, path: &Path, content: &str) -> Result<(), Box<dyn Error>> {
+    let mut fd = fs::File::create(tmp_path)?;
+    fd.write_all(content.as_bytes())?;
+    fd.sync_all()?;
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
@@ -1,8 +1,12 @@
-use crate::data;
-
 use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
 use std::sync::Mutex;
+use vmware_horizon_exporter::CollectorStatus;
+
+pub static SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
 lazy_static! {
-    pub static ref DESKTOP_POOLS: Mutex<Vec<data::DesktopPool>> = Mutex::new(Vec::new());
+    // results of the outputs pushing the metrics
+    pub static ref OUTPUTS: Mutex<BTreeMap<String, CollectorStatus>> = Mutex::new(BTreeMap::new());
 }
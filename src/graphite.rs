@@ -0,0 +1,118 @@
+use crate::configuration;
+use crate::constants;
+use crate::measurement::Measurement;
+use crate::sink::{Collected, Sink};
+
+use log::debug;
+use simple_error::bail;
+use std::error::Error;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// Graphite uses dots as path separator, so only a safe subset of characters is kept
+fn sanitize(s: &str) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+// Tag values must not contain ; or ~ and must not be empty
+fn sanitize_tag(s: &str) -> String {
+    s.replace([';', '~', ' '], "_")
+}
+
+// The pool and the value of the counted field are appended as path components, e.g.
+// prefix.horizon_sessions.<pool>.<state>, or as tags if tagged is set
+fn encode(
+    cfg: &configuration::GraphiteConfig,
+    measurements: &[Measurement],
+    timestamp: i64,
+) -> String {
+    let prefix = cfg
+        .prefix
+        .clone()
+        .unwrap_or_else(|| constants::DEFAULT_GRAPHITE_PREFIX.to_string());
+    let tagged = cfg.tagged.unwrap_or_default();
+    let mut result = String::new();
+
+    for m in measurements {
+        let mut path = if prefix.is_empty() {
+            sanitize(m.name)
+        } else {
+            format!("{}.{}", prefix, sanitize(m.name))
+        };
+        for (name, value) in [("pool", &m.pool), (m.field, &m.value)] {
+            if tagged {
+                if !value.is_empty() {
+                    path.push_str(&format!(";{}={}", sanitize_tag(name), sanitize_tag(value)));
+                }
+            } else if value.is_empty() {
+                // an empty path component would result in an invalid path like a..b
+                path.push_str(&format!(".{}", constants::GRAPHITE_EMPTY_VALUE));
+            } else {
+                path.push_str(&format!(".{}", sanitize(value)));
+            }
+        }
+
+        result.push_str(&format!("{} {} {}\n", path, m.count, timestamp));
+    }
+
+    result
+}
+
+pub struct Graphite {
+    cfg: configuration::GraphiteConfig,
+    interval: u64,
+}
+
+impl Graphite {
+    pub fn new(cfg: &configuration::GraphiteConfig, default_interval: u64) -> Self {
+        Graphite {
+            cfg: cfg.clone(),
+            interval: cfg.interval.unwrap_or(default_interval),
+        }
+    }
+}
+
+impl Sink for Graphite {
+    fn name(&self) -> &'static str {
+        constants::OUTPUT_GRAPHITE
+    }
+
+    fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>> {
+        send(&self.cfg, &data.measurements)?;
+        Ok(true)
+    }
+}
+
+fn send(
+    cfg: &configuration::GraphiteConfig,
+    measurements: &[Measurement],
+) -> Result<(), Box<dyn Error>> {
+    let payload = encode(cfg, measurements, chrono::Local::now().timestamp());
+    let timeout = Duration::from_secs(cfg.timeout.unwrap_or(constants::DEFAULT_TIMEOUT));
+
+    let addr = match cfg.address.to_socket_addrs()?.next() {
+        Some(v) => v,
+        None => bail!("can't resolve address {}", cfg.address),
+    };
+
+    debug!("graphite.rs:send: connecting to {}", addr);
+    let mut stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_write_timeout(Some(timeout))?;
+    stream.write_all(payload.as_bytes())?;
+    stream.flush()?;
+
+    Ok(())
+}
@@ -1,258 +1,472 @@
+use crate::api::{ApiVersions, Resource};
 use crate::configuration;
 use crate::constants;
 use crate::data;
+use crate::failover;
 use crate::http;
+use crate::recorder;
+use crate::tls;
 
-use log::debug;
+use log::{debug, info, warn};
 use simple_error::bail;
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Client for the Horizon REST API
+///
+/// The client logs in with the credentials of the `horizon_api` section of the
+/// configuration and keeps the access and refresh token until `logout` is called.
+/// The versions of the inventory endpoints are selected by the Horizon version
+/// returned by `environment_properties`. If several connection servers are configured,
+/// `login` selects the server used until the next login.
+///
+/// The HTTP connections are kept open between logins, so a client should be reused
+/// as long as `is_current` returns true.
+pub struct HorizonClient {
+    cfg: configuration::HorizonAPIConfig,
+    url: String,
+    // base URL of the requests, differs from url if the TLS server name is overridden
+    base: String,
+    failovers: u32,
+    endpoints: failover::EndpointState,
+    http: reqwest::blocking::Client,
+    // HTTP clients and base URLs of all connection servers used so far
+    clients: HashMap<String, (reqwest::blocking::Client, String)>,
+    stamp: Vec<(PathBuf, Option<SystemTime>)>,
+    tokens: Option<data::LoginResponse>,
+    api: ApiVersions,
+    requests: http::RequestContext,
+}
 
-pub fn login(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-) -> Result<data::LoginResponse, Box<dyn Error>> {
-    let lgi = data::LoginRequest {
-        domain: cfg.horizon_api.domain.clone(),
-        password: cfg.horizon_api.password.clone(),
-        username: cfg.horizon_api.user.clone(),
-    };
+impl HorizonClient {
+    /// Create a new client, no request is sent before `login` is called
+    pub fn new(cfg: &configuration::Configuration) -> Result<Self, Box<dyn Error>> {
+        let url = cfg
+            .horizon_api
+            .url_list
+            .first()
+            .cloned()
+            .unwrap_or_default();
+        let stamp = tls::file_stamp(&cfg.horizon_api);
+        let (http, base) = http::build_horizon_client(&cfg.horizon_api, &url)?;
+
+        Ok(HorizonClient {
+            cfg: cfg.horizon_api.clone(),
+            url: url.clone(),
+            base: base.clone(),
+            failovers: 0,
+            endpoints: failover::EndpointState::default(),
+            http: http.clone(),
+            clients: HashMap::from([(url, (http, base))]),
+            stamp,
+            tokens: None,
+            api: ApiVersions::default(),
+            requests: http::RequestContext::new(&cfg.horizon_api),
+        })
+    }
 
-    let payload = serde_json::to_string(&lgi)?;
-
-    debug!(
-        "horizon.rs:login: sending login data to {}{} - {:?}",
-        cfg.horizon_api.url,
-        constants::REST_LOGIN,
-        lgi
-    );
-
-    let (st, lg_str) = http::post(
-        cli,
-        &format!("{}{}", cfg.horizon_api.url, constants::REST_LOGIN),
-        &payload,
-        None,
-    )?;
-
-    debug!(
-        "horizon.rs:login: received response HTTP status={} - {:?}",
-        st, lg_str
-    );
-
-    if st != reqwest::StatusCode::OK {
-        bail!(
-            "login failed, received {} instead of 200: {}",
-            st,
-            format_error_message(&lg_str)
-        );
+    /// False if the configuration or one of the certificate files changed since the client
+    /// was created
+    pub fn is_current(&self, cfg: &configuration::Configuration) -> bool {
+        self.cfg == cfg.horizon_api && self.stamp == tls::file_stamp(&cfg.horizon_api)
     }
 
-    let result = serde_json::from_str(lg_str.as_str())?;
+    /// Save the responses to a directory or replay saved responses instead of sending requests
+    pub fn set_recorder(&mut self, mode: recorder::Mode) -> Result<(), Box<dyn Error>> {
+        mode.prepare()?;
+        self.requests.recorder = mode;
+        Ok(())
+    }
 
-    Ok(result)
-}
+    /// Estimated number of new connections to the connection servers since the last call
+    pub fn take_new_connections(&mut self) -> u64 {
+        self.requests.take_new_connections()
+    }
 
-pub fn logout(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<(), Box<dyn Error>> {
-    let lgo = data::LogoutRequest {
-        refresh_token: token.to_string(),
-    };
+    /// Endpoints selected for the Horizon version, including fallbacks to older versions
+    pub fn api_versions(&self) -> &ApiVersions {
+        &self.api
+    }
+
+    /// Reuse endpoints selected by a previous client
+    pub fn set_api_versions(&mut self, api: ApiVersions) {
+        self.api = api;
+    }
 
-    let payload = serde_json::to_string(&lgo)?;
-
-    debug!(
-        "horizon.rs:logout: sending login data to {}{} - {:?}",
-        cfg.horizon_api.url,
-        constants::REST_LOGOUT,
-        lgo
-    );
-
-    let (st, lg_str) = http::post(
-        cli,
-        &format!("{}{}", cfg.horizon_api.url, constants::REST_LOGOUT),
-        &payload,
-        None,
-    )?;
-
-    debug!(
-        "horizon.rs:logout: received response HTTP status={} - {:?}",
-        st, lg_str
-    );
-
-    if st != reqwest::StatusCode::OK {
-        bail!(
-            "logout failed, received {} instead of 200: {}",
-            st,
-            format_error_message(&lg_str)
+    /// URL of the connection server selected by the last login
+    pub fn endpoint(&self) -> &str {
+        &self.url
+    }
+
+    /// Number of connection servers skipped by the last login because they failed
+    pub fn failovers(&self) -> u32 {
+        self.failovers
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    // Switch to another connection server, the TLS settings can depend on the server
+    fn connect(&mut self, url: String) -> Result<(), Box<dyn Error>> {
+        if url != self.url {
+            let (http, base) = match self.clients.get(&url) {
+                Some(v) => v.clone(),
+                None => {
+                    let v = http::build_horizon_client(&self.cfg, &url)?;
+                    self.clients.insert(url.clone(), v.clone());
+                    v
+                }
+            };
+            self.http = http;
+            self.base = base;
+            self.url = url;
+        }
+        http::verify_server(&self.http, &self.base, &mut self.requests)
+    }
+
+    // GET a resource, older versions of the endpoint are used if Horizon doesn't know it
+    fn get_resource(
+        &mut self,
+        resource: Resource,
+        query: &str,
+    ) -> Result<(reqwest::StatusCode, String), Box<dyn Error>> {
+        let token = self.access_token()?;
+        loop {
+            let url = format!("{}{}", self.url(self.api.path(resource)), query);
+            debug!(
+                "horizon.rs:get_resource: requesting {} from {}",
+                resource, url
+            );
+
+            let (st, reply) = http::get(&mut self.http, &url, &token, &mut self.requests)?;
+            debug!("horizon.rs:get_resource: received HTTP status={}", st);
+
+            // a filter without results is reported as 404 too
+            if st == reqwest::StatusCode::NOT_FOUND
+                && query.is_empty()
+                && self.api.fall_back(resource)
+            {
+                continue;
+            }
+            return Ok((st, reply));
+        }
+    }
+
+    fn access_token(&self) -> Result<String, Box<dyn Error>> {
+        match &self.tokens {
+            Some(v) => Ok(v.access_token.clone()),
+            None => bail!("not logged in to Horizon"),
+        }
+    }
+
+    pub fn is_logged_in(&self) -> bool {
+        self.tokens.is_some()
+    }
+
+    /// Login to Horizon, logins are suspended after too many consecutive failures to
+    /// prevent a lockout of the account
+    pub fn login(&mut self) -> Result<(), Box<dyn Error>> {
+        self.requests.breaker.login_allowed()?;
+
+        match self.login_failover() {
+            Ok(_) => {
+                self.requests.breaker.login_succeeded();
+                Ok(())
+            }
+            Err(e) => {
+                self.requests.breaker.login_failed();
+                Err(e)
+            }
+        }
+    }
+
+    // Try the connection servers until one of them answers. A rejected login is not sent to
+    // the other servers, they would reject it too.
+    fn login_failover(&mut self) -> Result<(), Box<dyn Error>> {
+        self.failovers = 0;
+        let mut last_error: Option<Box<dyn Error>> = None;
+
+        for url in self.endpoints.candidates(&self.cfg) {
+            if let Some(e) = &last_error {
+                info!(
+                    "login to {} failed ({}), failing over to {}",
+                    self.url, e, url
+                );
+                self.failovers += 1;
+            }
+            let login = match self.connect(url) {
+                Ok(_) => self.send_login(),
+                Err(e) => Err(e),
+            };
+
+            let (st, lg_str) = match login {
+                Ok(v) => v,
+                Err(e) => {
+                    self.endpoints.endpoint_failed(&self.cfg, &self.url);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            if st.is_server_error() || st == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                self.endpoints.endpoint_failed(&self.cfg, &self.url);
+                last_error = Some(
+                    format!(
+                        "login failed, received {} instead of 200: {}",
+                        st,
+                        format_error_message(&lg_str)
+                    )
+                    .into(),
+                );
+                continue;
+            }
+            self.endpoints.endpoint_succeeded(&self.url);
+
+            if st != reqwest::StatusCode::OK {
+                bail!(
+                    "login failed, received {} instead of 200: {}",
+                    st,
+                    format_error_message(&lg_str)
+                );
+            }
+
+            self.tokens = Some(serde_json::from_str(lg_str.as_str())?);
+            return Ok(());
+        }
+
+        match last_error {
+            Some(e) => {
+                if self.cfg.url_list.len() > 1 {
+                    warn!("login failed on all connection servers");
+                }
+                Err(e)
+            }
+            None => bail!("no Horizon URL configured"),
+        }
+    }
+
+    fn send_login(&mut self) -> Result<(reqwest::StatusCode, String), Box<dyn Error>> {
+        let lgi = data::LoginRequest {
+            domain: self.cfg.domain.clone(),
+            password: self.cfg.get_password()?,
+            username: self.cfg.user.clone(),
+        };
+
+        let payload = serde_json::to_string(&lgi)?;
+
+        debug!(
+            "horizon.rs:login: sending login data to {} - {:?}",
+            self.url(constants::REST_LOGIN),
+            lgi
         );
+
+        let url = self.url(constants::REST_LOGIN);
+        let (st, lg_str) = http::post(&mut self.http, &url, &payload, None, &mut self.requests)?;
+
+        // the response contains the tokens
+        debug!("horizon.rs:login: received response HTTP status={}", st);
+
+        Ok((st, lg_str))
     }
 
-    Ok(())
-}
+    /// Replace the access token by a new one obtained with the refresh token
+    pub fn refresh(&mut self) -> Result<(), Box<dyn Error>> {
+        let refresh_token = match &self.tokens {
+            Some(v) => v.refresh_token.clone(),
+            None => bail!("not logged in to Horizon"),
+        };
+        let rfr = data::RefreshRequest { refresh_token };
+
+        let payload = serde_json::to_string(&rfr)?;
 
-pub fn get_sessions(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<Vec<data::Session>, Box<dyn Error>> {
-    debug!(
-        "horizon.rs:get_sessions: requesting session list from {}{}",
-        cfg.horizon_api.url,
-        constants::REST_SESSIONS
-    );
-
-    let (st, sess) = http::get(
-        cli,
-        &format!("{}{}", cfg.horizon_api.url, constants::REST_SESSIONS),
-        token,
-    )?;
-    debug!("horizon.rs:get_sessions: received HTTP status={}", st);
-
-    if st != reqwest::StatusCode::OK {
-        bail!(
-            "logout failed, received {} instead of 200: {}",
-            st,
-            format_error_message(&sess)
+        debug!(
+            "horizon.rs:refresh: requesting new access token from {}",
+            self.url(constants::REST_REFRESH),
         );
+
+        let url = self.url(constants::REST_REFRESH);
+        let (st, rf_str) = http::post(&mut self.http, &url, &payload, None, &mut self.requests)?;
+
+        debug!("horizon.rs:refresh: received HTTP status={}", st);
+
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "token refresh failed, received {} instead of 200: {}",
+                st,
+                format_error_message(&rf_str)
+            );
+        }
+
+        let result: data::RefreshResponse = serde_json::from_str(rf_str.as_str())?;
+        if let Some(t) = self.tokens.as_mut() {
+            t.access_token = result.access_token;
+        }
+
+        Ok(())
     }
 
-    let slist: Vec<data::Session> = serde_json::from_str(sess.as_str())?;
-    debug!("horizon.rs:get_sessions: {} sessions in list", slist.len());
+    /// Logout and drop the tokens, the tokens are dropped even if the logout failed
+    pub fn logout(&mut self) -> Result<(), Box<dyn Error>> {
+        let tokens = match self.tokens.take() {
+            Some(v) => v,
+            None => bail!("not logged in to Horizon"),
+        };
+        let lgo = data::LogoutRequest {
+            refresh_token: tokens.refresh_token,
+        };
+
+        let payload = serde_json::to_string(&lgo)?;
+
+        debug!(
+            "horizon.rs:logout: sending logout request to {} - {:?}",
+            self.url(constants::REST_LOGOUT),
+            lgo
+        );
 
-    Ok(slist)
-}
+        let url = self.url(constants::REST_LOGOUT);
+        let (st, lg_str) = http::post(&mut self.http, &url, &payload, None, &mut self.requests)?;
 
-pub fn get_session_for_machine_id(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
-    machine_id: &str,
-) -> Result<Option<data::Session>, Box<dyn Error>> {
-    let mut filter = data::FilterPayload {
-        filter_type: "And".to_string(),
-        filters: Vec::new(),
-    };
-    let mid_filter = data::FilterRequest {
-        comparison: "Equals".to_string(),
-        name: "machine_id".to_string(),
-        value: machine_id.to_string(),
-    };
-    filter.filters.push(mid_filter);
-    let filter_str = serde_json::to_string(&filter)?;
-    let encoded_filter = urlencoding::encode(&filter_str);
-
-    debug!(
-        "horizon.rs:get_session_for_machine_id: requesting session list from {}{}?filter={}",
-        cfg.horizon_api.url,
-        constants::REST_SESSIONS,
-        encoded_filter,
-    );
-
-    let (st, sess) = http::get(
-        cli,
-        &format!(
-            "{}{}?filter={}",
-            cfg.horizon_api.url,
-            constants::REST_SESSIONS,
-            encoded_filter
-        ),
-        token,
-    )?;
-    debug!(
-        "horizon.rs:get_session_for_machine_id: received HTTP status={}",
-        st
-    );
-
-    if st != reqwest::StatusCode::OK && st != reqwest::StatusCode::NOT_FOUND {
-        bail!(
-            "logout failed, received {} instead of 200 or 404: {}",
-            st,
-            format_error_message(&sess)
+        debug!(
+            "horizon.rs:logout: received response HTTP status={} - {:?}",
+            st, lg_str
         );
+
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "logout failed, received {} instead of 200: {}",
+                st,
+                format_error_message(&lg_str)
+            );
+        }
+
+        Ok(())
     }
 
-    if st == reqwest::StatusCode::NOT_FOUND {
-        return Ok(None);
+    pub fn environment_properties(
+        &mut self,
+    ) -> Result<data::EnvironmentProperties, Box<dyn Error>> {
+        let token = self.access_token()?;
+        let url = self.url(constants::REST_ENVIRONMENT_PROPERTIES);
+        debug!(
+            "horizon.rs:environment_properties: requesting environment properties from {}",
+            url
+        );
+
+        let (st, env) = http::get(&mut self.http, &url, &token, &mut self.requests)?;
+        debug!(
+            "horizon.rs:environment_properties: received HTTP status={}",
+            st
+        );
+
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "environment property request failed, received {} instead of 200: {}",
+                st,
+                format_error_message(&env)
+            );
+        }
+
+        let result: data::EnvironmentProperties = serde_json::from_str(env.as_str())?;
+        if let Some(v) = &result.local_connection_server_version {
+            self.api.select(v);
+        }
+
+        Ok(result)
     }
 
-    let slist: Vec<data::Session> = serde_json::from_str(sess.as_str())?;
-    debug!(
-        "horizon.rs:get_session_for_machine_id: {} sessions in list",
-        slist.len()
-    );
-    if slist.is_empty() {
-        return Ok(None);
+    pub fn sessions(&mut self) -> Result<Vec<data::Session>, Box<dyn Error>> {
+        let (st, sess) = self.get_resource(Resource::Sessions, "")?;
+
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "session list request failed, received {} instead of 200: {}",
+                st,
+                format_error_message(&sess)
+            );
+        }
+
+        let slist: Vec<data::Session> = serde_json::from_str(sess.as_str())?;
+        debug!("horizon.rs:sessions: {} sessions in list", slist.len());
+
+        Ok(slist)
     }
-    Ok(Some(slist[0].clone()))
-}
 
-pub fn get_desktop_pools(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<Vec<data::DesktopPool>, Box<dyn Error>> {
-    debug!(
-        "horizon.rs:get_desktop_pools: requesting desktop pool list from {}{}",
-        cfg.horizon_api.url,
-        constants::REST_DESKTOP_POOLS
-    );
-
-    let (st, dpl) = http::get(
-        cli,
-        &format!("{}{}", cfg.horizon_api.url, constants::REST_DESKTOP_POOLS),
-        token,
-    )?;
-    debug!("horizon.rs:get_desktop_pools: received HTTP status={}", st);
-
-    if st != reqwest::StatusCode::OK {
-        bail!(
-            "logout failed, received {} instead of 200: {}",
-            st,
-            format_error_message(&dpl)
+    pub fn session_for_machine_id(
+        &mut self,
+        machine_id: &str,
+    ) -> Result<Option<data::Session>, Box<dyn Error>> {
+        let mut filter = data::FilterPayload {
+            filter_type: "And".to_string(),
+            filters: Vec::new(),
+        };
+        let mid_filter = data::FilterRequest {
+            comparison: "Equals".to_string(),
+            name: "machine_id".to_string(),
+            value: machine_id.to_string(),
+        };
+        filter.filters.push(mid_filter);
+        let filter_str = serde_json::to_string(&filter)?;
+        let encoded_filter = urlencoding::encode(&filter_str);
+
+        let (st, sess) =
+            self.get_resource(Resource::Sessions, &format!("?filter={}", encoded_filter))?;
+
+        if st != reqwest::StatusCode::OK && st != reqwest::StatusCode::NOT_FOUND {
+            bail!(
+                "session list request failed, received {} instead of 200 or 404: {}",
+                st,
+                format_error_message(&sess)
+            );
+        }
+
+        if st == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let slist: Vec<data::Session> = serde_json::from_str(sess.as_str())?;
+        debug!(
+            "horizon.rs:session_for_machine_id: {} sessions in list",
+            slist.len()
         );
+        Ok(slist.into_iter().next())
     }
 
-    let dplist: Vec<data::DesktopPool> = serde_json::from_str(dpl.as_str())?;
-    debug!(
-        "horizon.rs:get_desktop_pools: {} pools in list",
-        dplist.len()
-    );
+    pub fn desktop_pools(&mut self) -> Result<Vec<data::DesktopPool>, Box<dyn Error>> {
+        let (st, dpl) = self.get_resource(Resource::DesktopPools, "")?;
 
-    Ok(dplist)
-}
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "desktop pool list request failed, received {} instead of 200: {}",
+                st,
+                format_error_message(&dpl)
+            );
+        }
 
-pub fn get_machines(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<Vec<data::Machine>, Box<dyn Error>> {
-    debug!(
-        "horizon.rs:get_machines: requesting machine list from {}{}",
-        cfg.horizon_api.url,
-        constants::REST_MACHINES
-    );
-
-    let (st, mach) = http::get(
-        cli,
-        &format!("{}{}", cfg.horizon_api.url, constants::REST_MACHINES),
-        token,
-    )?;
-    debug!("horizon.rs:get_machines: received HTTP status={}", st);
-
-    if st != reqwest::StatusCode::OK {
-        bail!(
-            "logout failed, received {} instead of 200 - {}",
-            st,
-            format_error_message(&mach)
-        );
+        let dplist: Vec<data::DesktopPool> = serde_json::from_str(dpl.as_str())?;
+        debug!("horizon.rs:desktop_pools: {} pools in list", dplist.len());
+
+        Ok(dplist)
     }
 
-    let mlist: Vec<data::Machine> = serde_json::from_str(mach.as_str())?;
-    debug!("horizon.rs:get_machines: {} machines in list", mlist.len());
+    pub fn machines(&mut self) -> Result<Vec<data::Machine>, Box<dyn Error>> {
+        let (st, mach) = self.get_resource(Resource::Machines, "")?;
+
+        if st != reqwest::StatusCode::OK {
+            bail!(
+                "machine list request failed, received {} instead of 200 - {}",
+                st,
+                format_error_message(&mach)
+            );
+        }
 
-    Ok(mlist)
+        let mlist: Vec<data::Machine> = serde_json::from_str(mach.as_str())?;
+        debug!("horizon.rs:machines: {} machines in list", mlist.len());
+
+        Ok(mlist)
+    }
 }
 
 fn format_error_message(e: &str) -> String {
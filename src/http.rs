@@ -1,19 +1,78 @@
 use crate::configuration;
 use crate::constants;
-use crate::exporter;
+use crate::recorder;
+use crate::retry::{CircuitBreaker, RetryPolicy};
+use crate::tls;
 
-use log::{debug, error, info};
+use log::debug;
 use simple_error::bail;
+use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
-use std::time::Duration;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-pub fn build_client(
-    insecure_ssl: bool,
-    ca_file: &str,
-    timeout_sec: u64,
-) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
+/// Settings applied to all requests to the Horizon REST API and the connections seen so far
+pub struct RequestContext {
+    pub(crate) retry: RetryPolicy,
+    pub(crate) breaker: CircuitBreaker,
+    pub(crate) pins: Vec<String>,
+    pub(crate) recorder: recorder::Mode,
+    idle_timeout: Duration,
+    // local address of the pooled connections and when they were used last
+    connections: HashMap<SocketAddr, Instant>,
+    new_connections: u64,
+}
+
+impl RequestContext {
+    pub fn new(cfg: &configuration::HorizonAPIConfig) -> Self {
+        RequestContext {
+            retry: RetryPolicy::new(cfg),
+            breaker: CircuitBreaker::new(cfg),
+            pins: cfg.pinned_fingerprints.clone(),
+            recorder: recorder::Mode::Off,
+            idle_timeout: Duration::from_secs(
+                cfg.pool_idle_timeout
+                    .unwrap_or(constants::DEFAULT_POOL_IDLE_TIMEOUT),
+            ),
+            connections: HashMap::new(),
+            new_connections: 0,
+        }
+    }
+
+    // A response from an unknown local address was received on a new connection. Connections
+    // idle for longer than the pool idle timeout are closed, so their port can be reused. This is
+    // only an estimate, reqwest doesn't report the connections it opens: a connection closed by
+    // the server and reopened from the same port within the idle timeout isn't counted.
+    fn observe(&mut self, response: &reqwest::blocking::Response) {
+        let local = match response
+            .extensions()
+            .get::<hyper::client::connect::HttpInfo>()
+        {
+            Some(v) => v.local_addr(),
+            None => return,
+        };
+
+        let now = Instant::now();
+        let idle_timeout = self.idle_timeout;
+        self.connections
+            .retain(|_, last| now.duration_since(*last) <= idle_timeout);
+        if self.connections.insert(local, now).is_none() {
+            debug!(
+                "http.rs:observe: new connection from {} to {}",
+                local,
+                response.url()
+            );
+            self.new_connections += 1;
+        }
+    }
+
+    /// Estimated number of new connections since the last call
+    pub fn take_new_connections(&mut self) -> u64 {
+        std::mem::take(&mut self.new_connections)
+    }
+}
+
+fn client_builder(timeout_sec: u64) -> reqwest::blocking::ClientBuilder {
     let timeout = Duration::from_secs(timeout_sec);
     let mut headers = reqwest::header::HeaderMap::new();
     headers.insert(
@@ -24,37 +83,31 @@ pub fn build_client(
         "Accept",
         reqwest::header::HeaderValue::from_static("application/json"),
     );
-    headers.insert(
-        "Content-Type",
-        reqwest::header::HeaderValue::from_static("application/json"),
-    );
 
-    let mut http_client_builder = reqwest::blocking::ClientBuilder::new()
+    reqwest::blocking::ClientBuilder::new()
         .user_agent(constants::generate_default_user_agent())
         .default_headers(headers)
-        .timeout(timeout);
-
-    if insecure_ssl {
-        http_client_builder = http_client_builder
-            .danger_accept_invalid_hostnames(true)
-            .danger_accept_invalid_certs(true);
-    } else if !ca_file.is_empty() {
-        let mut ca_buffer = Vec::new();
-        let mut fd = match File::open(ca_file) {
-            Ok(v) => v,
-            Err(e) => bail!("can't open CA file: {}", e),
-        };
-        if let Err(e) = fd.read_to_end(&mut ca_buffer) {
-            bail!("can't read CA data: {}", e);
-        }
+        .timeout(timeout)
+}
 
-        let ca_cert = match reqwest::Certificate::from_pem(&ca_buffer) {
-            Ok(v) => v,
-            Err(e) => bail!("can't decode CA data as PEM format: {}", e),
-        };
+/// HTTP client of an output, the configured headers are sent with every request
+pub fn build_client(
+    cfg: &configuration::HttpSinkConfig,
+) -> Result<reqwest::blocking::Client, Box<dyn Error>> {
+    let mut http_client_builder = client_builder(cfg.timeout.unwrap_or(constants::DEFAULT_TIMEOUT))
+        .use_preconfigured_tls(tls::sink_connector(cfg)?);
 
-        http_client_builder = http_client_builder.add_root_certificate(ca_cert);
+    if let Some(h) = &cfg.headers {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (k, v) in h.iter() {
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(k.as_bytes())?,
+                reqwest::header::HeaderValue::from_str(v)?,
+            );
+        }
+        http_client_builder = http_client_builder.default_headers(headers);
     }
+
     let http_client = match http_client_builder.build() {
         Ok(v) => v,
         Err(e) => bail!("can't create HTTP client: {}", e),
@@ -63,17 +116,140 @@ pub fn build_client(
     Ok(http_client)
 }
 
+/// Add basic authentication to a request if a user is configured
+pub fn basic_auth(
+    request: reqwest::blocking::RequestBuilder,
+    cfg: &configuration::BasicAuthConfig,
+) -> Result<reqwest::blocking::RequestBuilder, Box<dyn Error>> {
+    match &cfg.user {
+        Some(user) => Ok(request.basic_auth(user, cfg.get_password()?)),
+        None => Ok(request),
+    }
+}
+
+/// HTTP client for a Horizon connection server, returns the client and the base URL for requests
+///
+/// The base URL differs from `url` if `tls_server_name` is set.
+pub fn build_horizon_client(
+    cfg: &configuration::HorizonAPIConfig,
+    url: &str,
+) -> Result<(reqwest::blocking::Client, String), Box<dyn Error>> {
+    let timeout = cfg.timeout.unwrap_or(constants::DEFAULT_TIMEOUT);
+    let idle_timeout = cfg
+        .pool_idle_timeout
+        .unwrap_or(constants::DEFAULT_POOL_IDLE_TIMEOUT);
+    // the outputs set the content type of their payload per request
+    let mut headers = reqwest::header::HeaderMap::new();
+    headers.insert(
+        reqwest::header::CONTENT_TYPE,
+        reqwest::header::HeaderValue::from_static("application/json"),
+    );
+    let mut http_client_builder = client_builder(timeout)
+        .default_headers(headers)
+        .pool_idle_timeout(Duration::from_secs(idle_timeout))
+        .use_preconfigured_tls(tls::horizon_connector(cfg)?)
+        .tls_info(!cfg.pinned_fingerprints.is_empty());
+    http_client_builder = configure_proxy(http_client_builder, cfg.proxy.as_ref())?;
+
+    let mut base = url.to_string();
+    if let Some(server_name) = &cfg.tls_server_name {
+        let (b, addr) = tls::server_name_override(url, server_name)?;
+        if let Some(a) = addr {
+            http_client_builder = http_client_builder.resolve(server_name, a);
+        }
+        base = b;
+    }
+
+    let http_client = match http_client_builder.build() {
+        Ok(v) => v,
+        Err(e) => bail!("can't create HTTP client: {}", e),
+    };
+
+    Ok((http_client, base))
+}
+
+/// Verify the certificate of a server before credentials are sent to it
+pub fn verify_server(
+    http_client: &reqwest::blocking::Client,
+    url: &str,
+    ctx: &mut RequestContext,
+) -> Result<(), Box<dyn Error>> {
+    if ctx.pins.is_empty() || matches!(ctx.recorder, recorder::Mode::Replay(_)) {
+        return Ok(());
+    }
+
+    debug!("http.rs:verify_server: verifying certificate of {}", url);
+    let response = http_client.head(url).send()?;
+    ctx.observe(&response);
+    tls::verify_pin(&response, &ctx.pins)
+}
+
+// Without proxy configuration the proxy from the environment variables is used
+fn configure_proxy(
+    builder: reqwest::blocking::ClientBuilder,
+    proxy_cfg: Option<&configuration::ProxyConfig>,
+) -> Result<reqwest::blocking::ClientBuilder, Box<dyn Error>> {
+    let proxy_cfg = match proxy_cfg {
+        Some(v) => v,
+        None => return Ok(builder),
+    };
+
+    if proxy_cfg.disable.unwrap_or_default() {
+        debug!("http.rs:configure_proxy: proxy disabled");
+        return Ok(builder.no_proxy());
+    }
+
+    let url = match &proxy_cfg.url {
+        Some(v) => v,
+        None => bail!("no proxy URL configured"),
+    };
+    let mut proxy = match reqwest::Proxy::all(url) {
+        Ok(v) => v,
+        Err(e) => bail!("invalid proxy {}: {}", url, e),
+    };
+
+    if let Some(user) = &proxy_cfg.auth.user {
+        let password = proxy_cfg.auth.get_password()?.unwrap_or_default();
+        proxy = proxy.basic_auth(user, &password);
+    }
+
+    if let Some(v) = &proxy_cfg.no_proxy {
+        proxy = proxy.no_proxy(reqwest::NoProxy::from_string(&v.join(",")));
+    }
+
+    debug!("http.rs:configure_proxy: using proxy {}", url);
+    Ok(builder.proxy(proxy))
+}
+
 pub fn get(
     http_client: &mut reqwest::blocking::Client,
     url: &str,
     token: &str,
+    ctx: &mut RequestContext,
 ) -> Result<(reqwest::StatusCode, String), Box<dyn Error>> {
     debug!("http.rs:get: GET {}", &url);
 
-    let response = http_client.get(url).bearer_auth(token).send()?;
+    if let recorder::Mode::Replay(d) = &ctx.recorder {
+        return recorder::replay(d, "GET", url);
+    }
+
+    let start = Instant::now();
+    let response = ctx
+        .retry
+        .send(true, || http_client.get(url).bearer_auth(token).send())?;
+    ctx.observe(&response);
+    tls::verify_pin(&response, &ctx.pins)?;
 
     let status = response.status();
     let reply = response.text()?;
+    debug!(
+        http_status = status.as_u16(), duration_ms = start.elapsed().as_millis() as u64;
+        "http.rs:get: GET {} returned {}",
+        url, status
+    );
+    if let recorder::Mode::Record(d) = &ctx.recorder {
+        recorder::record(d, "GET", url, status, &reply);
+    }
     Ok((status, reply))
 }
 
@@ -82,85 +258,34 @@ pub fn post(
     url: &str,
     payload: &str,
     token: Option<&str>,
+    ctx: &mut RequestContext,
 ) -> Result<(reqwest::StatusCode, String), Box<dyn Error>> {
     debug!("http.rs:post: POST {}", &url);
 
-    let response = match token {
-        Some(t) => http_client
-            .post(url)
-            .bearer_auth(t)
-            .body(payload.to_string())
-            .send()?,
-        None => http_client.post(url).body(payload.to_string()).send()?,
-    };
-
-    let status = response.status();
-    let reply = response.text()?;
-    Ok((status, reply))
-}
+    if let recorder::Mode::Replay(d) = &ctx.recorder {
+        return recorder::replay(d, "POST", url);
+    }
 
-pub fn server(
-    cfg: configuration::Configuration,
-    listen_address: &str,
-) -> Result<(), Box<dyn Error>> {
-    let headers: Vec<tiny_http::Header> = vec![
-        tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"text/plain"[..]).unwrap(),
-        tiny_http::Header::from_bytes(&b"X-Clacks-Overhead"[..], &b"GNU Terry Pratchett"[..])
-            .unwrap(),
-    ];
-
-    let http_server = tiny_http::Server::http(listen_address).unwrap();
-
-    loop {
-        let request = match http_server.recv() {
-            Ok(v) => v,
-            Err(e) => {
-                error!("Can't process incoming request: {}", e);
-                continue;
-            }
-        };
-        let method = request.method();
-        let url = request.url();
-
-        info!(
-            "HTTP {} request to {} from {:?}",
-            method,
-            url,
-            request.remote_addr()
-        );
-
-        let status_code: tiny_http::StatusCode;
-        let payload: String;
-
-        if method == &tiny_http::Method::Get {
-            match url {
-                "/" => {
-                    status_code = tiny_http::StatusCode::from(302_i16);
-                    payload = constants::ROOT_HTML.to_string();
-                }
-                constants::METRICS_PATH => {
-                    let reply = exporter::fetch(&cfg);
-                    status_code = tiny_http::StatusCode::from(200_i16);
-                    payload = reply;
-                }
-                _ => {
-                    status_code = tiny_http::StatusCode::from(404_i16);
-                    payload = constants::REPLY_NOT_FOUND.to_string();
-                }
-            };
-        } else {
-            status_code = tiny_http::StatusCode::from(405_i16);
-            payload = constants::REPLY_METHOD_NOT_ALLOWED.to_string();
+    let start = Instant::now();
+    let response = ctx.retry.send(false, || {
+        let request = http_client.post(url).body(payload.to_string());
+        match token {
+            Some(t) => request.bearer_auth(t).send(),
+            None => request.send(),
         }
+    })?;
+    ctx.observe(&response);
+    tls::verify_pin(&response, &ctx.pins)?;
 
-        if let Err(e) = request.respond(tiny_http::Response::new(
-            status_code,
-            headers.clone(),
-            payload.as_bytes(),
-            Some(payload.len()),
-            None,
-        )) {
-            error!("Can't send response to client: {}", e);
-        }
+    let status = response.status();
+    let reply = response.text()?;
+    debug!(
+        http_status = status.as_u16(), duration_ms = start.elapsed().as_millis() as u64;
+        "http.rs:post: POST {} returned {}",
+        url, status
+    );
+    if let recorder::Mode::Record(d) = &ctx.recorder {
+        recorder::record(d, "POST", url, status, &reply);
     }
+    Ok((status, reply))
 }
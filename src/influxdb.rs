@@ -0,0 +1,155 @@
+use crate::configuration;
+use crate::constants;
+use crate::http;
+use crate::measurement::Measurement;
+use crate::sink::{Collected, Sink};
+
+use log::debug;
+use simple_error::bail;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+fn escape_measurement(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+}
+
+fn escape_tag(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+// Every count becomes a measurement with the pool and the counted field as tags and the count as
+// field "value"
+fn encode(
+    cfg: &configuration::InfluxDBConfig,
+    measurements: &[Measurement],
+    timestamp: i64,
+) -> String {
+    let mut result = String::new();
+
+    for m in measurements {
+        let mut tags: BTreeMap<String, String> = BTreeMap::new();
+        if let Some(t) = &cfg.tags {
+            for (k, v) in t.iter() {
+                tags.insert(k.clone(), v.clone());
+            }
+        }
+        tags.insert("pool".to_string(), m.pool.clone());
+        tags.insert(m.field.to_string(), m.value.clone());
+
+        result.push_str(&escape_measurement(m.name));
+        for (k, v) in tags.iter() {
+            // empty tag values are not allowed by the line protocol
+            if v.is_empty() {
+                continue;
+            }
+            result.push_str(&format!(",{}={}", escape_tag(k), escape_tag(v)));
+        }
+        result.push_str(&format!(" value={} {}\n", m.count, timestamp));
+    }
+
+    result
+}
+
+fn write_url(cfg: &configuration::InfluxDBConfig) -> String {
+    let base = cfg.url.trim_end_matches('/');
+    let mut url;
+
+    if cfg
+        .api_version
+        .unwrap_or(constants::DEFAULT_INFLUXDB_API_VERSION)
+        == 2
+    {
+        url = format!(
+            "{}/api/v2/write?org={}&bucket={}&precision=s",
+            base,
+            urlencoding::encode(cfg.org.as_deref().unwrap_or_default()),
+            urlencoding::encode(cfg.bucket.as_deref().unwrap_or_default())
+        );
+    } else {
+        url = format!(
+            "{}/write?db={}&precision=s",
+            base,
+            urlencoding::encode(cfg.database.as_deref().unwrap_or_default())
+        );
+        if let Some(rp) = &cfg.retention_policy {
+            url.push_str(&format!("&rp={}", urlencoding::encode(rp)));
+        }
+    }
+
+    url
+}
+
+pub struct InfluxDB {
+    cfg: configuration::InfluxDBConfig,
+    client: reqwest::blocking::Client,
+    interval: u64,
+}
+
+impl InfluxDB {
+    pub fn new(
+        cfg: &configuration::InfluxDBConfig,
+        default_interval: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(InfluxDB {
+            cfg: cfg.clone(),
+            client: http::build_client(&cfg.http)?,
+            interval: cfg.interval.unwrap_or(default_interval),
+        })
+    }
+}
+
+impl Sink for InfluxDB {
+    fn name(&self) -> &'static str {
+        constants::OUTPUT_INFLUXDB
+    }
+
+    fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>> {
+        write(&self.cfg, &self.client, &data.measurements)?;
+        Ok(true)
+    }
+}
+
+fn write(
+    cfg: &configuration::InfluxDBConfig,
+    client: &reqwest::blocking::Client,
+    measurements: &[Measurement],
+) -> Result<(), Box<dyn Error>> {
+    let payload = encode(cfg, measurements, chrono::Local::now().timestamp());
+    let url = write_url(cfg);
+
+    debug!("influxdb.rs:write: POST {}", url);
+
+    let mut request = client
+        .post(&url)
+        .header(reqwest::header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(payload);
+    if let Some(token) = cfg.get_token()? {
+        request = request.header(reqwest::header::AUTHORIZATION, format!("Token {}", token));
+    } else {
+        request = http::basic_auth(request, &cfg.auth)?;
+    }
+
+    let response = request.send()?;
+    let status = response.status();
+    debug!("influxdb.rs:write: received HTTP status={}", status);
+
+    if !status.is_success() {
+        bail!(
+            "write to {} failed, received {}: {}",
+            cfg.url,
+            status,
+            response.text().unwrap_or_default().trim()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use crate::configuration;
+use crate::constants;
+use crate::exporter;
+use crate::status_page;
+
+use serde_json::json;
+use std::collections::HashMap;
+
+// Filters from the query string, multiple values of the same parameter are combined by "or"
+#[derive(Debug, Default)]
+struct Filter {
+    pools: Vec<String>,
+    states: Vec<String>,
+}
+
+fn parse_query(query: &str) -> Result<Filter, String> {
+    let mut filter = Filter::default();
+
+    for param in query.split('&').filter(|p| !p.is_empty()) {
+        let (key, value) = param.split_once('=').unwrap_or((param, ""));
+        let value = match urlencoding::decode(&value.replace('+', " ")) {
+            Ok(v) => v.to_string(),
+            Err(e) => return Err(format!("invalid value for {}: {}", key, e)),
+        };
+        match key {
+            "pool" => filter.pools.push(value),
+            "state" => filter.states.push(value.to_lowercase()),
+            _ => return Err(format!("unsupported query parameter {}", key)),
+        };
+    }
+
+    Ok(filter)
+}
+
+// A pool can be selected by its UUID, its name or the name from pool_uuid_map
+fn pool_matches(
+    cfg: &configuration::Configuration,
+    filter: &Filter,
+    pool_names: &HashMap<String, String>,
+    pool_id: &str,
+) -> bool {
+    if filter.pools.is_empty() {
+        return true;
+    }
+
+    let mapped = cfg
+        .horizon_api
+        .pool_uuid_map
+        .as_ref()
+        .and_then(|m| m.get(pool_id));
+    let name = pool_names.get(pool_id);
+
+    filter
+        .pools
+        .iter()
+        .any(|p| p == pool_id || Some(p) == mapped || Some(p) == name)
+}
+
+fn state_matches(filter: &Filter, state: &str) -> bool {
+    filter.states.is_empty() || filter.states.contains(&state.to_lowercase())
+}
+
+fn pool_names() -> HashMap<String, String> {
+    exporter::COLLECTOR
+        .desktop_pools()
+        .iter()
+        .filter_map(|p| p.name.as_ref().map(|n| (p.id.clone(), n.clone())))
+        .collect()
+}
+
+fn last_refresh() -> Option<String> {
+    status_page::format_timestamp(exporter::COLLECTOR.status().last_refresh)
+}
+
+fn render(value: serde_json::Value) -> String {
+    match serde_json::to_string_pretty(&value) {
+        Ok(v) => v,
+        Err(e) => format!("{{\"error\": \"{}\"}}", e),
+    }
+}
+
+// Returns the HTTP status code and the JSON payload, data is taken from the last collection and
+// no requests are sent to Horizon
+pub fn handle(cfg: &configuration::Configuration, path: &str, query: &str) -> (u16, String) {
+    let filter = match parse_query(query) {
+        Ok(v) => v,
+        Err(e) => return (400, render(json!({ "error": e }))),
+    };
+    let names = pool_names();
+
+    match path {
+        constants::API_POOLS_PATH => {
+            let pools: Vec<_> = exporter::COLLECTOR
+                .desktop_pools()
+                .iter()
+                .filter(|p| cfg.horizon_api.exports_pool(&p.id))
+                .filter(|p| pool_matches(cfg, &filter, &names, &p.id))
+                .filter(|p| state_matches(&filter, if p.enabled { "enabled" } else { "disabled" }))
+                .cloned()
+                .collect();
+            (
+                200,
+                render(json!({ "last_refresh": last_refresh(), "pools": pools })),
+            )
+        }
+        constants::API_MACHINES_PATH => {
+            let machines: Vec<_> = exporter::COLLECTOR
+                .machines()
+                .iter()
+                .filter(|m| cfg.horizon_api.exports_pool(&m.desktop_pool_id))
+                .filter(|m| pool_matches(cfg, &filter, &names, &m.desktop_pool_id))
+                .filter(|m| {
+                    state_matches(
+                        &filter,
+                        m.effective_state.as_ref().unwrap_or(&m.state).as_str(),
+                    )
+                })
+                .cloned()
+                .collect();
+            (
+                200,
+                render(json!({ "last_refresh": last_refresh(), "machines": machines })),
+            )
+        }
+        constants::API_SESSIONS_PATH => {
+            let sessions: Vec<_> = exporter::COLLECTOR
+                .sessions()
+                .iter()
+                .filter(|s| match &s.desktop_pool_id {
+                    Some(p) => {
+                        cfg.horizon_api.exports_pool(p) && pool_matches(cfg, &filter, &names, p)
+                    }
+                    None => filter.pools.is_empty(),
+                })
+                .filter(|s| state_matches(&filter, s.session_state.as_str()))
+                .cloned()
+                .collect();
+            (
+                200,
+                render(json!({ "last_refresh": last_refresh(), "sessions": sessions })),
+            )
+        }
+        _ => (404, render(json!({ "error": constants::REPLY_NOT_FOUND }))),
+    }
+}
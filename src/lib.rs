@@ -0,0 +1,37 @@
+//! Client for the VMWare Horizon REST API and collector of the Horizon metrics
+//!
+//! ```no_run
+//! use vmware_horizon_exporter::collector::Collector;
+//! use vmware_horizon_exporter::configuration;
+//!
+//! let registry = prometheus::Registry::new();
+//! let collector = Collector::new(&registry).unwrap();
+//! let cfg = configuration::parse_config_file(Some("config.yaml")).unwrap();
+//!
+//! if collector.collect(&cfg) {
+//!     println!("{} metric families", registry.gather().len());
+//! }
+//! ```
+
+pub mod api;
+pub mod collector;
+pub mod configuration;
+pub mod constants;
+pub mod data;
+pub mod file;
+pub mod horizon;
+pub mod http;
+
+pub(crate) mod failover;
+pub(crate) mod recorder;
+pub(crate) mod retry;
+pub(crate) mod status;
+pub(crate) mod tls;
+
+mod machines;
+mod sessions;
+
+pub use collector::Collector;
+pub use horizon::HorizonClient;
+pub use recorder::Mode as RecorderMode;
+pub use status::{CollectorStatus, Status};
@@ -0,0 +1,372 @@
+use crate::constants;
+
+use log::kv::{Key, Value, VisitSource};
+use simple_error::bail;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SYSLOG_SOCKET: &str = "/dev/log";
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+// LOG_DAEMON
+const SYSLOG_FACILITY: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Stdout,
+    Stderr,
+    Syslog,
+    Journald,
+    File(PathBuf),
+}
+
+pub type ModuleLevel = (String, log::LevelFilter);
+
+pub struct Options {
+    pub format: Format,
+    pub target: Target,
+    pub level: log::LevelFilter,
+    // log levels of single modules, e.g. http=debug
+    pub modules: Vec<ModuleLevel>,
+    pub max_size: u64,
+    pub max_files: usize,
+}
+
+pub fn parse_format(v: &str) -> Result<Format, Box<dyn Error>> {
+    match v {
+        constants::LOG_FORMAT_TEXT => Ok(Format::Text),
+        constants::LOG_FORMAT_JSON => Ok(Format::Json),
+        _ => bail!(
+            "unknown log format {}, supported formats are text and json",
+            v
+        ),
+    }
+}
+
+pub fn parse_target(v: &str) -> Result<Target, Box<dyn Error>> {
+    if let Some(p) = v.strip_prefix("file:") {
+        if p.is_empty() {
+            bail!("missing path of the log file");
+        }
+        return Ok(Target::File(PathBuf::from(p)));
+    }
+    match v {
+        "stdout" => Ok(Target::Stdout),
+        "stderr" => Ok(Target::Stderr),
+        "syslog" => Ok(Target::Syslog),
+        "journald" => Ok(Target::Journald),
+        _ => bail!(
+            "unknown log target {}, supported targets are stdout, stderr, syslog, journald and file:<path>",
+            v
+        ),
+    }
+}
+
+/// Parse a comma separated list of `<module>=<level>`, a level without module sets the
+/// default level
+pub fn parse_levels(
+    v: &str,
+    default: log::LevelFilter,
+) -> Result<(log::LevelFilter, Vec<ModuleLevel>), Box<dyn Error>> {
+    let mut level = default;
+    let mut modules = Vec::new();
+
+    for entry in v.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+        let (module, l) = match entry.split_once('=') {
+            Some((m, l)) => (Some(m.trim()), l.trim()),
+            None => (None, entry),
+        };
+        let parsed: log::LevelFilter = match l.parse() {
+            Ok(v) => v,
+            Err(_) => bail!("invalid log level {} in {}", l, entry),
+        };
+        match module {
+            Some(m) if !m.is_empty() => modules.push((module_target(m), parsed)),
+            Some(_) => bail!("missing module name in {}", entry),
+            None => level = parsed,
+        }
+    }
+
+    Ok((level, modules))
+}
+
+// Modules of the exporter can be given without the crate name, e.g. http instead of
+// vmware_horizon_exporter::http
+fn module_target(m: &str) -> String {
+    if m.contains("::") || m == constants::CRATE_NAME {
+        m.to_string()
+    } else {
+        format!("{}::{}", constants::CRATE_NAME, m)
+    }
+}
+
+// Module name without the crate name of the exporter
+fn short_module(record: &log::Record) -> String {
+    let module = record.module_path().unwrap_or_else(|| record.target());
+    module
+        .strip_prefix(constants::CRATE_NAME)
+        .and_then(|m| m.strip_prefix("::"))
+        .unwrap_or(module)
+        .to_string()
+}
+
+// Collects the structured fields of a record, e.g. collector, pool, http_status or duration_ms
+struct Fields(Vec<(String, serde_json::Value)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        let v = if let Some(n) = value.to_u64() {
+            serde_json::Value::from(n)
+        } else if let Some(n) = value.to_i64() {
+            serde_json::Value::from(n)
+        } else if let Some(b) = value.to_bool() {
+            serde_json::Value::from(b)
+        } else {
+            serde_json::Value::from(value.to_string())
+        };
+        self.0.push((key.to_string(), v));
+        Ok(())
+    }
+}
+
+fn fields(record: &log::Record) -> Vec<(String, serde_json::Value)> {
+    let mut f = Fields(Vec::new());
+    let _ = record.key_values().visit(&mut f);
+    f.0
+}
+
+fn format_json(message: &std::fmt::Arguments, record: &log::Record) -> String {
+    let mut line = serde_json::Map::new();
+    line.insert(
+        "timestamp".to_string(),
+        chrono::Local::now()
+            .format("%Y-%m-%dT%H:%M:%S%.3f%z")
+            .to_string()
+            .into(),
+    );
+    line.insert("level".to_string(), record.level().as_str().into());
+    line.insert("module".to_string(), short_module(record).into());
+    line.insert("message".to_string(), message.to_string().into());
+    for (k, v) in fields(record) {
+        line.insert(k, v);
+    }
+    serde_json::Value::Object(line).to_string()
+}
+
+/// Log file rotated by size, the rotated files are renamed to `<path>.1`, `<path>.2`, ...
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+    // the file is only rotated at the start of a line
+    line_start: bool,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+            line_start: true,
+        })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut p = self.path.clone().into_os_string();
+        p.push(format!(".{}", n));
+        PathBuf::from(p)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            self.file.set_len(0)?;
+        } else {
+            for n in (1..self.max_files).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+            self.file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+        }
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.line_start
+            && self.max_size > 0
+            && self.size > 0
+            && self.size + buf.len() as u64 > self.max_size
+        {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        if n > 0 {
+            self.line_start = buf[n - 1] == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn syslog_severity(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+// Send a message to the local syslog daemon
+fn send_syslog(socket: &UnixDatagram, format: Format, record: &log::Record) {
+    let message = match format {
+        Format::Text => record.args().to_string(),
+        Format::Json => format_json(record.args(), record),
+    };
+    let line = format!(
+        "<{}>{}[{}]: {}",
+        SYSLOG_FACILITY * 8 + syslog_severity(record.level()),
+        constants::NAME,
+        std::process::id(),
+        message
+    );
+    let _ = socket.send_to(line.as_bytes(), SYSLOG_SOCKET);
+}
+
+// Fields of the journal must consist of upper case letters, digits and underscores
+fn journal_field(key: &str) -> String {
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_start_matches('_')
+        .to_string()
+}
+
+fn append_journal_field(buf: &mut Vec<u8>, key: &str, value: &str) {
+    buf.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        // values containing newlines are sent with their length
+        buf.push(b'\n');
+        buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        buf.extend_from_slice(value.as_bytes());
+    } else {
+        buf.push(b'=');
+        buf.extend_from_slice(value.as_bytes());
+    }
+    buf.push(b'\n');
+}
+
+// Send a message with its structured fields by the native journald protocol
+fn send_journald(socket: &UnixDatagram, record: &log::Record) {
+    let mut buf = Vec::new();
+    append_journal_field(&mut buf, "MESSAGE", &record.args().to_string());
+    append_journal_field(
+        &mut buf,
+        "PRIORITY",
+        &syslog_severity(record.level()).to_string(),
+    );
+    append_journal_field(&mut buf, "SYSLOG_IDENTIFIER", constants::NAME);
+    append_journal_field(&mut buf, "MODULE", &short_module(record));
+    for (k, v) in fields(record) {
+        let value = match v {
+            serde_json::Value::String(s) => s,
+            other => other.to_string(),
+        };
+        append_journal_field(&mut buf, &journal_field(&k), &value);
+    }
+    let _ = socket.send_to(&buf, JOURNALD_SOCKET);
+}
+
+pub fn init(opts: &Options) -> Result<(), Box<dyn Error>> {
+    let mut dispatch = fern::Dispatch::new().level(opts.level);
+    for (m, l) in opts.modules.iter() {
+        dispatch = dispatch.level_for(m.clone(), *l);
+    }
+
+    let output: fern::Output = match &opts.target {
+        Target::Syslog | Target::Journald => {
+            let socket = UnixDatagram::unbound()?;
+            let format = opts.format;
+            let journald = opts.target == Target::Journald;
+            let socket = Mutex::new(socket);
+            fern::Output::call(move |record| {
+                let socket = socket.lock().unwrap();
+                if journald {
+                    send_journald(&socket, record);
+                } else {
+                    send_syslog(&socket, format, record);
+                }
+            })
+        }
+        target => {
+            let writer: fern::Output = match target {
+                Target::Stdout => io::stdout().into(),
+                Target::Stderr => io::stderr().into(),
+                Target::File(p) => {
+                    let file = match RotatingFile::open(p, opts.max_size, opts.max_files) {
+                        Ok(v) => v,
+                        Err(e) => bail!("can't open log file {}: {}", p.display(), e),
+                    };
+                    fern::Output::writer(Box::new(file), "\n")
+                }
+                Target::Syslog | Target::Journald => {
+                    bail!("log target {:?} can't be used as writer", target)
+                }
+            };
+
+            let format = opts.format;
+            fern::Dispatch::new()
+                .format(move |logout, logmsg, logrecord| match format {
+                    Format::Text => logout.finish(format_args!(
+                        "{:<6}: {} {}",
+                        logrecord.level(),
+                        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%z"),
+                        logmsg
+                    )),
+                    Format::Json => {
+                        logout.finish(format_args!("{}", format_json(logmsg, logrecord)))
+                    }
+                })
+                .chain(writer)
+                .into()
+        }
+    };
+
+    dispatch.chain(output).apply()?;
+    Ok(())
+}
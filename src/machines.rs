@@ -1,15 +1,12 @@
+use crate::collector::{self, Metrics};
 use crate::configuration;
 use crate::constants;
 use crate::data;
-use crate::exporter;
-use crate::globals;
-use crate::horizon;
+use crate::horizon::HorizonClient;
 
-use lazy_static::lazy_static;
-use log::{debug, warn};
+use log::debug;
 use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Mutex;
 
 // Map poolid -> machine state, count
 type MachineStateMap = HashMap<String, HashMap<String, i64>>;
@@ -20,6 +17,13 @@ type MachineOSMap = HashMap<String, HashMap<String, i64>>;
 // Map poolid -> arch, count
 type MachineArchMap = HashMap<String, HashMap<String, i64>>;
 
+#[derive(Default)]
+pub struct MachineCounters {
+    mstates: MachineStateMap,
+    os_map: MachineOSMap,
+    arch_map: MachineArchMap,
+}
+
 fn flush_machine_arch_map(m: &mut MachineOSMap) {
     for (k1, v1) in m.iter_mut() {
         for (k2, v2) in v1.iter_mut() {
@@ -62,12 +66,10 @@ fn initialise_machine_arch_map(m: &mut MachineOSMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    pm.insert(constants::LC_ARCH_BIT_64.to_string(), 0);
-    pm.insert(constants::LC_ARCH_BIT_32.to_string(), 0);
-    pm.insert(constants::LC_ARCH_UNKNOWN.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::Architecture::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
 fn initialise_machine_os_map(m: &mut MachineOSMap, p: &str) {
@@ -76,29 +78,10 @@ fn initialise_machine_os_map(m: &mut MachineOSMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-
-    pm.insert(constants::LC_OS_LINUX_CENTOS.to_string(), 0);
-    pm.insert(constants::LC_OS_LINUX_OTHER.to_string(), 0);
-    pm.insert(constants::LC_OS_LINUX_RHEL.to_string(), 0);
-    pm.insert(constants::LC_OS_LINUX_SERVER_OTHER.to_string(), 0);
-    pm.insert(constants::LC_OS_LINUX_SUSE.to_string(), 0);
-    pm.insert(constants::LC_OS_LINUX_UBUNTU.to_string(), 0);
-    pm.insert(constants::LC_OS_UNKNOWN.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_10.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_11.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_7.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_8.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2003.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2008.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2008_R2.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2012.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2012_R2.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_SERVER_2016_OR_ABOVE.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_VISTA.to_string(), 0);
-    pm.insert(constants::LC_OS_WINDOWS_XP.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::OperatingSystem::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
 fn initialise_machine_state_map(m: &mut MachineStateMap, p: &str) {
@@ -107,77 +90,30 @@ fn initialise_machine_state_map(m: &mut MachineStateMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    pm.insert(constants::LC_MSTATE_AGENT_CONFIG_ERROR.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_AGENT_DRAIN_MODE.to_string(), 0);
-    pm.insert(
-        constants::LC_MSTATE_AGENT_DRAIN_UNTIL_RESTART.to_string(),
-        0,
-    );
-    pm.insert(constants::LC_MSTATE_AGENT_ERROR_DISABLED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_AGENT_ERROR_INVALID_IP.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_AGENT_ERROR_NEEDS_REBOOT.to_string(), 0);
-    pm.insert(
-        constants::LC_MSTATE_AGENT_ERROR_PROTOCOL_FAILURE.to_string(),
-        0,
-    );
-    pm.insert(
-        constants::LC_MSTATE_AGENT_ERROR_STARTUP_IN_PROGRESS.to_string(),
-        0,
-    );
-    pm.insert(
-        constants::LC_MSTATE_AGENT_ERROR_DOMAIN_FAILURE.to_string(),
-        0,
-    );
-    pm.insert(constants::LC_MSTATE_AGENT_UNREACHABLE.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_ALREADY_USED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_AVAILABLE.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_CONNECTED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_CUSTOMIZING.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_DELETING.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_DISABLED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_DISABLE_IN_PROGRESS.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_DISCONNECTED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_ERROR.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_IN_PROGRESS.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_MAINTENANCE.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_PROVISIONED.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_PROVISIONING.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_PROVISIONING_ERROR.to_string(), 0);
-    pm.insert(
-        constants::LC_MSTATE_UNASSIGNED_USER_CONNECTED.to_string(),
-        0,
-    );
-    pm.insert(
-        constants::LC_MSTATE_UNASSIGNED_USER_DISCONNECTED.to_string(),
-        0,
-    );
-    pm.insert(constants::LC_MSTATE_UNKNOWN.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_VALIDATING.to_string(), 0);
-    pm.insert(constants::LC_MSTATE_WAITING_FOR_AGENT.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::MachineState::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
+// Returns the machines of the exported pools
 pub fn machine_metric_update(
     cfg: &configuration::Configuration,
-    client: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<(), Box<dyn Error>> {
-    lazy_static! {
-        static ref MSTATES: Mutex<MachineStateMap> = Mutex::new(HashMap::new());
-        static ref OS: Mutex<MachineOSMap> = Mutex::new(HashMap::new());
-        static ref ARCH: Mutex<MachineArchMap> = Mutex::new(HashMap::new());
-    }
-    let mut mstates = MSTATES.lock().unwrap();
-    let mut os_map = OS.lock().unwrap();
-    let mut arch_map = ARCH.lock().unwrap();
+    client: &mut HorizonClient,
+    metrics: &Metrics,
+    counters: &mut MachineCounters,
+    dsktp_pools: &[data::DesktopPool],
+) -> Result<Vec<data::Machine>, Box<dyn Error>> {
+    let MachineCounters {
+        mstates,
+        os_map,
+        arch_map,
+    } = counters;
+
+    flush_machine_state_map(mstates);
+    flush_machine_os_map(os_map);
+    flush_machine_arch_map(arch_map);
 
-    flush_machine_state_map(&mut mstates);
-    flush_machine_os_map(&mut os_map);
-    flush_machine_arch_map(&mut arch_map);
-
-    let dsktp_pools = globals::DESKTOP_POOLS.lock().unwrap().clone();
     for dp in dsktp_pools {
         if cfg.horizon_api.skip_pools_set.contains(&dp.id) {
             continue;
@@ -188,14 +124,15 @@ pub fn machine_metric_update(
             continue;
         }
         if !mstates.contains_key(&dp.id) {
-            initialise_machine_state_map(&mut mstates, &dp.id);
-            initialise_machine_os_map(&mut os_map, &dp.id);
-            initialise_machine_arch_map(&mut arch_map, &dp.id);
+            initialise_machine_state_map(mstates, &dp.id);
+            initialise_machine_os_map(os_map, &dp.id);
+            initialise_machine_arch_map(arch_map, &dp.id);
         }
     }
 
     debug!("machines.rs:machine_metric_update: getting list of current machines");
-    let machines = horizon::get_machines(cfg, client, token)?;
+    let machines = client.machines()?;
+    let mut snapshot: Vec<data::Machine> = Vec::new();
     for m in machines.iter() {
         debug!(
             "machines.rs:session_metric_update: processing machine data - {:?}",
@@ -203,6 +140,7 @@ pub fn machine_metric_update(
         );
         if cfg.horizon_api.skip_pools_set.contains(&m.desktop_pool_id) {
             debug!(
+                pool = m.desktop_pool_id.as_str();
                 "machines.rs:machine_metric_update: desktop pool id {} is in skip_pools list",
                 m.desktop_pool_id
             );
@@ -212,189 +150,135 @@ pub fn machine_metric_update(
         if !cfg.horizon_api.only_pools_set.is_empty()
             && !cfg.horizon_api.only_pools_set.contains(&m.desktop_pool_id)
         {
-            debug!("machines.rs:machine_metric_update: only_pools list is not empty and desktop pool id {} is not in only_pools list", m.desktop_pool_id);
+            debug!(pool = m.desktop_pool_id.as_str(); "machines.rs:machine_metric_update: only_pools list is not empty and desktop pool id {} is not in only_pools list", m.desktop_pool_id);
             continue;
         }
 
-        set_machine_state_metrics(cfg, client, token, &mut mstates, m)?;
-        set_machine_os_metrics(&mut os_map, m);
-        set_machine_arch_metrics(&mut arch_map, m);
+        let state = set_machine_state_metrics(client, metrics, mstates, m)?;
+        set_machine_os_metrics(metrics, os_map, m);
+        set_machine_arch_metrics(metrics, arch_map, m);
+        snapshot.push(data::Machine {
+            effective_state: Some(state),
+            ..m.clone()
+        });
     }
 
-    prometheus_machine_states(&mstates, &cfg.horizon_api);
-    prometheus_machine_os(&os_map, &cfg.horizon_api);
-    prometheus_machine_arch(&arch_map, &cfg.horizon_api);
-    Ok(())
+    prometheus_machine_states(metrics, mstates, &cfg.horizon_api);
+    prometheus_machine_os(metrics, os_map, &cfg.horizon_api);
+    prometheus_machine_arch(metrics, arch_map, &cfg.horizon_api);
+
+    Ok(snapshot)
 }
 
-fn prometheus_machine_arch(amap: &MachineArchMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_machine_arch(
+    metrics: &Metrics,
+    amap: &MachineArchMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, archname) in amap.iter() {
         for (arch, count) in archname.iter() {
-            exporter::MACHINE_ARCH
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), arch])
+            let pool = cfg.user_defined_pool_uuid_resolve(pool);
+            metrics
+                .machine_arch
+                .with_label_values(&[&pool, arch])
                 .set(*count);
+            collector::set_info(&metrics.machine_arch_info, &[&pool, arch], *count);
         }
     }
 }
 
-fn prometheus_machine_os(omap: &MachineOSMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_machine_os(
+    metrics: &Metrics,
+    omap: &MachineOSMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, osname) in omap.iter() {
         for (os, count) in osname.iter() {
-            exporter::MACHINE_OS
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), os])
+            let pool = cfg.user_defined_pool_uuid_resolve(pool);
+            metrics
+                .machine_os
+                .with_label_values(&[&pool, os])
                 .set(*count);
+            collector::set_info(&metrics.machine_os_info, &[&pool, os], *count);
         }
     }
 }
 
-fn prometheus_machine_states(mmap: &MachineStateMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_machine_states(
+    metrics: &Metrics,
+    mmap: &MachineStateMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, mstate) in mmap.iter() {
         for (state, count) in mstate.iter() {
-            exporter::MACHINE_STATES
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), state])
+            metrics
+                .machine_states
+                .with_label_values(&[&cfg.user_defined_pool_uuid_resolve(pool), state])
                 .set(*count);
         }
     }
 }
 
-fn set_machine_arch_metrics(amap: &mut MachineArchMap, m: &data::Machine) {
+fn set_machine_arch_metrics(metrics: &Metrics, amap: &mut MachineArchMap, m: &data::Machine) {
     if let Some(arch) = &m.operating_system_architecture {
-        match arch.as_str() {
-            constants::ARCH_BIT_64 | constants::ARCH_BIT_32 | constants::ARCH_UNKNOWN => {}
-            _ => {
-                warn!(
-                    "skipping unknown architecture {} for machine id {}",
-                    arch, m.id
-                );
-            }
-        };
-        let am = amap
-            .entry(m.desktop_pool_id.to_string())
-            .or_insert_with(HashMap::new);
-        let lc_arch = arch.to_lowercase();
-        *am.entry(lc_arch).or_insert(0) += 1;
+        if arch.is_other() {
+            metrics.unknown_enum_value(
+                constants::FIELD_OPERATING_SYSTEM_ARCHITECTURE,
+                arch.as_str(),
+                &m.id,
+            );
+        }
+        let am = amap.entry(m.desktop_pool_id.to_string()).or_default();
+        *am.entry(arch.label()).or_insert(0) += 1;
     }
 }
 
-fn set_machine_os_metrics(omap: &mut MachineOSMap, m: &data::Machine) {
+fn set_machine_os_metrics(metrics: &Metrics, omap: &mut MachineOSMap, m: &data::Machine) {
     if let Some(os) = &m.operating_system {
-        match os.as_str() {
-            constants::OS_LINUX_CENTOS
-            | constants::OS_LINUX_OTHER
-            | constants::OS_LINUX_RHEL
-            | constants::OS_LINUX_SERVER_OTHER
-            | constants::OS_LINUX_SUSE
-            | constants::OS_LINUX_UBUNTU
-            | constants::OS_UNKNOWN
-            | constants::OS_WINDOWS_10
-            | constants::OS_WINDOWS_11
-            | constants::OS_WINDOWS_7
-            | constants::OS_WINDOWS_8
-            | constants::OS_WINDOWS_SERVER_2003
-            | constants::OS_WINDOWS_SERVER_2008
-            | constants::OS_WINDOWS_SERVER_2008_R2
-            | constants::OS_WINDOWS_SERVER_2012
-            | constants::OS_WINDOWS_SERVER_2012_R2
-            | constants::OS_WINDOWS_SERVER_2016_OR_ABOVE
-            | constants::OS_WINDOWS_VISTA
-            | constants::OS_WINDOWS_XP => {}
-            _ => {
-                warn!(
-                    "skipping unknown operating system {} for machine id {}",
-                    os, m.id
-                );
-            }
-        };
-
-        let om = omap
-            .entry(m.desktop_pool_id.to_string())
-            .or_insert_with(HashMap::new);
-        let lc_os = os.to_lowercase();
-        *om.entry(lc_os).or_insert(0) += 1;
+        if os.is_other() {
+            metrics.unknown_enum_value(constants::FIELD_OPERATING_SYSTEM, os.as_str(), &m.id);
+        }
+        let om = omap.entry(m.desktop_pool_id.to_string()).or_default();
+        *om.entry(os.label()).or_insert(0) += 1;
     }
 }
 
 fn set_machine_state_metrics(
-    cfg: &configuration::Configuration,
-    cli: &mut reqwest::blocking::Client,
-    token: &str,
+    client: &mut HorizonClient,
+    metrics: &Metrics,
     mmap: &mut MachineStateMap,
     m: &data::Machine,
-) -> Result<(), Box<dyn Error>> {
-    match m.state.as_str() {
-        constants::MSTATE_AGENT_CONFIG_ERROR
-        | constants::MSTATE_AGENT_DRAIN_MODE
-        | constants::MSTATE_AGENT_DRAIN_UNTIL_RESTART
-        | constants::MSTATE_AGENT_ERROR_DISABLED
-        | constants::MSTATE_AGENT_ERROR_INVALID_IP
-        | constants::MSTATE_AGENT_ERROR_NEEDS_REBOOT
-        | constants::MSTATE_AGENT_ERROR_PROTOCOL_FAILURE
-        | constants::MSTATE_AGENT_ERROR_STARTUP_IN_PROGRESS
-        | constants::MSTATE_AGENT_ERROR_DOMAIN_FAILURE
-        | constants::MSTATE_AGENT_UNREACHABLE
-        | constants::MSTATE_ALREADY_USED
-        | constants::MSTATE_AVAILABLE
-        | constants::MSTATE_CONNECTED
-        | constants::MSTATE_CUSTOMIZING
-        | constants::MSTATE_DELETING
-        | constants::MSTATE_DISABLED
-        | constants::MSTATE_DISABLE_IN_PROGRESS
-        | constants::MSTATE_DISCONNECTED
-        | constants::MSTATE_ERROR
-        | constants::MSTATE_IN_PROGRESS
-        | constants::MSTATE_MAINTENANCE
-        | constants::MSTATE_PROVISIONED
-        | constants::MSTATE_PROVISIONING
-        | constants::MSTATE_PROVISIONING_ERROR
-        | constants::MSTATE_UNASSIGNED_USER_CONNECTED
-        | constants::MSTATE_UNASSIGNED_USER_DISCONNECTED
-        | constants::MSTATE_UNKNOWN
-        | constants::MSTATE_VALIDATING
-        | constants::MSTATE_WAITING_FOR_AGENT => {}
-        _ => {
-            warn!("skipping unknown state {} for machine id {}", m.state, m.id);
-        }
-    };
+) -> Result<data::MachineState, Box<dyn Error>> {
+    if m.state.is_other() {
+        metrics.unknown_enum_value(constants::FIELD_MACHINE_STATE, m.state.as_str(), &m.id);
+    }
 
     let mut m_state = m.state.clone();
-    if m.state == constants::MSTATE_UNASSIGNED_USER_CONNECTED
-        || m.state == constants::MSTATE_UNASSIGNED_USER_DISCONNECTED
+    if m.state == data::MachineState::UnassignedUserConnected
+        || m.state == data::MachineState::UnassignedUserDisconnected
     {
         debug!("machines.rs:set_machine_state_metrics: machine state is {}, looking up session information for machine id {}", m.state, m.id);
 
-        if let Some(s) = horizon::get_session_for_machine_id(cfg, cli, token, &m.id)? {
+        if let Some(s) = client.session_for_machine_id(&m.id)? {
             if let Some(user_ids) = &m.user_ids {
                 debug!("machines.rs:set_machine_state_metrics: assigned user SIDs for machine id {}: {:?}, session user SID is {}", m.id, user_ids, s.user_id);
                 if user_ids.contains(&s.user_id) {
-                    m_state = if m.state == constants::MSTATE_UNASSIGNED_USER_CONNECTED {
-                        debug!(
-                            "machines.rs: changing state for machine id {} from {} to {}",
-                            m.id,
-                            constants::MSTATE_UNASSIGNED_USER_CONNECTED,
-                            constants::MSTATE_CONNECTED
-                        );
-                        constants::MSTATE_CONNECTED.to_string()
-                    } else if m.state == constants::MSTATE_UNASSIGNED_USER_DISCONNECTED {
-                        debug!(
-                            "machines.rs: changing state for machine id {} from {} to {}",
-                            m.id,
-                            constants::MSTATE_UNASSIGNED_USER_DISCONNECTED,
-                            constants::MSTATE_DISCONNECTED
-                        );
-                        constants::MSTATE_DISCONNECTED.to_string()
+                    m_state = if m.state == data::MachineState::UnassignedUserConnected {
+                        data::MachineState::Connected
                     } else {
-                        m_state
-                    }
+                        data::MachineState::Disconnected
+                    };
+                    debug!(
+                        "machines.rs: changing state for machine id {} from {} to {}",
+                        m.id, m.state, m_state
+                    );
                 }
             }
         }
     }
 
-    let ms = mmap
-        .entry(m.desktop_pool_id.to_string())
-        .or_insert_with(HashMap::new);
-    let lc_state = m_state.to_lowercase();
-    *ms.entry(lc_state).or_insert(0) += 1;
+    let ms = mmap.entry(m.desktop_pool_id.to_string()).or_default();
+    *ms.entry(m_state.label()).or_insert(0) += 1;
 
-    Ok(())
+    Ok(m_state)
 }
@@ -1,16 +1,29 @@
 use getopts::Options;
 use log::{debug, error};
+use simple_error::bail;
+use std::sync::{Arc, RwLock};
 use std::{env, process};
+use vmware_horizon_exporter::{configuration, constants, data, file, horizon, http, RecorderMode};
 
-mod configuration;
-mod constants;
-mod data;
+mod check;
 mod exporter;
 mod globals;
-mod horizon;
-mod http;
-mod machines;
-mod sessions;
+mod graphite;
+mod influxdb;
+mod inventory;
+mod logging;
+mod measurement;
+mod once;
+mod openmetrics;
+mod otlp;
+mod pushgateway;
+mod remote_write;
+mod report;
+mod scheduler;
+mod server;
+mod signals;
+mod sink;
+mod status_page;
 mod usage;
 
 fn main() {
@@ -23,6 +36,72 @@ fn main() {
     options.optflag("Q", "quiet", "Only log warning and error messages");
     options.optopt("c", "config", "Path to configuration file", "<config_file>");
     options.optflag("h", "help", "Show help text");
+    options.optflag("", "check-config", "Check configuration and exit");
+    options.optflag(
+        "",
+        "online",
+        "Login to Horizon and list pools when checking the configuration",
+    );
+    options.optflag("", "once", "Fetch metrics once, print them and exit");
+    options.optopt(
+        "",
+        "output",
+        "Write metrics of --once or the report to <file> instead of standard output",
+        "<file>",
+    );
+    options.optopt(
+        "",
+        "format",
+        "Format of the report, csv or json",
+        "<format>",
+    );
+    options.optopt(
+        "",
+        "record",
+        "Save responses of the Horizon REST API to <dir>",
+        "<dir>",
+    );
+    options.optopt(
+        "",
+        "replay",
+        "Use responses saved by --record in <dir> instead of the Horizon REST API",
+        "<dir>",
+    );
+    options.optopt(
+        "",
+        "log-format",
+        "Format of log messages, text or json",
+        "<format>",
+    );
+    options.optopt(
+        "",
+        "log-target",
+        "Write log messages to stdout, stderr, syslog, journald or file:<path>",
+        "<target>",
+    );
+    options.optopt(
+        "",
+        "log-level",
+        "Log level, optionally per module, e.g. info,http=debug",
+        "<levels>",
+    );
+    options.optopt(
+        "",
+        "log-max-size",
+        "Rotate the log file after <size> MiB",
+        "<size>",
+    );
+    options.optopt(
+        "",
+        "log-max-files",
+        "Number of rotated log files to keep",
+        "<count>",
+    );
+    options.optflag(
+        "",
+        "enable-reload",
+        "Allow reloading the configuration by a POST request to /-/reload",
+    );
     options.optopt(
         "l",
         "listen",
@@ -58,21 +137,30 @@ fn main() {
         process::exit(0);
     }
 
-    let config_file = match opts.opt_str("c") {
-        Some(v) => v,
-        None => {
-            eprintln!("Error: Missing configuration file");
+    // the configuration file is optional if all mandatory settings are set by environment variables
+    let config_file = opts.opt_str("c");
+
+    let report = match opts.free.first().map(|c| c.as_str()) {
+        Some(constants::REPORT_COMMAND) if opts.free.len() == 1 => true,
+        None => false,
+        Some(_) => {
+            eprintln!("Error: Unknown command {}", opts.free.join(" "));
             println!();
             usage::show_usage();
             process::exit(1);
         }
     };
 
-    let listen_address = opts
-        .opt_str("l")
-        .unwrap_or_else(|| constants::DEFAULT_LISTEN_ADDR.to_string());
+    let log_options = match parse_log_options(&opts, log_level, opts.opt_present("once") || report)
+    {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: Invalid logging options: {}", e);
+            process::exit(1);
+        }
+    };
 
-    match logging_init(log_level) {
+    match logging::init(&log_options) {
         Ok(_) => {}
         Err(e) => {
             eprintln!("Error: Can't initialise logging: {}", e);
@@ -80,39 +168,167 @@ fn main() {
         }
     };
 
-    let config = match configuration::parse_config_file(&config_file) {
+    let recorder_mode = match (opts.opt_str("record"), opts.opt_str("replay")) {
+        (Some(_), Some(_)) => {
+            error!("--record and --replay can't be used together");
+            process::exit(1);
+        }
+        (Some(d), None) => RecorderMode::Record(d.into()),
+        (None, Some(d)) => RecorderMode::Replay(d.into()),
+        (None, None) => RecorderMode::Off,
+    };
+    if let Err(e) = exporter::COLLECTOR.set_recorder(recorder_mode.clone()) {
+        error!("can't initialise recording or replay: {}", e);
+        process::exit(1);
+    }
+
+    if opts.opt_present("check-config") {
+        if check::run(
+            config_file.as_deref(),
+            opts.opt_present("online"),
+            &recorder_mode,
+        ) {
+            process::exit(0);
+        }
+        process::exit(1);
+    }
+
+    let config = match configuration::parse_config_file(config_file.as_deref()) {
         Ok(v) => v,
         Err(e) => {
-            error!("can't parse configuration file: {}", e);
+            error!("can't parse configuration: {}", e);
             process::exit(1);
         }
     };
 
     debug!(
-        "main.rs:main: parsed configuration fomr {} - {:?}",
+        "main.rs:main: parsed configuration from {:?} - {:?}",
         config_file, config
     );
 
+    let listen_address = match opts.opt_str("l") {
+        Some(v) => v,
+        None => config
+            .exporter
+            .listen
+            .clone()
+            .unwrap_or_else(|| constants::DEFAULT_LISTEN_ADDR.to_string()),
+    };
+
     exporter::register_metrics();
 
-    if let Err(e) = http::server(config, &listen_address) {
+    if opts.opt_present("once") {
+        if once::run(&config, opts.opt_str("output").as_deref()) {
+            process::exit(0);
+        }
+        process::exit(1);
+    }
+
+    if report {
+        let format = opts
+            .opt_str("format")
+            .unwrap_or_else(|| constants::REPORT_FORMAT_CSV.to_string());
+        if report::run(
+            &config,
+            &format,
+            opts.opt_str("output").as_deref(),
+            &recorder_mode,
+        ) {
+            process::exit(0);
+        }
+        process::exit(1);
+    }
+
+    let shared_config = Arc::new(RwLock::new(Arc::new(config)));
+
+    if let Err(e) = signals::start_handler(shared_config.clone(), config_file.clone()) {
+        error!("can't install signal handler: {}", e);
+        process::exit(1);
+    }
+
+    if let Err(e) = exporter::start_initial_refresh(shared_config.clone()) {
+        error!("can't start initial collection: {}", e);
+        process::exit(1);
+    }
+
+    let scheduler = match scheduler::start(shared_config.clone()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("can't start scheduler: {}", e);
+            process::exit(1);
+        }
+    };
+
+    let remote_write_sender = match remote_write::start_sender(shared_config.clone()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("can't start remote_write sender: {}", e);
+            process::exit(1);
+        }
+    };
+
+    if let Err(e) = server::run(
+        shared_config,
+        config_file,
+        &listen_address,
+        opts.opt_present("enable-reload"),
+    ) {
         error!("can't start HTTP server: {}", e);
         process::exit(1);
     };
+
+    if scheduler.join().is_err() {
+        error!("scheduler thread panicked");
+    }
+    if remote_write_sender.join().is_err() {
+        error!("remote_write sender thread panicked");
+    }
 }
 
-fn logging_init(l: log::LevelFilter) -> Result<(), fern::InitError> {
-    fern::Dispatch::new()
-        .format(|logout, logmsg, logrecord| {
-            logout.finish(format_args!(
-                "{:<6}: {} {}",
-                logrecord.level(),
-                chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%z"),
-                logmsg
-            ))
-        })
-        .level(l)
-        .chain(std::io::stdout())
-        .apply()?;
-    Ok(())
+fn parse_log_options(
+    opts: &getopts::Matches,
+    log_level: log::LevelFilter,
+    use_stderr: bool,
+) -> Result<logging::Options, Box<dyn std::error::Error>> {
+    let format = match opts.opt_str("log-format") {
+        Some(v) => logging::parse_format(&v)?,
+        None => logging::Format::Text,
+    };
+
+    // metrics of --once and reports are printed to standard output, so log messages go to standard error
+    let target = match opts.opt_str("log-target") {
+        Some(v) => logging::parse_target(&v)?,
+        None if use_stderr => logging::Target::Stderr,
+        None => logging::Target::Stdout,
+    };
+
+    let (level, modules) = match opts.opt_str("log-level") {
+        Some(v) => logging::parse_levels(&v, log_level)?,
+        None => (log_level, Vec::new()),
+    };
+
+    let max_size = match opts.opt_str("log-max-size") {
+        Some(v) => match v.parse::<u64>() {
+            Ok(v) => v,
+            Err(e) => bail!("invalid value {} for --log-max-size: {}", v, e),
+        },
+        None => constants::DEFAULT_LOG_MAX_SIZE,
+    };
+
+    let max_files = match opts.opt_str("log-max-files") {
+        Some(v) => match v.parse::<usize>() {
+            Ok(v) => v,
+            Err(e) => bail!("invalid value {} for --log-max-files: {}", v, e),
+        },
+        None => constants::DEFAULT_LOG_MAX_FILES,
+    };
+
+    Ok(logging::Options {
+        format,
+        target,
+        level,
+        modules,
+        max_size: max_size * 1024 * 1024,
+        max_files,
+    })
 }
@@ -0,0 +1,141 @@
+use crate::exporter;
+
+use std::collections::BTreeMap;
+use vmware_horizon_exporter::{configuration, constants, data};
+
+/// Number of sessions or machines of a pool with the same value of a field, e.g. the sessions of
+/// a pool in state connected
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Measurement {
+    pub name: &'static str,
+    /// Pool name from `pool_uuid_map` or the pool id
+    pub pool: String,
+    /// Name of the counted field, e.g. `state`
+    pub field: &'static str,
+    /// Value of the counted field, e.g. `connected`
+    pub value: String,
+    pub count: i64,
+}
+
+// Map measurement, pool id, field, value -> count, sorted for a stable output
+#[derive(Default)]
+struct Counts(BTreeMap<(&'static str, String, &'static str, String), i64>);
+
+impl Counts {
+    // Known values are reported with a count of 0, like the gauges of the collector
+    fn init(&mut self, name: &'static str, pool: &str, field: &'static str, values: &[String]) {
+        for v in values {
+            self.0
+                .entry((name, pool.to_string(), field, v.clone()))
+                .or_insert(0);
+        }
+    }
+
+    fn add(&mut self, name: &'static str, pool: &str, field: &'static str, value: String) {
+        *self
+            .0
+            .entry((name, pool.to_string(), field, value))
+            .or_insert(0) += 1;
+    }
+}
+
+fn labels<T>(known: &[T], label: fn(&T) -> String) -> Vec<String> {
+    known.iter().map(label).collect()
+}
+
+/// Counts of the sessions and machines from the last collection, no requests are sent to Horizon
+pub fn from_snapshot(cfg: &configuration::Configuration) -> Vec<Measurement> {
+    let mut counts = Counts::default();
+
+    let session_states = labels(data::SessionState::KNOWN, data::SessionState::label);
+    let session_protocols = labels(data::SessionProtocol::KNOWN, data::SessionProtocol::label);
+    let session_types = labels(data::SessionType::KNOWN, data::SessionType::label);
+    let machine_states = labels(data::MachineState::KNOWN, data::MachineState::label);
+    for p in exporter::COLLECTOR
+        .desktop_pools()
+        .iter()
+        .filter(|p| cfg.horizon_api.exports_pool(&p.id))
+    {
+        counts.init(constants::SESSIONS_NAME, &p.id, "state", &session_states);
+        counts.init(
+            constants::SESSION_PROTOCOLS_NAME,
+            &p.id,
+            "protocol",
+            &session_protocols,
+        );
+        counts.init(constants::SESSION_TYPES_NAME, &p.id, "type", &session_types);
+        counts.init(
+            constants::MACHINE_STATES_NAME,
+            &p.id,
+            "state",
+            &machine_states,
+        );
+    }
+
+    for s in exporter::COLLECTOR.sessions() {
+        let pool = match &s.desktop_pool_id {
+            Some(v) => v,
+            None => continue,
+        };
+        counts.add(
+            constants::SESSIONS_NAME,
+            pool,
+            "state",
+            s.session_state.label(),
+        );
+        counts.add(
+            constants::AGENT_VERSIONS_NAME,
+            pool,
+            "version",
+            s.agent_version.clone(),
+        );
+        if let Some(v) = &s.session_protocol {
+            counts.add(
+                constants::SESSION_PROTOCOLS_NAME,
+                pool,
+                "protocol",
+                v.label(),
+            );
+        }
+        counts.add(
+            constants::SESSION_TYPES_NAME,
+            pool,
+            "type",
+            s.session_type.label(),
+        );
+    }
+
+    for m in exporter::COLLECTOR.machines() {
+        let pool = &m.desktop_pool_id;
+        let state = m.effective_state.as_ref().unwrap_or(&m.state);
+        counts.add(constants::MACHINE_STATES_NAME, pool, "state", state.label());
+        if let Some(v) = &m.operating_system {
+            counts.add(
+                constants::MACHINE_OS_NAME,
+                pool,
+                "operating_system",
+                v.label(),
+            );
+        }
+        if let Some(v) = &m.operating_system_architecture {
+            counts.add(
+                constants::MACHINE_ARCH_NAME,
+                pool,
+                "architecture",
+                v.label(),
+            );
+        }
+    }
+
+    counts
+        .0
+        .into_iter()
+        .map(|((name, pool, field, value), count)| Measurement {
+            name,
+            pool: cfg.horizon_api.user_defined_pool_uuid_resolve(&pool),
+            field,
+            value,
+            count,
+        })
+        .collect()
+}
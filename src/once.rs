@@ -0,0 +1,37 @@
+use crate::configuration;
+use crate::exporter;
+use crate::file;
+
+use log::{error, info};
+use std::io::Write;
+
+// Returns false if the login to Horizon, a collector or writing the output failed, a file is
+// only written if the collection succeeded
+pub fn run(cfg: &configuration::Configuration, output: Option<&str>) -> bool {
+    let (metrics, success) = exporter::fetch_with_result(cfg);
+    let metrics = exporter::encode_text(&metrics);
+
+    match output {
+        Some(f) => {
+            // the textfile keeps the metrics of the last successful run
+            if !success {
+                error!("collection of the metrics failed, {} is not replaced", f);
+                return false;
+            }
+            if let Err(e) = file::write_atomic(f, &metrics) {
+                error!("can't write metrics to {}: {}", f, e);
+                return false;
+            }
+            info!("metrics written to {}", f);
+        }
+        None => {
+            let mut stdout = std::io::stdout();
+            if let Err(e) = stdout.write_all(metrics.as_bytes()) {
+                error!("can't write metrics to standard output: {}", e);
+                return false;
+            }
+        }
+    };
+
+    success
+}
@@ -0,0 +1,200 @@
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+
+// Units recognised by the suffix of the metric name, OpenMetrics requires the name to end with
+// the unit
+const UNITS: [&str; 6] = ["seconds", "bytes", "ratio", "celsius", "volts", "joules"];
+
+// Label values and help texts use the same escaping
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(v: f64) -> String {
+    if v.is_nan() {
+        "NaN".to_string()
+    } else if v.is_infinite() {
+        if v > 0.0 {
+            "+Inf".to_string()
+        } else {
+            "-Inf".to_string()
+        }
+    } else {
+        v.to_string()
+    }
+}
+
+fn unit(name: &str) -> Option<&'static str> {
+    UNITS
+        .iter()
+        .find(|u| name.ends_with(&format!("_{}", u)))
+        .copied()
+}
+
+fn sample(
+    buffer: &mut String,
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+    value: f64,
+) {
+    let mut l: Vec<String> = labels
+        .iter()
+        .map(|lp| format!("{}=\"{}\"", lp.get_name(), escape(lp.get_value())))
+        .collect();
+    if let Some((n, v)) = extra {
+        l.push(format!("{}=\"{}\"", n, escape(&v)));
+    }
+
+    buffer.push_str(name);
+    if !l.is_empty() {
+        buffer.push('{');
+        buffer.push_str(&l.join(","));
+        buffer.push('}');
+    }
+    buffer.push(' ');
+    buffer.push_str(&format_value(value));
+    buffer.push('\n');
+}
+
+fn header(buffer: &mut String, name: &str, mtype: &str, help: &str) {
+    buffer.push_str(&format!("# TYPE {} {}\n", name, mtype));
+    if let Some(u) = unit(name) {
+        buffer.push_str(&format!("# UNIT {} {}\n", name, u));
+    }
+    if !help.is_empty() {
+        buffer.push_str(&format!("# HELP {} {}\n", name, escape(help)));
+    }
+}
+
+// OpenMetrics text format, see https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+//
+// Gauges with the suffix _info (e.g. horizon_agent_version_info) have the value 1 and are
+// exposed as info metrics.
+pub fn encode(metrics: &[MetricFamily]) -> String {
+    let mut buffer = String::new();
+
+    for mf in metrics {
+        let name = mf.get_name();
+        let help = mf.get_help();
+
+        match mf.get_field_type() {
+            MetricType::GAUGE if name.ends_with("_info") => {
+                header(&mut buffer, name.trim_end_matches("_info"), "info", help);
+                for m in mf.get_metric() {
+                    sample(&mut buffer, name, m.get_label(), None, 1.0);
+                }
+            }
+            MetricType::GAUGE => {
+                header(&mut buffer, name, "gauge", help);
+                for m in mf.get_metric() {
+                    sample(
+                        &mut buffer,
+                        name,
+                        m.get_label(),
+                        None,
+                        m.get_gauge().get_value(),
+                    );
+                }
+            }
+            MetricType::UNTYPED => {
+                header(&mut buffer, name, "unknown", help);
+                for m in mf.get_metric() {
+                    sample(
+                        &mut buffer,
+                        name,
+                        m.get_label(),
+                        None,
+                        m.get_untyped().get_value(),
+                    );
+                }
+            }
+            MetricType::COUNTER => {
+                let family = name.trim_end_matches("_total");
+                header(&mut buffer, family, "counter", help);
+                for m in mf.get_metric() {
+                    sample(
+                        &mut buffer,
+                        &format!("{}_total", family),
+                        m.get_label(),
+                        None,
+                        m.get_counter().get_value(),
+                    );
+                }
+            }
+            MetricType::SUMMARY => {
+                header(&mut buffer, name, "summary", help);
+                for m in mf.get_metric() {
+                    let s = m.get_summary();
+                    for q in s.get_quantile() {
+                        sample(
+                            &mut buffer,
+                            name,
+                            m.get_label(),
+                            Some(("quantile", format_value(q.get_quantile()))),
+                            q.get_value(),
+                        );
+                    }
+                    sample(
+                        &mut buffer,
+                        &format!("{}_sum", name),
+                        m.get_label(),
+                        None,
+                        s.get_sample_sum(),
+                    );
+                    sample(
+                        &mut buffer,
+                        &format!("{}_count", name),
+                        m.get_label(),
+                        None,
+                        s.get_sample_count() as f64,
+                    );
+                }
+            }
+            MetricType::HISTOGRAM => {
+                header(&mut buffer, name, "histogram", help);
+                for m in mf.get_metric() {
+                    let h = m.get_histogram();
+                    for b in h.get_bucket() {
+                        // the +Inf bucket is always added below
+                        if b.get_upper_bound().is_infinite() {
+                            continue;
+                        }
+                        sample(
+                            &mut buffer,
+                            &format!("{}_bucket", name),
+                            m.get_label(),
+                            Some(("le", format_value(b.get_upper_bound()))),
+                            b.get_cumulative_count() as f64,
+                        );
+                    }
+                    sample(
+                        &mut buffer,
+                        &format!("{}_bucket", name),
+                        m.get_label(),
+                        Some(("le", "+Inf".to_string())),
+                        h.get_sample_count() as f64,
+                    );
+                    sample(
+                        &mut buffer,
+                        &format!("{}_sum", name),
+                        m.get_label(),
+                        None,
+                        h.get_sample_sum(),
+                    );
+                    sample(
+                        &mut buffer,
+                        &format!("{}_count", name),
+                        m.get_label(),
+                        None,
+                        h.get_sample_count() as f64,
+                    );
+                }
+            }
+        };
+    }
+
+    buffer.push_str("# EOF\n");
+    buffer
+}
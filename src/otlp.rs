@@ -0,0 +1,278 @@
+use crate::configuration;
+use crate::constants;
+use crate::exporter;
+use crate::http;
+use crate::sink::{Collected, Sink};
+
+use log::debug;
+use prometheus::proto::{MetricFamily, MetricType};
+use prost::Message;
+use simple_error::bail;
+use std::collections::BTreeMap;
+use std::error::Error;
+
+// Subset of the OTLP metrics messages, see
+// https://github.com/open-telemetry/opentelemetry-proto/blob/main/opentelemetry/proto/metrics/v1/metrics.proto
+// Fields of oneof groups are modelled as optional fields, they have the same wire format.
+#[derive(Clone, PartialEq, Message)]
+struct ExportMetricsServiceRequest {
+    #[prost(message, repeated, tag = "1")]
+    resource_metrics: Vec<ResourceMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ResourceMetrics {
+    #[prost(message, optional, tag = "1")]
+    resource: Option<Resource>,
+    #[prost(message, repeated, tag = "2")]
+    scope_metrics: Vec<ScopeMetrics>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Resource {
+    #[prost(message, repeated, tag = "1")]
+    attributes: Vec<KeyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct ScopeMetrics {
+    #[prost(message, optional, tag = "1")]
+    scope: Option<InstrumentationScope>,
+    #[prost(message, repeated, tag = "2")]
+    metrics: Vec<Metric>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct InstrumentationScope {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    version: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Metric {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    description: String,
+    #[prost(string, tag = "3")]
+    unit: String,
+    #[prost(message, optional, tag = "5")]
+    gauge: Option<Gauge>,
+    #[prost(message, optional, tag = "7")]
+    sum: Option<Sum>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Gauge {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sum {
+    #[prost(message, repeated, tag = "1")]
+    data_points: Vec<NumberDataPoint>,
+    #[prost(int32, tag = "2")]
+    aggregation_temporality: i32,
+    #[prost(bool, tag = "3")]
+    is_monotonic: bool,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct NumberDataPoint {
+    #[prost(message, repeated, tag = "7")]
+    attributes: Vec<KeyValue>,
+    #[prost(fixed64, tag = "3")]
+    time_unix_nano: u64,
+    #[prost(double, optional, tag = "4")]
+    as_double: Option<f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct KeyValue {
+    #[prost(string, tag = "1")]
+    key: String,
+    #[prost(message, optional, tag = "2")]
+    value: Option<AnyValue>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct AnyValue {
+    #[prost(string, optional, tag = "1")]
+    string_value: Option<String>,
+}
+
+const AGGREGATION_TEMPORALITY_CUMULATIVE: i32 = 2;
+
+fn key_value(key: &str, value: &str) -> KeyValue {
+    KeyValue {
+        key: key.to_string(),
+        value: Some(AnyValue {
+            string_value: Some(value.to_string()),
+        }),
+    }
+}
+
+fn resource(horizon_url: &str, otlp: &configuration::OtlpConfig) -> Resource {
+    let mut attributes: BTreeMap<String, String> = BTreeMap::new();
+    attributes.insert(
+        "service.name".to_string(),
+        otlp.service_name
+            .clone()
+            .unwrap_or_else(|| constants::DEFAULT_OTLP_SERVICE_NAME.to_string()),
+    );
+    attributes.insert(
+        "service.version".to_string(),
+        constants::VERSION.to_string(),
+    );
+    attributes.insert("horizon.url".to_string(), horizon_url.to_string());
+    if let Some(ra) = &otlp.resource_attributes {
+        for (k, v) in ra.iter() {
+            attributes.insert(k.clone(), v.clone());
+        }
+    }
+
+    Resource {
+        attributes: attributes.iter().map(|(k, v)| key_value(k, v)).collect(),
+    }
+}
+
+fn build_request(
+    horizon_url: &str,
+    otlp: &configuration::OtlpConfig,
+    metrics: &[MetricFamily],
+    timestamp: u64,
+) -> ExportMetricsServiceRequest {
+    let mut result = Vec::new();
+
+    for mf in metrics {
+        let mut data_points = Vec::new();
+        for m in mf.get_metric() {
+            let value = match exporter::sample_value(mf.get_field_type(), m) {
+                Some(v) => v,
+                None => {
+                    debug!(
+                        "otlp.rs:build_request: skipping {} with unsupported metric type",
+                        mf.get_name()
+                    );
+                    continue;
+                }
+            };
+            data_points.push(NumberDataPoint {
+                attributes: m
+                    .get_label()
+                    .iter()
+                    .map(|lp| key_value(lp.get_name(), lp.get_value()))
+                    .collect(),
+                time_unix_nano: timestamp,
+                as_double: Some(value),
+            });
+        }
+        if data_points.is_empty() {
+            continue;
+        }
+
+        let mut metric = Metric {
+            name: mf.get_name().to_string(),
+            description: mf.get_help().to_string(),
+            ..Default::default()
+        };
+        if mf.get_field_type() == MetricType::COUNTER {
+            metric.sum = Some(Sum {
+                data_points,
+                aggregation_temporality: AGGREGATION_TEMPORALITY_CUMULATIVE,
+                is_monotonic: true,
+            });
+        } else {
+            metric.gauge = Some(Gauge { data_points });
+        }
+        result.push(metric);
+    }
+
+    ExportMetricsServiceRequest {
+        resource_metrics: vec![ResourceMetrics {
+            resource: Some(resource(horizon_url, otlp)),
+            scope_metrics: vec![ScopeMetrics {
+                scope: Some(InstrumentationScope {
+                    name: constants::NAME.to_string(),
+                    version: constants::VERSION.to_string(),
+                }),
+                metrics: result,
+            }],
+        }],
+    }
+}
+
+pub struct Otlp {
+    cfg: configuration::OtlpConfig,
+    client: reqwest::blocking::Client,
+    horizon_url: String,
+    interval: u64,
+}
+
+impl Otlp {
+    pub fn new(
+        cfg: &configuration::Configuration,
+        otlp: &configuration::OtlpConfig,
+        default_interval: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Otlp {
+            cfg: otlp.clone(),
+            client: http::build_client(&otlp.http)?,
+            horizon_url: cfg.horizon_api.url_description(),
+            interval: otlp.interval.unwrap_or(default_interval),
+        })
+    }
+}
+
+impl Sink for Otlp {
+    fn name(&self) -> &'static str {
+        constants::OUTPUT_OTLP
+    }
+
+    fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>> {
+        push(&self.horizon_url, &self.cfg, &self.client, &data.metrics)?;
+        Ok(true)
+    }
+}
+
+fn push(
+    horizon_url: &str,
+    otlp: &configuration::OtlpConfig,
+    client: &reqwest::blocking::Client,
+    metrics: &[MetricFamily],
+) -> Result<(), Box<dyn Error>> {
+    let timestamp = match chrono::Local::now().timestamp_nanos_opt() {
+        Some(v) => v as u64,
+        None => bail!("current time can't be represented in nanoseconds"),
+    };
+    let payload = build_request(horizon_url, otlp, metrics, timestamp).encode_to_vec();
+
+    debug!("otlp.rs:push: POST {}", otlp.url);
+
+    let request = client
+        .post(&otlp.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+        .body(payload);
+
+    let response = http::basic_auth(request, &otlp.auth)?.send()?;
+    let status = response.status();
+    debug!("otlp.rs:push: received HTTP status={}", status);
+
+    if !status.is_success() {
+        bail!(
+            "export to {} failed, received {}: {}",
+            otlp.url,
+            status,
+            response.text().unwrap_or_default().trim()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::configuration;
+use crate::constants;
+use crate::http;
+use crate::sink::{Collected, Sink};
+
+use base64::engine::general_purpose;
+use base64::Engine;
+use log::debug;
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, TextEncoder};
+use simple_error::bail;
+use std::error::Error;
+
+// Label values containing a slash or empty values must be base64 encoded in the URL path
+fn path_component(name: &str, value: &str) -> String {
+    if value.is_empty() || value.contains('/') {
+        format!(
+            "/{}@base64/{}",
+            urlencoding::encode(name),
+            general_purpose::URL_SAFE.encode(value)
+        )
+    } else {
+        format!(
+            "/{}/{}",
+            urlencoding::encode(name),
+            urlencoding::encode(value)
+        )
+    }
+}
+
+fn grouping_path(cfg: &configuration::PushgatewayConfig) -> Result<String, Box<dyn Error>> {
+    let job = cfg
+        .job
+        .clone()
+        .unwrap_or_else(|| constants::DEFAULT_PUSHGATEWAY_JOB.to_string());
+    let instance = match &cfg.instance {
+        Some(v) => v.clone(),
+        None => hostname::get()?.to_string_lossy().to_string(),
+    };
+
+    let mut path = format!(
+        "/metrics{}{}",
+        path_component("job", &job),
+        path_component("instance", &instance)
+    );
+
+    if let Some(grouping) = &cfg.grouping {
+        let mut labels: Vec<(&String, &String)> = grouping.iter().collect();
+        labels.sort();
+        for (k, v) in labels {
+            path.push_str(&path_component(k, v));
+        }
+    }
+
+    Ok(path)
+}
+
+pub struct Pushgateway {
+    cfg: configuration::PushgatewayConfig,
+    client: reqwest::blocking::Client,
+    interval: u64,
+}
+
+impl Pushgateway {
+    pub fn new(
+        cfg: &configuration::PushgatewayConfig,
+        default_interval: u64,
+    ) -> Result<Self, Box<dyn Error>> {
+        Ok(Pushgateway {
+            cfg: cfg.clone(),
+            client: http::build_client(&cfg.http)?,
+            interval: cfg.interval.unwrap_or(default_interval),
+        })
+    }
+}
+
+impl Sink for Pushgateway {
+    fn name(&self) -> &'static str {
+        constants::OUTPUT_PUSHGATEWAY
+    }
+
+    fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>> {
+        push(&self.cfg, &self.client, &data.metrics)?;
+        Ok(true)
+    }
+}
+
+fn push(
+    cfg: &configuration::PushgatewayConfig,
+    client: &reqwest::blocking::Client,
+    metrics: &[MetricFamily],
+) -> Result<(), Box<dyn Error>> {
+    let url = format!("{}{}", cfg.url.trim_end_matches('/'), grouping_path(cfg)?);
+
+    let encoder = TextEncoder::new();
+    let mut payload = Vec::new();
+    encoder.encode(metrics, &mut payload)?;
+
+    debug!("pushgateway.rs:push: PUT {}", url);
+
+    let request = client
+        .put(&url)
+        .header(reqwest::header::CONTENT_TYPE, encoder.format_type())
+        .body(payload);
+
+    let response = http::basic_auth(request, &cfg.auth)?.send()?;
+    let status = response.status();
+    debug!("pushgateway.rs:push: received HTTP status={}", status);
+
+    if !status.is_success() {
+        bail!(
+            "push to {} failed, received {}: {}",
+            url,
+            status,
+            response.text().unwrap_or_default().trim()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use crate::constants;
+use crate::file;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use simple_error::bail;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Recording or replay of the responses of the Horizon REST API
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Mode {
+    #[default]
+    Off,
+    Record(PathBuf),
+    Replay(PathBuf),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct RecordedResponse {
+    method: String,
+    path: String,
+    status: u16,
+    body: String,
+}
+
+// Keys of JSON objects whose values are replaced before a response is written to disk
+const REDACTED_KEYS: [&str; 6] = [
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "token",
+    "password",
+    "secret",
+];
+
+impl Mode {
+    /// Create the directory for recordings, or check that the directory of a replay exists
+    pub fn prepare(&self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Mode::Record(d) => {
+                if let Err(e) = fs::create_dir_all(d) {
+                    bail!("can't create directory {}: {}", d.display(), e);
+                }
+            }
+            Mode::Replay(d) => {
+                if !d.is_dir() {
+                    bail!("{} is not a directory", d.display());
+                }
+            }
+            Mode::Off => {}
+        };
+        Ok(())
+    }
+}
+
+// The host part of the URL is not used, so recordings can be replayed with any configured URL
+fn request_path(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(u) => match u.query() {
+            Some(q) => format!("{}?{}", u.path(), q),
+            None => u.path().to_string(),
+        },
+        Err(_) => url.to_string(),
+    }
+}
+
+// FNV-1a, the hash of the standard library is not guaranteed to be stable between releases
+fn fnv1a(s: &str) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn file_name(dir: &Path, method: &str, path: &str) -> PathBuf {
+    let base = path.split('?').next().unwrap_or_default();
+    let readable: String = base
+        .trim_matches('/')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    dir.join(format!(
+        "{}_{}_{:016x}.json",
+        method,
+        readable,
+        fnv1a(&format!("{} {}", method, path))
+    ))
+}
+
+fn redact_value(v: &mut serde_json::Value) {
+    match v {
+        serde_json::Value::Object(m) => {
+            for (k, val) in m.iter_mut() {
+                if REDACTED_KEYS.contains(&k.to_lowercase().as_str()) {
+                    *val = serde_json::Value::String(constants::REDACTED.to_string());
+                } else {
+                    redact_value(val);
+                }
+            }
+        }
+        serde_json::Value::Array(a) => {
+            for val in a.iter_mut() {
+                redact_value(val);
+            }
+        }
+        _ => {}
+    };
+}
+
+fn redact(body: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(body) {
+        Ok(mut v) => {
+            redact_value(&mut v);
+            v.to_string()
+        }
+        // bodies which are not JSON (e.g. empty replies) don't contain tokens
+        Err(_) => body.to_string(),
+    }
+}
+
+pub fn record(dir: &Path, method: &str, url: &str, status: reqwest::StatusCode, body: &str) {
+    let path = request_path(url);
+    let file = file_name(dir, method, &path);
+    let recorded = RecordedResponse {
+        method: method.to_string(),
+        path,
+        status: status.as_u16(),
+        body: redact(body),
+    };
+
+    debug!("recorder.rs:record: writing response to {}", file.display());
+    let result = serde_json::to_string_pretty(&recorded)
+        .map_err(|e| e.into())
+        .and_then(|s| file::write_atomic(&file, &s));
+    if let Err(e) = result {
+        warn!("can't record response to {}: {}", file.display(), e);
+    }
+}
+
+pub fn replay(
+    dir: &Path,
+    method: &str,
+    url: &str,
+) -> Result<(reqwest::StatusCode, String), Box<dyn Error>> {
+    let path = request_path(url);
+    let file = file_name(dir, method, &path);
+
+    debug!(
+        "recorder.rs:replay: reading response from {}",
+        file.display()
+    );
+    let raw = match fs::read_to_string(&file) {
+        Ok(v) => v,
+        Err(e) => bail!(
+            "no recorded response for {} {} in {}: {}",
+            method,
+            path,
+            file.display(),
+            e
+        ),
+    };
+    let recorded: RecordedResponse = serde_json::from_str(&raw)?;
+
+    Ok((
+        reqwest::StatusCode::from_u16(recorded.status)?,
+        recorded.body,
+    ))
+}
@@ -0,0 +1,448 @@
+use crate::configuration;
+use crate::constants;
+use crate::globals;
+use crate::http;
+use crate::sink::{Collected, Sink};
+use crate::status_page;
+
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use prometheus::proto::{MetricFamily, MetricType};
+use prometheus::IntCounter;
+use prost::Message;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Messages of the remote_write protocol, see https://prometheus.io/docs/concepts/remote_write_spec/
+#[derive(Clone, PartialEq, Message)]
+struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct TimeSeries {
+    #[prost(message, repeated, tag = "1")]
+    labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Label {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Sample {
+    #[prost(double, tag = "1")]
+    value: f64,
+    #[prost(int64, tag = "2")]
+    timestamp: i64,
+}
+
+// Snappy compressed WriteRequest waiting to be sent
+#[derive(Clone)]
+struct Batch {
+    id: u64,
+    samples: u64,
+    payload: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Queue {
+    batches: VecDeque<Batch>,
+    next_id: u64,
+}
+
+lazy_static! {
+    static ref QUEUE: (Mutex<Queue>, Condvar) = (Mutex::new(Queue::default()), Condvar::new());
+    pub static ref DROPPED_SAMPLES: IntCounter = IntCounter::new(
+        constants::REMOTE_WRITE_DROPPED_SAMPLES_NAME,
+        constants::REMOTE_WRITE_DROPPED_SAMPLES_HELP
+    )
+    .unwrap();
+}
+
+enum SendError {
+    Retryable(Box<dyn Error>),
+    Permanent(Box<dyn Error>),
+}
+
+fn new_series(
+    name: &str,
+    labels: &[(String, String)],
+    extra: Option<(&str, String)>,
+    value: f64,
+    timestamp: i64,
+) -> TimeSeries {
+    let mut l: Vec<Label> = labels
+        .iter()
+        .map(|(n, v)| Label {
+            name: n.clone(),
+            value: v.clone(),
+        })
+        .collect();
+    l.push(Label {
+        name: "__name__".to_string(),
+        value: name.to_string(),
+    });
+    if let Some((n, v)) = extra {
+        l.push(Label {
+            name: n.to_string(),
+            value: v,
+        });
+    }
+    // the specification requires labels sorted by name
+    l.sort_by(|a, b| a.name.cmp(&b.name));
+
+    TimeSeries {
+        labels: l,
+        samples: vec![Sample { value, timestamp }],
+    }
+}
+
+fn build_write_request(
+    cfg: &configuration::RemoteWriteConfig,
+    metrics: &[MetricFamily],
+    timestamp: i64,
+) -> WriteRequest {
+    let mut timeseries = Vec::new();
+
+    for mf in metrics {
+        let name = mf.get_name();
+        for m in mf.get_metric() {
+            let mut labels: Vec<(String, String)> = Vec::new();
+            if let Some(external) = &cfg.external_labels {
+                for (k, v) in external.iter() {
+                    labels.push((k.clone(), v.clone()));
+                }
+            }
+            for lp in m.get_label() {
+                labels.retain(|(k, _)| k != lp.get_name());
+                labels.push((lp.get_name().to_string(), lp.get_value().to_string()));
+            }
+
+            match mf.get_field_type() {
+                MetricType::COUNTER => timeseries.push(new_series(
+                    name,
+                    &labels,
+                    None,
+                    m.get_counter().get_value(),
+                    timestamp,
+                )),
+                MetricType::GAUGE => timeseries.push(new_series(
+                    name,
+                    &labels,
+                    None,
+                    m.get_gauge().get_value(),
+                    timestamp,
+                )),
+                MetricType::UNTYPED => timeseries.push(new_series(
+                    name,
+                    &labels,
+                    None,
+                    m.get_untyped().get_value(),
+                    timestamp,
+                )),
+                MetricType::SUMMARY => {
+                    let s = m.get_summary();
+                    for q in s.get_quantile() {
+                        timeseries.push(new_series(
+                            name,
+                            &labels,
+                            Some(("quantile", q.get_quantile().to_string())),
+                            q.get_value(),
+                            timestamp,
+                        ));
+                    }
+                    timeseries.push(new_series(
+                        &format!("{}_sum", name),
+                        &labels,
+                        None,
+                        s.get_sample_sum(),
+                        timestamp,
+                    ));
+                    timeseries.push(new_series(
+                        &format!("{}_count", name),
+                        &labels,
+                        None,
+                        s.get_sample_count() as f64,
+                        timestamp,
+                    ));
+                }
+                MetricType::HISTOGRAM => {
+                    let h = m.get_histogram();
+                    for b in h.get_bucket() {
+                        timeseries.push(new_series(
+                            &format!("{}_bucket", name),
+                            &labels,
+                            Some(("le", b.get_upper_bound().to_string())),
+                            b.get_cumulative_count() as f64,
+                            timestamp,
+                        ));
+                    }
+                    timeseries.push(new_series(
+                        &format!("{}_bucket", name),
+                        &labels,
+                        Some(("le", "+Inf".to_string())),
+                        h.get_sample_count() as f64,
+                        timestamp,
+                    ));
+                    timeseries.push(new_series(
+                        &format!("{}_sum", name),
+                        &labels,
+                        None,
+                        h.get_sample_sum(),
+                        timestamp,
+                    ));
+                    timeseries.push(new_series(
+                        &format!("{}_count", name),
+                        &labels,
+                        None,
+                        h.get_sample_count() as f64,
+                        timestamp,
+                    ));
+                }
+            };
+        }
+    }
+
+    WriteRequest { timeseries }
+}
+
+// The data is delivered by the sender thread, which records the delivery status
+pub struct RemoteWrite {
+    cfg: configuration::RemoteWriteConfig,
+    interval: u64,
+}
+
+impl RemoteWrite {
+    pub fn new(cfg: &configuration::RemoteWriteConfig, default_interval: u64) -> Self {
+        RemoteWrite {
+            cfg: cfg.clone(),
+            interval: cfg.interval.unwrap_or(default_interval),
+        }
+    }
+}
+
+impl Sink for RemoteWrite {
+    fn name(&self) -> &'static str {
+        constants::OUTPUT_REMOTE_WRITE
+    }
+
+    fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>> {
+        enqueue(&self.cfg, &data.metrics)?;
+        Ok(false)
+    }
+}
+
+fn enqueue(
+    cfg: &configuration::RemoteWriteConfig,
+    metrics: &[MetricFamily],
+) -> Result<(), Box<dyn Error>> {
+    let timestamp = chrono::Local::now().timestamp_millis();
+    let request = build_write_request(cfg, metrics, timestamp);
+    debug!(
+        "remote_write.rs:enqueue: encoding {} time series",
+        request.timeseries.len()
+    );
+
+    let payload = snap::raw::Encoder::new().compress_vec(&request.encode_to_vec())?;
+
+    let queue_size = cfg
+        .queue_size
+        .unwrap_or(constants::DEFAULT_REMOTE_WRITE_QUEUE_SIZE);
+    let (lock, cvar) = &*QUEUE;
+    let mut queue = lock.lock().unwrap();
+    let id = queue.next_id;
+    queue.next_id += 1;
+    queue.batches.push_back(Batch {
+        id,
+        samples: request.timeseries.len() as u64,
+        payload,
+    });
+    if queue.batches.len() > queue_size {
+        if let Some(dropped) = queue.batches.pop_front() {
+            DROPPED_SAMPLES.inc_by(dropped.samples);
+        }
+        warn!(
+            "remote_write queue is full ({} entries), dropping the oldest data",
+            queue_size
+        );
+    }
+    cvar.notify_all();
+
+    Ok(())
+}
+
+fn send(
+    cfg: &configuration::RemoteWriteConfig,
+    client: &reqwest::blocking::Client,
+    payload: &[u8],
+) -> Result<(), SendError> {
+    let request = client
+        .post(&cfg.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/x-protobuf")
+        .header(reqwest::header::CONTENT_ENCODING, "snappy")
+        .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+        .body(payload.to_vec());
+
+    let request = match http::basic_auth(request, &cfg.auth) {
+        Ok(v) => v,
+        Err(e) => return Err(SendError::Permanent(e)),
+    };
+
+    debug!("remote_write.rs:send: POST {}", cfg.url);
+    let response = match request.send() {
+        Ok(v) => v,
+        Err(e) => return Err(SendError::Retryable(Box::new(e))),
+    };
+
+    let status = response.status();
+    debug!("remote_write.rs:send: received HTTP status={}", status);
+    if status.is_success() {
+        return Ok(());
+    }
+
+    let message = format!(
+        "received {}: {}",
+        status,
+        response.text().unwrap_or_default().trim()
+    );
+    // the specification requires retries for 5xx and 429, other errors are permanent
+    if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        Err(SendError::Retryable(message.into()))
+    } else {
+        Err(SendError::Permanent(message.into()))
+    }
+}
+
+// Sleep for the backoff time, returns false if the process is shutting down
+fn backoff(seconds: u64) -> bool {
+    for _ in 0..seconds {
+        if globals::SHUTDOWN.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+    !globals::SHUTDOWN.load(Ordering::SeqCst)
+}
+
+pub fn start_sender(
+    cfg: configuration::SharedConfiguration,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let handle = thread::Builder::new()
+        .name("remote-write".to_string())
+        .spawn(move || sender(&cfg))?;
+    Ok(handle)
+}
+
+// Remove the batch from the queue, it could have been dropped meanwhile if the queue was full
+fn remove(batch: &Batch) {
+    let mut queue = QUEUE.0.lock().unwrap();
+    if queue.batches.front().is_some_and(|b| b.id == batch.id) {
+        queue.batches.pop_front();
+    }
+}
+
+// The oldest batch is retried until it is delivered, so data is only dropped if the queue is
+// full or the endpoint rejects it
+fn sender(shared_cfg: &configuration::SharedConfiguration) {
+    let (lock, cvar) = &*QUEUE;
+    // the HTTP client is created again if the configuration was reloaded
+    let mut client: Option<(Arc<configuration::Configuration>, reqwest::blocking::Client)> = None;
+    let mut wait: Option<u64> = None;
+
+    while !globals::SHUTDOWN.load(Ordering::SeqCst) {
+        let batch = {
+            let mut queue = lock.lock().unwrap();
+            if queue.batches.is_empty() {
+                let (q, _) = cvar
+                    .wait_timeout(queue, Duration::from_secs(constants::HTTP_RECV_TIMEOUT))
+                    .unwrap();
+                queue = q;
+            }
+            match queue.batches.front() {
+                Some(v) => v.clone(),
+                None => continue,
+            }
+        };
+
+        let cfg = configuration::current(shared_cfg);
+        let rw = match &cfg.remote_write {
+            Some(v) => v,
+            None => {
+                // remote_write was removed by a configuration reload
+                lock.lock().unwrap().batches.clear();
+                continue;
+            }
+        };
+
+        if !client.as_ref().is_some_and(|(c, _)| Arc::ptr_eq(c, &cfg)) {
+            client = match http::build_client(&rw.http) {
+                Ok(v) => Some((cfg.clone(), v)),
+                Err(e) => {
+                    error!("can't create HTTP client for remote_write: {}", e);
+                    status_page::output_failure(constants::OUTPUT_REMOTE_WRITE, &e.to_string());
+                    lock.lock().unwrap().batches.clear();
+                    None
+                }
+            };
+        }
+        let http_client = match &client {
+            Some((_, v)) => v,
+            None => continue,
+        };
+
+        match send(rw, http_client, &batch.payload) {
+            Ok(_) => {
+                info!("metrics sent to remote_write endpoint {}", rw.url);
+                status_page::output_success(constants::OUTPUT_REMOTE_WRITE);
+                remove(&batch);
+                wait = None;
+            }
+            Err(SendError::Permanent(e)) => {
+                error!(
+                    "remote_write endpoint {} rejected the metrics, dropping them: {}",
+                    rw.url, e
+                );
+                status_page::output_failure(constants::OUTPUT_REMOTE_WRITE, &e.to_string());
+                DROPPED_SAMPLES.inc_by(batch.samples);
+                remove(&batch);
+                wait = None;
+            }
+            Err(SendError::Retryable(e)) => {
+                let min_backoff = rw
+                    .min_backoff
+                    .unwrap_or(constants::DEFAULT_REMOTE_WRITE_MIN_BACKOFF);
+                let max_backoff = rw
+                    .max_backoff
+                    .unwrap_or(constants::DEFAULT_REMOTE_WRITE_MAX_BACKOFF);
+                let seconds = wait.map_or(min_backoff, |w| (w * 2).min(max_backoff));
+                warn!(
+                    "sending data to remote_write endpoint {} failed, retrying in {} seconds: {}",
+                    rw.url, seconds, e
+                );
+                status_page::output_failure(constants::OUTPUT_REMOTE_WRITE, &e.to_string());
+                wait = Some(seconds);
+                if !backoff(seconds) {
+                    break;
+                }
+            }
+        };
+    }
+
+    debug!("remote_write.rs:sender: sender stopped");
+}
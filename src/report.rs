@@ -0,0 +1,238 @@
+use crate::configuration;
+use crate::constants;
+use crate::data;
+use crate::file;
+use crate::horizon::HorizonClient;
+use crate::RecorderMode;
+
+use log::{error, info, warn};
+use serde::Serialize;
+use simple_error::bail;
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+// One row per machine of the exported pools
+#[derive(Serialize, Clone, Debug)]
+struct ReportRow {
+    pool_id: String,
+    pool_name: String,
+    pool_label: String,
+    machine_id: String,
+    machine_name: String,
+    state: String,
+    operating_system: String,
+    operating_system_architecture: String,
+    agent_version: String,
+    assigned_users: Vec<String>,
+    session_state: String,
+    session_protocol: String,
+}
+
+const CSV_HEADER: [&str; 12] = [
+    "pool_id",
+    "pool_name",
+    "pool_label",
+    "machine_id",
+    "machine_name",
+    "state",
+    "operating_system",
+    "operating_system_architecture",
+    "agent_version",
+    "assigned_users",
+    "session_state",
+    "session_protocol",
+];
+
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+fn render_csv(rows: &[ReportRow]) -> String {
+    let mut result = CSV_HEADER.join(",");
+    result.push('\n');
+
+    for r in rows {
+        let fields = [
+            &r.pool_id,
+            &r.pool_name,
+            &r.pool_label,
+            &r.machine_id,
+            &r.machine_name,
+            &r.state,
+            &r.operating_system,
+            &r.operating_system_architecture,
+            &r.agent_version,
+            &r.assigned_users.join(";"),
+            &r.session_state,
+            &r.session_protocol,
+        ];
+        let line: Vec<String> = fields.iter().map(|f| csv_field(f)).collect();
+        result.push_str(&line.join(","));
+        result.push('\n');
+    }
+
+    result
+}
+
+fn build_rows(
+    cfg: &configuration::Configuration,
+    pools: &[data::DesktopPool],
+    machines: &[data::Machine],
+    sessions: &[data::Session],
+) -> Vec<ReportRow> {
+    let pool_names: HashMap<&str, &str> = pools
+        .iter()
+        .map(|p| (p.id.as_str(), p.name.as_deref().unwrap_or_default()))
+        .collect();
+    let mut machine_sessions: HashMap<&str, &data::Session> = HashMap::new();
+    for s in sessions.iter() {
+        if let Some(m) = &s.machine_id {
+            machine_sessions.entry(m.as_str()).or_insert(s);
+        }
+    }
+
+    let mut rows: Vec<ReportRow> = machines
+        .iter()
+        .filter(|m| cfg.horizon_api.exports_pool(&m.desktop_pool_id))
+        .map(|m| {
+            let session = machine_sessions.get(m.id.as_str());
+            ReportRow {
+                pool_id: m.desktop_pool_id.clone(),
+                pool_name: pool_names
+                    .get(m.desktop_pool_id.as_str())
+                    .unwrap_or(&"")
+                    .to_string(),
+                pool_label: cfg
+                    .horizon_api
+                    .user_defined_pool_uuid_resolve(&m.desktop_pool_id),
+                machine_id: m.id.clone(),
+                machine_name: m.name.clone().unwrap_or_default(),
+                state: m.state.to_string(),
+                operating_system: m
+                    .operating_system
+                    .as_ref()
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+                operating_system_architecture: m
+                    .operating_system_architecture
+                    .as_ref()
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+                agent_version: m
+                    .agent_version
+                    .clone()
+                    .or_else(|| session.map(|s| s.agent_version.clone()))
+                    .unwrap_or_default(),
+                assigned_users: m.user_ids.clone().unwrap_or_default(),
+                session_state: session
+                    .map(|s| s.session_state.to_string())
+                    .unwrap_or_default(),
+                session_protocol: session
+                    .and_then(|s| s.session_protocol.as_ref())
+                    .map(|v| v.to_string())
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    rows.sort_by(|a, b| {
+        (&a.pool_label, &a.machine_name, &a.machine_id).cmp(&(
+            &b.pool_label,
+            &b.machine_name,
+            &b.machine_id,
+        ))
+    });
+    rows
+}
+
+fn fetch_rows(
+    cfg: &configuration::Configuration,
+    recorder: &RecorderMode,
+) -> Result<Vec<ReportRow>, Box<dyn Error>> {
+    let mut client = HorizonClient::new(cfg)?;
+    client.set_recorder(recorder.clone())?;
+
+    client.login()?;
+    // selects the REST API endpoints for the Horizon version
+    if let Err(e) = client.environment_properties() {
+        warn!("can't get Horizon version information: {}", e);
+    }
+    let result = collect(cfg, &mut client);
+
+    if let Err(e) = client.logout() {
+        warn!("horizon logout failed: {}", e);
+    }
+
+    result
+}
+
+fn collect(
+    cfg: &configuration::Configuration,
+    client: &mut HorizonClient,
+) -> Result<Vec<ReportRow>, Box<dyn Error>> {
+    let pools = client.desktop_pools()?;
+    let machines = client.machines()?;
+    let sessions = client.sessions()?;
+    Ok(build_rows(cfg, &pools, &machines, &sessions))
+}
+
+fn render(rows: &[ReportRow], format: &str) -> Result<String, Box<dyn Error>> {
+    match format {
+        constants::REPORT_FORMAT_CSV => Ok(render_csv(rows)),
+        constants::REPORT_FORMAT_JSON => {
+            let mut result = serde_json::to_string_pretty(rows)?;
+            result.push('\n');
+            Ok(result)
+        }
+        _ => bail!("unsupported report format {}", format),
+    }
+}
+
+// Returns false if the report can't be created or written
+pub fn run(
+    cfg: &configuration::Configuration,
+    format: &str,
+    output: Option<&str>,
+    recorder: &RecorderMode,
+) -> bool {
+    if format != constants::REPORT_FORMAT_CSV && format != constants::REPORT_FORMAT_JSON {
+        error!(
+            "unsupported report format {}, only {} and {} are supported",
+            format,
+            constants::REPORT_FORMAT_CSV,
+            constants::REPORT_FORMAT_JSON
+        );
+        return false;
+    }
+
+    let report = match fetch_rows(cfg, recorder).and_then(|rows| render(&rows, format)) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("can't create report: {}", e);
+            return false;
+        }
+    };
+
+    match output {
+        Some(f) => {
+            if let Err(e) = file::write_atomic(f, &report) {
+                error!("can't write report to {}: {}", f, e);
+                return false;
+            }
+            info!("report written to {}", f);
+        }
+        None => {
+            if let Err(e) = std::io::stdout().write_all(report.as_bytes()) {
+                error!("can't write report to standard output: {}", e);
+                return false;
+            }
+        }
+    };
+
+    true
+}
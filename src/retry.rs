@@ -0,0 +1,209 @@
+use crate::configuration;
+use crate::constants;
+
+use chrono::TimeZone;
+use log::{debug, warn};
+use simple_error::bail;
+use std::collections::hash_map::RandomState;
+use std::error::Error;
+use std::hash::{BuildHasher, Hasher};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Retries of requests to the Horizon REST API
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub min_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: constants::DEFAULT_HORIZON_RETRIES,
+            min_backoff: Duration::from_secs(constants::DEFAULT_HORIZON_MIN_BACKOFF),
+            max_backoff: Duration::from_secs(constants::DEFAULT_HORIZON_MAX_BACKOFF),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new(cfg: &configuration::HorizonAPIConfig) -> Self {
+        RetryPolicy {
+            retries: cfg.retries.unwrap_or(constants::DEFAULT_HORIZON_RETRIES),
+            min_backoff: Duration::from_secs(
+                cfg.min_backoff
+                    .unwrap_or(constants::DEFAULT_HORIZON_MIN_BACKOFF),
+            ),
+            max_backoff: Duration::from_secs(
+                cfg.max_backoff
+                    .unwrap_or(constants::DEFAULT_HORIZON_MAX_BACKOFF),
+            ),
+        }
+    }
+
+    // Exponential backoff before the retry following `attempt` (starting at 0). Half of the
+    // delay is random, so several exporters don't retry at the same time.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let base = self
+            .min_backoff
+            .saturating_mul(2_u32.saturating_pow(attempt))
+            .min(self.max_backoff);
+        let half = base / 2;
+        half + random_duration(base - half)
+    }
+
+    /// Send a request until it succeeds or the retries are exhausted
+    ///
+    /// Requests failing to connect and requests answered with 429 are always retried. Timeouts
+    /// and server errors are only retried for idempotent requests.
+    pub fn send<F>(
+        &self,
+        idempotent: bool,
+        send: F,
+    ) -> Result<reqwest::blocking::Response, reqwest::Error>
+    where
+        F: Fn() -> Result<reqwest::blocking::Response, reqwest::Error>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = send();
+            let delay = match &result {
+                Ok(r) if r.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => Some(
+                    retry_after(r.headers())
+                        .unwrap_or_else(|| self.backoff(attempt))
+                        .min(self.max_backoff),
+                ),
+                Ok(r) if idempotent && r.status().is_server_error() => Some(self.backoff(attempt)),
+                Ok(_) => None,
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                    Some(self.backoff(attempt))
+                }
+                Err(_) => None,
+            };
+
+            let delay = match delay {
+                Some(v) if attempt < self.retries => v,
+                _ => return result,
+            };
+
+            match &result {
+                Ok(r) => warn!(
+                    "request to {} failed with {}, retrying in {:.1}s",
+                    r.url(),
+                    r.status(),
+                    delay.as_secs_f64()
+                ),
+                Err(e) => warn!(
+                    "request failed: {}, retrying in {:.1}s",
+                    e,
+                    delay.as_secs_f64()
+                ),
+            };
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+}
+
+fn random_duration(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    // RandomState is seeded randomly for each instance, no need for a random number crate
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    Duration::from_nanos(hasher.finish() % (max.as_nanos() as u64).max(1))
+}
+
+// Retry-After contains either the number of seconds or a HTTP date
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let value = headers
+        .get(reqwest::header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim();
+    if let Ok(v) = value.parse::<u64>() {
+        return Some(Duration::from_secs(v));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = date.timestamp() - chrono::Utc::now().timestamp();
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// Consecutive login failures of a service account
+///
+/// Logins are suspended for `login_suspend` seconds after `max_login_failures` consecutive
+/// failures, to prevent a lockout of the account.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    max_failures: u32,
+    suspend: u64,
+    failures: u32,
+    open_until: Option<i64>,
+}
+
+impl CircuitBreaker {
+    pub fn new(cfg: &configuration::HorizonAPIConfig) -> Self {
+        CircuitBreaker {
+            max_failures: cfg
+                .max_login_failures
+                .unwrap_or(constants::DEFAULT_MAX_LOGIN_FAILURES),
+            suspend: cfg
+                .login_suspend
+                .unwrap_or(constants::DEFAULT_LOGIN_SUSPEND),
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// Fail if logins are suspended after too many consecutive login failures
+    pub fn login_allowed(&self) -> Result<(), Box<dyn Error>> {
+        if let Some(until) = self.open_until {
+            if chrono::Local::now().timestamp() < until {
+                bail!(
+                    "login suspended after {} consecutive login failures until {}",
+                    self.failures,
+                    chrono::Local
+                        .timestamp_opt(until, 0)
+                        .single()
+                        .map(|t| t.to_rfc3339())
+                        .unwrap_or_default()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn login_succeeded(&mut self) {
+        self.failures = 0;
+        self.open_until = None;
+    }
+
+    pub fn login_failed(&mut self) {
+        if self.max_failures == 0 {
+            return;
+        }
+
+        self.failures += 1;
+        debug!(
+            "retry.rs:login_failed: {} consecutive login failures",
+            self.failures
+        );
+
+        // after the suspension a single login is tried, another failure suspends the logins again
+        if self.failures >= self.max_failures {
+            warn!(
+                "{} consecutive login failures, suspending logins for {} seconds",
+                self.failures, self.suspend
+            );
+            self.open_until = Some(chrono::Local::now().timestamp() + self.suspend as i64);
+        }
+    }
+}
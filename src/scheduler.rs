@@ -0,0 +1,87 @@
+use crate::configuration;
+use crate::constants;
+use crate::exporter;
+use crate::globals;
+use crate::measurement;
+use crate::sink::{self, Collected, Sink};
+use crate::status_page;
+
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Delivery status is recorded here for synchronous outputs, queued outputs record it on delivery
+fn run_sink(sink: &mut dyn Sink, data: &Collected) {
+    match sink.send(data) {
+        Ok(true) => {
+            info!("metrics sent to {}", sink.name());
+            status_page::output_success(sink.name());
+        }
+        Ok(false) => {}
+        Err(e) => {
+            error!("can't send metrics to {}: {}", sink.name(), e);
+            status_page::output_failure(sink.name(), &e.to_string());
+        }
+    };
+}
+
+pub fn start(
+    cfg: configuration::SharedConfiguration,
+) -> Result<thread::JoinHandle<()>, Box<dyn Error>> {
+    let handle = thread::Builder::new()
+        .name("scheduler".to_string())
+        .spawn(move || run(&cfg))?;
+    Ok(handle)
+}
+
+fn run(shared_cfg: &configuration::SharedConfiguration) {
+    let mut next_run: HashMap<&'static str, Instant> = HashMap::new();
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+    let mut sinks_cfg: Option<Arc<configuration::Configuration>> = None;
+
+    while !globals::SHUTDOWN.load(Ordering::SeqCst) {
+        // the sinks are created again if the configuration was reloaded
+        let cfg = configuration::current(shared_cfg);
+        if !sinks_cfg.as_ref().is_some_and(|c| Arc::ptr_eq(c, &cfg)) {
+            sinks = sink::configured(&cfg);
+            sinks_cfg = Some(cfg.clone());
+        }
+        let now = Instant::now();
+
+        let mut due: Vec<&mut Box<dyn Sink>> = sinks
+            .iter_mut()
+            .filter(|s| match next_run.get(s.name()) {
+                Some(v) => *v <= now,
+                None => true,
+            })
+            .collect();
+
+        if !due.is_empty() {
+            debug!(
+                "scheduler.rs:run: refreshing metrics for outputs {:?}",
+                due.iter().map(|s| s.name()).collect::<Vec<&str>>()
+            );
+            let (metrics, success) = exporter::fetch_with_result(&cfg);
+            if !success {
+                warn!("metric refresh was not successful, pushing available metrics");
+            }
+            let data = Collected {
+                metrics,
+                measurements: measurement::from_snapshot(&cfg),
+            };
+
+            for sink in due.iter_mut() {
+                run_sink(sink.as_mut(), &data);
+                next_run.insert(sink.name(), now + Duration::from_secs(sink.interval()));
+            }
+        }
+
+        thread::sleep(Duration::from_secs(constants::SCHEDULER_TICK));
+    }
+
+    debug!("scheduler.rs:run: scheduler stopped");
+}
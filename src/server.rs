@@ -0,0 +1,305 @@
+use crate::configuration;
+use crate::constants;
+use crate::exporter;
+use crate::globals;
+use crate::inventory;
+use crate::openmetrics;
+use crate::status_page;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use log::{debug, error, info, warn};
+use simple_error::bail;
+use std::error::Error;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Quality and precedence of the best matching entry of the Accept or Accept-Encoding headers,
+// e.g. "application/openmetrics-text;version=1.0.0;q=0.5,text/plain;q=0.3,*/*;q=0.2"
+//
+// Exact matches take precedence over "type/*", which takes precedence over "*/*" or "*".
+// Returns None if no entry matches.
+fn quality(request: &tiny_http::Request, header: &'static str, value: &str) -> Option<(f32, u8)> {
+    let mut best: Option<(f32, u8)> = None;
+
+    for entry in request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv(header))
+        .flat_map(|h| h.value.as_str().split(','))
+    {
+        let mut parts = entry.split(';').map(|p| p.trim());
+        let range = parts.next().unwrap_or_default();
+        let precedence = if range.eq_ignore_ascii_case(value) {
+            2
+        } else if range == "*" || range == "*/*" {
+            0
+        } else if range
+            .strip_suffix("/*")
+            .map(|t| {
+                value
+                    .split('/')
+                    .next()
+                    .map(|v| v.eq_ignore_ascii_case(t))
+                    .unwrap_or_default()
+            })
+            .unwrap_or_default()
+        {
+            1
+        } else {
+            continue;
+        };
+
+        let q = parts
+            .filter_map(|p| p.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("q"))
+            .and_then(|(_, v)| v.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if best.map(|(_, p)| precedence > p).unwrap_or(true) {
+            best = Some((q, precedence));
+        }
+    }
+
+    best
+}
+
+fn wants_json(request: &tiny_http::Request, query: &str) -> bool {
+    if query.split('&').any(|q| q == "format=json") {
+        return true;
+    }
+
+    // */* is sent by browsers and curl, so JSON must be requested explicitly
+    matches!(
+        quality(request, "Accept", constants::MIME_JSON),
+        Some((q, 2)) if q > 0.0
+    )
+}
+
+// OpenMetrics is used if it is preferred to the Prometheus text format, an explicitly listed
+// OpenMetrics wins over text/plain with the same quality
+fn wants_openmetrics(request: &tiny_http::Request) -> bool {
+    let openmetrics = match quality(request, "Accept", "application/openmetrics-text") {
+        Some(v) => v,
+        None => return false,
+    };
+    let text = quality(request, "Accept", "text/plain").unwrap_or((0.0, 0));
+
+    openmetrics.0 > 0.0
+        && (openmetrics.0 > text.0 || (openmetrics.0 == text.0 && openmetrics.1 == 2))
+}
+
+// Encodings like "gzip;q=0" explicitly reject gzip
+fn wants_gzip(request: &tiny_http::Request) -> bool {
+    matches!(quality(request, "Accept-Encoding", "gzip"), Some((q, _)) if q > 0.0)
+}
+
+fn gzip(data: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+pub fn run(
+    cfg: configuration::SharedConfiguration,
+    config_file: Option<String>,
+    listen_address: &str,
+    enable_reload: bool,
+) -> Result<(), Box<dyn Error>> {
+    let http_server = match tiny_http::Server::http(listen_address) {
+        Ok(v) => Arc::new(v),
+        Err(e) => bail!("can't listen on {}: {}", listen_address, e),
+    };
+    let threads = configuration::current(&cfg)
+        .exporter
+        .http_threads
+        .unwrap_or(constants::DEFAULT_HTTP_THREADS);
+    let mut workers = Vec::new();
+
+    debug!("server.rs:run: starting {} HTTP worker threads", threads);
+    for i in 0..threads {
+        let worker_server = http_server.clone();
+        let worker_cfg = cfg.clone();
+        let worker_config_file = config_file.clone();
+        let worker = thread::Builder::new()
+            .name(format!("http-worker-{}", i))
+            .spawn(move || {
+                worker(
+                    &worker_server,
+                    &worker_cfg,
+                    worker_config_file.as_deref(),
+                    enable_reload,
+                )
+            })?;
+        workers.push(worker);
+    }
+
+    for w in workers {
+        if w.join().is_err() {
+            error!("HTTP worker thread panicked");
+        }
+    }
+
+    info!("HTTP server stopped");
+    Ok(())
+}
+
+fn worker(
+    http_server: &tiny_http::Server,
+    cfg: &configuration::SharedConfiguration,
+    config_file: Option<&str>,
+    enable_reload: bool,
+) {
+    // requests in progress are finished before the worker stops
+    while !globals::SHUTDOWN.load(Ordering::SeqCst) {
+        let request =
+            match http_server.recv_timeout(Duration::from_secs(constants::HTTP_RECV_TIMEOUT)) {
+                Ok(Some(v)) => v,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Can't process incoming request: {}", e);
+                    continue;
+                }
+            };
+        handle_request(cfg, config_file, enable_reload, request);
+    }
+}
+
+fn handle_request(
+    shared_cfg: &configuration::SharedConfiguration,
+    config_file: Option<&str>,
+    enable_reload: bool,
+    request: tiny_http::Request,
+) {
+    let cfg = &*configuration::current(shared_cfg);
+    let method = request.method();
+    let url = request.url();
+    let (path, query) = match url.split_once('?') {
+        Some((p, q)) => (p, q),
+        None => (url, ""),
+    };
+
+    info!(
+        "HTTP {} request to {} from {:?}",
+        method,
+        url,
+        request.remote_addr()
+    );
+
+    let status_code: tiny_http::StatusCode;
+    let payload: String;
+    let mut content_type = constants::MIME_TEXT;
+
+    if method == &tiny_http::Method::Get {
+        match path {
+            "/" => {
+                status_code = tiny_http::StatusCode::from(302_i16);
+                payload = constants::ROOT_HTML.to_string();
+                content_type = constants::MIME_HTML;
+            }
+            constants::METRICS_PATH => {
+                let metrics = exporter::fetch(cfg);
+                status_code = tiny_http::StatusCode::from(200_i16);
+                if wants_openmetrics(&request) {
+                    payload = openmetrics::encode(&metrics);
+                    content_type = constants::MIME_OPENMETRICS;
+                } else {
+                    payload = exporter::encode_text(&metrics);
+                    content_type = constants::MIME_PROMETHEUS_TEXT;
+                }
+            }
+            constants::HEALTH_PATH => {
+                status_code = tiny_http::StatusCode::from(200_i16);
+                payload = constants::REPLY_HEALTHY.to_string();
+            }
+            constants::READY_PATH => {
+                if status_page::is_ready(cfg) {
+                    status_code = tiny_http::StatusCode::from(200_i16);
+                    payload = constants::REPLY_READY.to_string();
+                } else {
+                    status_code = tiny_http::StatusCode::from(503_i16);
+                    payload = constants::REPLY_NOT_READY.to_string();
+                }
+            }
+            constants::STATUS_PATH => {
+                status_code = tiny_http::StatusCode::from(200_i16);
+                if wants_json(&request, query) {
+                    payload = status_page::render_json(cfg);
+                    content_type = constants::MIME_JSON;
+                } else {
+                    payload = status_page::render_html(cfg);
+                    content_type = constants::MIME_HTML;
+                }
+            }
+            constants::API_POOLS_PATH
+            | constants::API_MACHINES_PATH
+            | constants::API_SESSIONS_PATH => {
+                let (code, reply) = inventory::handle(cfg, path, query);
+                status_code = tiny_http::StatusCode::from(code);
+                payload = reply;
+                content_type = constants::MIME_JSON;
+            }
+            _ => {
+                status_code = tiny_http::StatusCode::from(404_i16);
+                payload = constants::REPLY_NOT_FOUND.to_string();
+            }
+        };
+    } else if method == &tiny_http::Method::Post && path == constants::RELOAD_PATH && !enable_reload
+    {
+        warn!("reload requested by HTTP, but --enable-reload is not set");
+        status_code = tiny_http::StatusCode::from(403_i16);
+        payload = constants::REPLY_RELOAD_DISABLED.to_string();
+    } else if method == &tiny_http::Method::Post && path == constants::RELOAD_PATH {
+        info!("reloading configuration");
+        match configuration::reload(shared_cfg, config_file) {
+            Ok(_) => {
+                status_code = tiny_http::StatusCode::from(200_i16);
+                payload = constants::REPLY_RELOADED.to_string();
+            }
+            Err(e) => {
+                error!(
+                    "can't reload configuration, keeping current configuration: {}",
+                    e
+                );
+                status_code = tiny_http::StatusCode::from(500_i16);
+                payload = format!("Can't reload configuration: {}", e);
+            }
+        };
+    } else {
+        status_code = tiny_http::StatusCode::from(405_i16);
+        payload = constants::REPLY_METHOD_NOT_ALLOWED.to_string();
+    }
+
+    let mut headers: Vec<tiny_http::Header> = vec![
+        tiny_http::Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes()).unwrap(),
+        tiny_http::Header::from_bytes(&b"Vary"[..], &b"Accept, Accept-Encoding"[..]).unwrap(),
+        tiny_http::Header::from_bytes(&b"X-Clacks-Overhead"[..], &b"GNU Terry Pratchett"[..])
+            .unwrap(),
+    ];
+
+    let mut body = payload.into_bytes();
+    if wants_gzip(&request) {
+        match gzip(&body) {
+            Ok(v) => {
+                body = v;
+                headers.push(
+                    tiny_http::Header::from_bytes(&b"Content-Encoding"[..], &b"gzip"[..]).unwrap(),
+                );
+            }
+            Err(e) => error!("can't compress response, sending it uncompressed: {}", e),
+        };
+    }
+
+    if let Err(e) = request.respond(tiny_http::Response::new(
+        status_code,
+        headers,
+        body.as_slice(),
+        Some(body.len()),
+        None,
+    )) {
+        error!("Can't send response to client: {}", e);
+    }
+}
@@ -1,15 +1,12 @@
+use crate::collector::{self, Metrics};
 use crate::configuration;
 use crate::constants;
 use crate::data;
-use crate::exporter;
-use crate::globals;
-use crate::horizon;
+use crate::horizon::HorizonClient;
 
-use lazy_static::lazy_static;
 use log::{debug, warn};
 use std::collections::HashMap;
 use std::error::Error;
-use std::sync::Mutex;
 
 // Map pool id -> state, count
 type SessionMap = HashMap<String, HashMap<String, i64>>;
@@ -23,6 +20,14 @@ type SessionProtocolMap = HashMap<String, HashMap<String, i64>>;
 // Map pool id -> session type, count
 type SessionTypeMap = HashMap<String, HashMap<String, i64>>;
 
+#[derive(Default)]
+pub struct SessionCounters {
+    pool_sessions: SessionMap,
+    agent_versions: AgentVersionMap,
+    pool_protocols: SessionProtocolMap,
+    types: SessionTypeMap,
+}
+
 fn flush_session_type_map(m: &mut SessionTypeMap) {
     for (k1, v1) in m.iter_mut() {
         for (k2, v2) in v1.iter_mut() {
@@ -65,14 +70,10 @@ fn initialise_session_protocol_map(m: &mut SessionProtocolMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    pm.insert(constants::LC_PROTOCOL_PCOIP.to_string(), 0);
-    pm.insert(constants::LC_PROTOCOL_RDP.to_string(), 0);
-    pm.insert(constants::LC_PROTOCOL_BLAST.to_string(), 0);
-    pm.insert(constants::LC_PROTOCOL_CONSOLE.to_string(), 0);
-    pm.insert(constants::LC_PROTOCOL_UNKNOWN.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::SessionProtocol::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
 fn initialise_session_type_map(m: &mut SessionTypeMap, p: &str) {
@@ -81,11 +82,10 @@ fn initialise_session_type_map(m: &mut SessionTypeMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    pm.insert(constants::LC_TYPE_APPLICATION.to_string(), 0);
-    pm.insert(constants::LC_TYPE_DESKTOP.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::SessionType::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
 fn flush_session_map(m: &mut SessionMap) {
@@ -106,37 +106,32 @@ fn initialise_session_map(m: &mut SessionMap, p: &str) {
         p
     );
 
-    let pm = m
-        .entry(p.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    pm.insert(constants::LC_SESSION_CONNECTED.to_string(), 0);
-    pm.insert(constants::LC_SESSION_DISCONNECTED.to_string(), 0);
-    pm.insert(constants::LC_SESSION_PENDING.to_string(), 0);
+    let pm = m.entry(p.to_string()).or_default();
+    for v in data::SessionState::KNOWN {
+        pm.insert(v.label(), 0);
+    }
 }
 
+// Returns the sessions of the exported pools
 pub fn session_metric_update(
     cfg: &configuration::Configuration,
-    client: &mut reqwest::blocking::Client,
-    token: &str,
-) -> Result<(), Box<dyn Error>> {
-    lazy_static! {
-        static ref POOL_SESSIONS: Mutex<SessionMap> = Mutex::new(HashMap::new());
-        static ref AGENT_VERSIONS: Mutex<AgentVersionMap> = Mutex::new(HashMap::new());
-        static ref POOL_PROTOCOLS: Mutex<SessionProtocolMap> = Mutex::new(HashMap::new());
-        static ref TYPES: Mutex<SessionTypeMap> = Mutex::new(HashMap::new());
-    }
-    let mut pool_sessions = POOL_SESSIONS.lock().unwrap();
-    let mut agent_versions = AGENT_VERSIONS.lock().unwrap();
-    let mut pool_protocols = POOL_PROTOCOLS.lock().unwrap();
-    let mut types = TYPES.lock().unwrap();
+    client: &mut HorizonClient,
+    metrics: &Metrics,
+    counters: &mut SessionCounters,
+    dsktp_pools: &[data::DesktopPool],
+) -> Result<Vec<data::Session>, Box<dyn Error>> {
+    let SessionCounters {
+        pool_sessions,
+        agent_versions,
+        pool_protocols,
+        types,
+    } = counters;
 
     // flush existing counters to prevent reporting of stale data
-    flush_session_map(&mut pool_sessions);
-    flush_agent_version_map(&mut agent_versions);
-    flush_session_protocol_map(&mut pool_protocols);
-    flush_session_type_map(&mut types);
-
-    let dsktp_pools = globals::DESKTOP_POOLS.lock().unwrap().clone();
+    flush_session_map(pool_sessions);
+    flush_agent_version_map(agent_versions);
+    flush_session_protocol_map(pool_protocols);
+    flush_session_type_map(types);
 
     for dp in dsktp_pools {
         if cfg.horizon_api.skip_pools_set.contains(&dp.id) {
@@ -148,14 +143,15 @@ pub fn session_metric_update(
             continue;
         }
         if !pool_sessions.contains_key(&dp.id) {
-            initialise_session_map(&mut pool_sessions, &dp.id);
-            initialise_session_protocol_map(&mut pool_protocols, &dp.id);
-            initialise_session_type_map(&mut types, &dp.id);
+            initialise_session_map(pool_sessions, &dp.id);
+            initialise_session_protocol_map(pool_protocols, &dp.id);
+            initialise_session_type_map(types, &dp.id);
         }
     }
 
     debug!("sessions.rs:session_metric_update: getting list of current sessions");
-    let sessions = horizon::get_sessions(cfg, client, token)?;
+    let sessions = client.sessions()?;
+    let mut snapshot: Vec<data::Session> = Vec::new();
 
     for s in sessions.iter() {
         debug!(
@@ -165,6 +161,7 @@ pub fn session_metric_update(
         if let Some(dp_id) = &s.desktop_pool_id {
             if cfg.horizon_api.skip_pools_set.contains(dp_id) {
                 debug!(
+                    pool = dp_id.as_str();
                     "sessions.rs:session_metric_update: desktop pool id {} is in skip_pools list",
                     dp_id
                 );
@@ -174,138 +171,142 @@ pub fn session_metric_update(
             if !cfg.horizon_api.only_pools_set.is_empty()
                 && !cfg.horizon_api.only_pools_set.contains(dp_id)
             {
-                debug!("sessions.rs:session_metric_update: only_pools list is not empty and desktop pool id {} is not in only_pools list", dp_id);
+                debug!(pool = dp_id.as_str(); "sessions.rs:session_metric_update: only_pools list is not empty and desktop pool id {} is not in only_pools list", dp_id);
                 continue;
             }
 
-            set_desktop_pool_session_metrics(&mut pool_sessions, s, dp_id);
-            set_agent_version_metrics(&mut agent_versions, s, dp_id);
-            set_desktop_pool_session_protocol_metrics(&mut pool_protocols, s, dp_id);
-            set_desktop_pool_session_type_metrics(&mut types, s, dp_id);
+            set_desktop_pool_session_metrics(metrics, pool_sessions, s, dp_id);
+            set_agent_version_metrics(agent_versions, s, dp_id);
+            set_desktop_pool_session_protocol_metrics(metrics, pool_protocols, s, dp_id);
+            set_desktop_pool_session_type_metrics(metrics, types, s, dp_id);
+            snapshot.push(s.clone());
         } else {
             warn!("BUG: session id {} is not a desktop session", s.id);
         }
     }
 
-    prometheus_pool_sessions(&pool_sessions, &cfg.horizon_api);
-    prometheus_agent_versions(&agent_versions, &cfg.horizon_api);
-    prometheus_pool_session_protocols(&pool_protocols, &cfg.horizon_api);
-    prometheus_pool_session_types(&types, &cfg.horizon_api);
+    prometheus_pool_sessions(metrics, pool_sessions, &cfg.horizon_api);
+    prometheus_agent_versions(metrics, agent_versions, &cfg.horizon_api);
+    prometheus_pool_session_protocols(metrics, pool_protocols, &cfg.horizon_api);
+    prometheus_pool_session_types(metrics, types, &cfg.horizon_api);
 
-    Ok(())
+    Ok(snapshot)
 }
 
-fn prometheus_agent_versions(amap: &AgentVersionMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_agent_versions(
+    metrics: &Metrics,
+    amap: &AgentVersionMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, vcount) in amap.iter() {
         for (ver, count) in vcount.iter() {
-            exporter::AGENT_VERSIONS
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), ver])
+            let pool = cfg.user_defined_pool_uuid_resolve(pool);
+            metrics
+                .agent_versions
+                .with_label_values(&[&pool, ver])
                 .set(*count);
+            collector::set_info(&metrics.agent_version_info, &[&pool, ver], *count);
         }
     }
 }
 
-fn prometheus_pool_sessions(pmap: &SessionMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_pool_sessions(
+    metrics: &Metrics,
+    pmap: &SessionMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, scount) in pmap.iter() {
         for (state, count) in scount.iter() {
-            exporter::SESSIONS
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), state])
+            metrics
+                .sessions
+                .with_label_values(&[&cfg.user_defined_pool_uuid_resolve(pool), state])
                 .set(*count);
         }
     }
 }
 
 fn prometheus_pool_session_protocols(
+    metrics: &Metrics,
     pmap: &SessionProtocolMap,
     cfg: &configuration::HorizonAPIConfig,
 ) {
     for (pool, scount) in pmap.iter() {
         for (proto, count) in scount.iter() {
-            exporter::SESSION_PROTOCOLS
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), proto])
+            metrics
+                .session_protocols
+                .with_label_values(&[&cfg.user_defined_pool_uuid_resolve(pool), proto])
                 .set(*count);
         }
     }
 }
 
-fn prometheus_pool_session_types(pmap: &SessionTypeMap, cfg: &configuration::HorizonAPIConfig) {
+fn prometheus_pool_session_types(
+    metrics: &Metrics,
+    pmap: &SessionTypeMap,
+    cfg: &configuration::HorizonAPIConfig,
+) {
     for (pool, scount) in pmap.iter() {
         for (_type, count) in scount.iter() {
-            exporter::SESSION_TYPES
-                .with_label_values(&[&cfg.clone().user_defined_pool_uuid_resolve(pool), _type])
+            metrics
+                .session_types
+                .with_label_values(&[&cfg.user_defined_pool_uuid_resolve(pool), _type])
                 .set(*count);
         }
     }
 }
 
-fn set_desktop_pool_session_type_metrics(smap: &mut SessionTypeMap, s: &data::Session, id: &str) {
-    match s.session_type.as_str() {
-        constants::TYPE_APPLICATION | constants::TYPE_DESKTOP => {}
-        _ => {
-            warn!(
-                "skipping unknown state {} for session id {}",
-                s.session_state, s.id
-            );
-        }
-    };
-
-    let ps = smap
-        .entry(id.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    let lc_type = s.session_type.to_lowercase();
+fn set_desktop_pool_session_type_metrics(
+    metrics: &Metrics,
+    smap: &mut SessionTypeMap,
+    s: &data::Session,
+    id: &str,
+) {
+    if s.session_type.is_other() {
+        metrics.unknown_enum_value(
+            constants::FIELD_SESSION_TYPE,
+            s.session_type.as_str(),
+            &s.id,
+        );
+    }
 
-    *ps.entry(lc_type).or_insert(0) += 1;
+    let ps = smap.entry(id.to_string()).or_default();
+    *ps.entry(s.session_type.label()).or_insert(0) += 1;
 }
 
 fn set_desktop_pool_session_protocol_metrics(
+    metrics: &Metrics,
     smap: &mut SessionProtocolMap,
     s: &data::Session,
     id: &str,
 ) {
-    if let Some(v) = s.session_protocol.clone() {
-        match v.as_str() {
-            constants::PROTOCOL_PCOIP
-            | constants::PROTOCOL_RDP
-            | constants::PROTOCOL_BLAST
-            | constants::PROTOCOL_CONSOLE
-            | constants::PROTOCOL_UNKNOWN => {}
-            _ => {
-                warn!(
-                    "skipping unknown session protocol {} for session id {}",
-                    v, s.id
-                );
-            }
-        };
-        let vl = v.to_lowercase();
-        let ps = smap
-            .entry(id.to_string())
-            .or_insert_with(HashMap::<String, i64>::new);
-        *ps.entry(vl).or_insert(0) += 1;
+    if let Some(v) = &s.session_protocol {
+        if v.is_other() {
+            metrics.unknown_enum_value(constants::FIELD_SESSION_PROTOCOL, v.as_str(), &s.id);
+        }
+        let ps = smap.entry(id.to_string()).or_default();
+        *ps.entry(v.label()).or_insert(0) += 1;
     }
 }
 
 fn set_agent_version_metrics(amap: &mut AgentVersionMap, s: &data::Session, id: &str) {
-    let sm = amap.entry(id.to_string()).or_insert_with(HashMap::new);
+    let sm = amap.entry(id.to_string()).or_default();
     *sm.entry(s.agent_version.clone()).or_insert(0) += 1;
 }
 
-fn set_desktop_pool_session_metrics(smap: &mut SessionMap, s: &data::Session, id: &str) {
-    match s.session_state.as_str() {
-        constants::SESSION_CONNECTED
-        | constants::SESSION_DISCONNECTED
-        | constants::SESSION_PENDING => {}
-        _ => {
-            warn!(
-                "skipping unknown state {} for session id {}",
-                s.session_state, s.id
-            );
-        }
-    };
-
-    let ps = smap
-        .entry(id.to_string())
-        .or_insert_with(HashMap::<String, i64>::new);
-    let lc_state = s.session_state.to_lowercase();
+fn set_desktop_pool_session_metrics(
+    metrics: &Metrics,
+    smap: &mut SessionMap,
+    s: &data::Session,
+    id: &str,
+) {
+    if s.session_state.is_other() {
+        metrics.unknown_enum_value(
+            constants::FIELD_SESSION_STATE,
+            s.session_state.as_str(),
+            &s.id,
+        );
+    }
 
-    *ps.entry(lc_state).or_insert(0) += 1;
+    let ps = smap.entry(id.to_string()).or_default();
+    *ps.entry(s.session_state.label()).or_insert(0) += 1;
 }
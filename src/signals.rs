@@ -0,0 +1,46 @@
+use crate::configuration;
+use crate::globals;
+
+use log::{error, info, warn};
+use signal_hook::consts::signal::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::error::Error;
+use std::process;
+use std::sync::atomic::Ordering;
+use std::thread;
+
+pub fn start_handler(
+    cfg: configuration::SharedConfiguration,
+    config_file: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+
+    thread::Builder::new()
+        .name("signal-handler".to_string())
+        .spawn(move || {
+            for sig in signals.forever() {
+                match sig {
+                    SIGHUP => {
+                        info!("received SIGHUP, reloading configuration");
+                        if let Err(e) = configuration::reload(&cfg, config_file.as_deref()) {
+                            error!(
+                                "can't reload configuration, keeping current configuration: {}",
+                                e
+                            );
+                        }
+                    }
+                    SIGINT | SIGTERM => {
+                        // a second signal doesn't wait for the requests in progress
+                        if globals::SHUTDOWN.swap(true, Ordering::SeqCst) {
+                            warn!("received signal {} again, exiting immediately", sig);
+                            process::exit(1);
+                        }
+                        info!("received signal {}, shutting down", sig);
+                    }
+                    _ => {}
+                };
+            }
+        })?;
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use crate::configuration;
+use crate::constants;
+use crate::graphite;
+use crate::influxdb;
+use crate::measurement::Measurement;
+use crate::otlp;
+use crate::pushgateway;
+use crate::remote_write;
+use crate::status_page;
+
+use log::error;
+use prometheus::proto::MetricFamily;
+use std::error::Error;
+
+/// Data of one refresh passed to the outputs
+pub struct Collected {
+    /// Metrics of the registry, the same as served on `/metrics`
+    pub metrics: Vec<MetricFamily>,
+    /// Counts of the sessions and machines of the last collection
+    pub measurements: Vec<Measurement>,
+}
+
+/// Output receiving the collected metrics periodically
+pub trait Sink {
+    /// Name of the output, used for the status page and log messages
+    fn name(&self) -> &'static str;
+
+    /// Seconds between two pushes
+    fn interval(&self) -> u64;
+
+    /// Send the data, returns false if the delivery is reported later, e.g. by a queue
+    fn send(&mut self, data: &Collected) -> Result<bool, Box<dyn Error>>;
+}
+
+/// Sinks of all outputs of the configuration, the HTTP clients are created once per sink
+pub fn configured(cfg: &configuration::Configuration) -> Vec<Box<dyn Sink>> {
+    let default_interval = cfg
+        .exporter
+        .scrape_interval
+        .unwrap_or(constants::DEFAULT_SCRAPE_INTERVAL);
+    let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+
+    if let Some(pgw) = &cfg.pushgateway {
+        add(
+            &mut sinks,
+            constants::OUTPUT_PUSHGATEWAY,
+            pushgateway::Pushgateway::new(pgw, default_interval),
+        );
+    }
+
+    if let Some(rw) = &cfg.remote_write {
+        sinks.push(Box::new(remote_write::RemoteWrite::new(
+            rw,
+            default_interval,
+        )));
+    }
+
+    if let Some(influx) = &cfg.influxdb {
+        add(
+            &mut sinks,
+            constants::OUTPUT_INFLUXDB,
+            influxdb::InfluxDB::new(influx, default_interval),
+        );
+    }
+
+    if let Some(g) = &cfg.graphite {
+        sinks.push(Box::new(graphite::Graphite::new(g, default_interval)));
+    }
+
+    if let Some(o) = &cfg.otlp {
+        add(
+            &mut sinks,
+            constants::OUTPUT_OTLP,
+            otlp::Otlp::new(cfg, o, default_interval),
+        );
+    }
+
+    sinks
+}
+
+// Outputs without HTTP client are skipped until the configuration is reloaded
+fn add<S: Sink + 'static>(
+    sinks: &mut Vec<Box<dyn Sink>>,
+    name: &str,
+    sink: Result<S, Box<dyn Error>>,
+) {
+    match sink {
+        Ok(v) => sinks.push(Box::new(v)),
+        Err(e) => {
+            error!("can't create HTTP client for {}: {}", name, e);
+            status_page::output_failure(name, &e.to_string());
+        }
+    };
+}
@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+/// Result of the last runs of a collector or an output
+#[derive(Clone, Debug, Default)]
+pub struct CollectorStatus {
+    pub last_success: Option<i64>,
+    pub last_error: Option<String>,
+    pub last_error_time: Option<i64>,
+}
+
+impl CollectorStatus {
+    pub fn succeeded(&mut self) {
+        self.last_success = Some(now());
+        self.last_error = None;
+        self.last_error_time = None;
+    }
+
+    pub fn failed(&mut self, err: &str) {
+        self.last_error = Some(err.to_string());
+        self.last_error_time = Some(now());
+    }
+}
+
+/// State of the collections of a `Collector`, times are UNIX timestamps
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    /// Last collection without failed collectors
+    pub last_refresh: Option<i64>,
+    /// Last collection, successful or not
+    pub last_attempt: Option<i64>,
+    pub last_login: Option<i64>,
+    pub horizon_version: Option<String>,
+    pub horizon_build: Option<String>,
+    pub collectors: BTreeMap<String, CollectorStatus>,
+}
+
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
+
+impl Status {
+    pub(crate) fn set_login_success(&mut self) {
+        self.last_login = Some(now());
+    }
+
+    pub(crate) fn set_refresh(&mut self, success: bool) {
+        let now = now();
+        self.last_attempt = Some(now);
+        if success {
+            self.last_refresh = Some(now);
+        }
+    }
+
+    pub(crate) fn set_horizon_version(&mut self, version: Option<String>, build: Option<String>) {
+        self.horizon_version = version;
+        self.horizon_build = build;
+    }
+
+    pub(crate) fn collector_success(&mut self, name: &str) {
+        self.collectors
+            .entry(name.to_string())
+            .or_default()
+            .succeeded();
+    }
+
+    pub(crate) fn collector_failure(&mut self, name: &str, err: &str) {
+        self.collectors
+            .entry(name.to_string())
+            .or_default()
+            .failed(err);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::configuration;
+use crate::constants;
+use crate::exporter;
+use crate::globals;
+
+use chrono::TimeZone;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use vmware_horizon_exporter::CollectorStatus;
+
+#[derive(Serialize, Clone, Debug)]
+struct StatusReport {
+    version: String,
+    ready: bool,
+    last_refresh: Option<String>,
+    last_attempt: Option<String>,
+    last_login: Option<String>,
+    horizon_version: Option<String>,
+    horizon_build: Option<String>,
+    configuration: ConfigurationSummary,
+    collectors: BTreeMap<String, CollectorReport>,
+    outputs: BTreeMap<String, CollectorReport>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct CollectorReport {
+    last_success: Option<String>,
+    last_error: Option<String>,
+    last_error_time: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+struct ConfigurationSummary {
+    url: String,
+    user: String,
+    domain: String,
+    insecure_ssl: bool,
+    ca_file: Option<String>,
+    timeout: u64,
+    only_pools: Vec<String>,
+    skip_pools: Vec<String>,
+    pool_uuid_map: HashMap<String, String>,
+    scrape_interval: u64,
+    ready_intervals: u64,
+}
+
+fn now() -> i64 {
+    chrono::Local::now().timestamp()
+}
+
+pub fn format_timestamp(ts: Option<i64>) -> Option<String> {
+    let ts = ts?;
+    chrono::Local
+        .timestamp_opt(ts, 0)
+        .single()
+        .map(|t| t.to_rfc3339())
+}
+
+pub fn output_success(name: &str) {
+    globals::OUTPUTS
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .succeeded();
+}
+
+pub fn output_failure(name: &str, err: &str) {
+    globals::OUTPUTS
+        .lock()
+        .unwrap()
+        .entry(name.to_string())
+        .or_default()
+        .failed(err);
+}
+
+pub fn is_ready(cfg: &configuration::Configuration) -> bool {
+    let status = exporter::COLLECTOR.status();
+    let last_login = match status.last_login {
+        Some(v) => v,
+        None => return false,
+    };
+    let interval = cfg
+        .exporter
+        .scrape_interval
+        .unwrap_or(constants::DEFAULT_SCRAPE_INTERVAL);
+    let intervals = cfg
+        .exporter
+        .ready_intervals
+        .unwrap_or(constants::DEFAULT_READY_INTERVALS);
+
+    now() - last_login <= (interval * intervals) as i64
+}
+
+fn collector_reports(m: &BTreeMap<String, CollectorStatus>) -> BTreeMap<String, CollectorReport> {
+    let mut result = BTreeMap::new();
+    for (name, cs) in m.iter() {
+        result.insert(
+            name.clone(),
+            CollectorReport {
+                last_success: format_timestamp(cs.last_success),
+                last_error: cs.last_error.clone(),
+                last_error_time: format_timestamp(cs.last_error_time),
+            },
+        );
+    }
+    result
+}
+
+fn build_report(cfg: &configuration::Configuration) -> StatusReport {
+    let ready = is_ready(cfg);
+    let status = exporter::COLLECTOR.status();
+
+    let collectors = collector_reports(&status.collectors);
+    let outputs = collector_reports(&globals::OUTPUTS.lock().unwrap());
+
+    StatusReport {
+        version: constants::VERSION.to_string(),
+        ready,
+        last_refresh: format_timestamp(status.last_refresh),
+        last_attempt: format_timestamp(status.last_attempt),
+        last_login: format_timestamp(status.last_login),
+        horizon_version: status.horizon_version,
+        horizon_build: status.horizon_build,
+        configuration: ConfigurationSummary {
+            url: cfg.horizon_api.url_description(),
+            user: cfg.horizon_api.user.clone(),
+            domain: cfg.horizon_api.domain.clone(),
+            insecure_ssl: cfg.horizon_api.insecure_ssl.unwrap_or_default(),
+            ca_file: cfg.horizon_api.ca_file.clone(),
+            timeout: cfg
+                .horizon_api
+                .timeout
+                .unwrap_or(constants::DEFAULT_TIMEOUT),
+            only_pools: cfg.horizon_api.only_pools.clone().unwrap_or_default(),
+            skip_pools: cfg.horizon_api.skip_pools.clone().unwrap_or_default(),
+            pool_uuid_map: cfg.horizon_api.pool_uuid_map.clone().unwrap_or_default(),
+            scrape_interval: cfg
+                .exporter
+                .scrape_interval
+                .unwrap_or(constants::DEFAULT_SCRAPE_INTERVAL),
+            ready_intervals: cfg
+                .exporter
+                .ready_intervals
+                .unwrap_or(constants::DEFAULT_READY_INTERVALS),
+        },
+        collectors,
+        outputs,
+    }
+}
+
+pub fn render_json(cfg: &configuration::Configuration) -> String {
+    let report = build_report(cfg);
+    match serde_json::to_string_pretty(&report) {
+        Ok(v) => v,
+        Err(e) => format!("{{\"error\": \"{}\"}}", e),
+    }
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_row(key: &str, value: &str) -> String {
+    format!(
+        "<tr><th>{}</th><td>{}</td></tr>\n",
+        html_escape(key),
+        html_escape(value)
+    )
+}
+
+pub fn render_html(cfg: &configuration::Configuration) -> String {
+    let report = build_report(cfg);
+    let never = "-".to_string();
+    let mut html = String::new();
+
+    html.push_str("<html>\n<head><title>VMWare Horizon exporter status</title></head>\n<body>\n<h1>VMWare Horizon exporter status</h1>\n");
+
+    html.push_str("<h2>Exporter</h2>\n<table>\n");
+    html.push_str(&html_row("Version", &report.version));
+    html.push_str(&html_row("Ready", if report.ready { "yes" } else { "no" }));
+    html.push_str(&html_row(
+        "Last successful refresh",
+        report.last_refresh.as_ref().unwrap_or(&never),
+    ));
+    html.push_str(&html_row(
+        "Last refresh attempt",
+        report.last_attempt.as_ref().unwrap_or(&never),
+    ));
+    html.push_str(&html_row(
+        "Last successful login",
+        report.last_login.as_ref().unwrap_or(&never),
+    ));
+    html.push_str(&html_row(
+        "Horizon version",
+        report.horizon_version.as_ref().unwrap_or(&never),
+    ));
+    html.push_str(&html_row(
+        "Horizon build",
+        report.horizon_build.as_ref().unwrap_or(&never),
+    ));
+    html.push_str("</table>\n");
+
+    let c = &report.configuration;
+    html.push_str("<h2>Configuration</h2>\n<table>\n");
+    html.push_str(&html_row("URL", &c.url));
+    html.push_str(&html_row("User", &c.user));
+    html.push_str(&html_row("Domain", &c.domain));
+    html.push_str(&html_row("Insecure SSL", &c.insecure_ssl.to_string()));
+    html.push_str(&html_row("CA file", c.ca_file.as_ref().unwrap_or(&never)));
+    html.push_str(&html_row("Timeout", &c.timeout.to_string()));
+    html.push_str(&html_row("Only pools", &c.only_pools.join(", ")));
+    html.push_str(&html_row("Skip pools", &c.skip_pools.join(", ")));
+    let mut pool_map: Vec<String> = c
+        .pool_uuid_map
+        .iter()
+        .map(|(k, v)| format!("{} = {}", k, v))
+        .collect();
+    pool_map.sort();
+    html.push_str(&html_row("Pool UUID map", &pool_map.join(", ")));
+    html.push_str(&html_row("Scrape interval", &c.scrape_interval.to_string()));
+    html.push_str(&html_row("Ready intervals", &c.ready_intervals.to_string()));
+    html.push_str("</table>\n");
+
+    html.push_str(&html_collector_table(
+        "Collectors",
+        "Collector",
+        &report.collectors,
+    ));
+    if !report.outputs.is_empty() {
+        html.push_str(&html_collector_table("Outputs", "Output", &report.outputs));
+    }
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+fn html_collector_table(
+    title: &str,
+    column: &str,
+    reports: &BTreeMap<String, CollectorReport>,
+) -> String {
+    let never = "-".to_string();
+    let mut html = format!(
+        "<h2>{}</h2>\n<table>\n<tr><th>{}</th><th>Last success</th><th>Last error</th><th>Last error time</th></tr>\n",
+        title, column
+    );
+    for (name, cs) in reports.iter() {
+        html.push_str(&format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+            html_escape(name),
+            html_escape(cs.last_success.as_ref().unwrap_or(&never)),
+            html_escape(cs.last_error.as_ref().unwrap_or(&never)),
+            html_escape(cs.last_error_time.as_ref().unwrap_or(&never)),
+        ));
+    }
+    html.push_str("</table>\n");
+    html
+}
@@ -0,0 +1,362 @@
+use crate::configuration;
+use crate::constants;
+
+use log::debug;
+use sha2::{Digest, Sha256};
+use simple_error::bail;
+use std::error::Error;
+use std::fs;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// Certificate settings shared by the Horizon REST API and the HTTP outputs
+#[derive(Default)]
+pub struct Certificates<'a> {
+    pub insecure_ssl: bool,
+    pub ca_file: Option<&'a str>,
+    pub ca_dir: Option<&'a str>,
+    pub client_cert: Option<&'a str>,
+    pub client_key: Option<&'a str>,
+    pub client_pkcs12: Option<&'a str>,
+    pub client_pkcs12_password: Option<&'a str>,
+}
+
+impl<'a> From<&'a configuration::HorizonAPIConfig> for Certificates<'a> {
+    fn from(cfg: &'a configuration::HorizonAPIConfig) -> Self {
+        Certificates {
+            insecure_ssl: cfg.insecure_ssl.unwrap_or_default(),
+            ca_file: cfg.ca_file.as_deref(),
+            ca_dir: cfg.ca_dir.as_deref(),
+            client_cert: cfg.client_cert.as_deref(),
+            client_key: cfg.client_key.as_deref(),
+            client_pkcs12: cfg.client_pkcs12.as_deref(),
+            client_pkcs12_password: cfg.client_pkcs12_password.as_deref(),
+        }
+    }
+}
+
+impl<'a> From<&'a configuration::HttpSinkConfig> for Certificates<'a> {
+    fn from(cfg: &'a configuration::HttpSinkConfig) -> Self {
+        Certificates {
+            insecure_ssl: cfg.insecure_ssl.unwrap_or_default(),
+            ca_file: cfg.ca_file.as_deref(),
+            ca_dir: cfg.ca_dir.as_deref(),
+            client_cert: cfg.client_cert.as_deref(),
+            client_key: cfg.client_key.as_deref(),
+            client_pkcs12: cfg.client_pkcs12.as_deref(),
+            client_pkcs12_password: cfg.client_pkcs12_password.as_deref(),
+        }
+    }
+}
+
+fn connector_builder(
+    certs: &Certificates,
+) -> Result<native_tls::TlsConnectorBuilder, Box<dyn Error>> {
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if certs.insecure_ssl {
+        builder
+            .danger_accept_invalid_hostnames(true)
+            .danger_accept_invalid_certs(true);
+    }
+
+    if let Some(f) = certs.ca_file {
+        for c in read_ca_bundle(f)? {
+            builder.add_root_certificate(c);
+        }
+    }
+
+    if let Some(d) = certs.ca_dir {
+        for c in read_ca_dir(d)? {
+            builder.add_root_certificate(c);
+        }
+    }
+
+    if let Some(id) = read_identity(certs)? {
+        builder.identity(id);
+    }
+
+    Ok(builder)
+}
+
+fn build(
+    builder: native_tls::TlsConnectorBuilder,
+) -> Result<native_tls::TlsConnector, Box<dyn Error>> {
+    match builder.build() {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("can't create TLS connector: {}", e),
+    }
+}
+
+/// TLS connector for the Horizon REST API
+///
+/// The HTTP clients configure native-tls directly, because reqwest doesn't support TLS 1.3 as
+/// minimal version with the native-tls backend.
+pub fn horizon_connector(
+    cfg: &configuration::HorizonAPIConfig,
+) -> Result<native_tls::TlsConnector, Box<dyn Error>> {
+    let mut builder = connector_builder(&Certificates::from(cfg))?;
+
+    let min_tls_version = cfg
+        .min_tls_version
+        .as_deref()
+        .unwrap_or(constants::DEFAULT_MIN_TLS_VERSION);
+    builder.min_protocol_version(Some(parse_tls_version(min_tls_version)?));
+
+    build(builder)
+}
+
+/// TLS connector for the HTTP outputs, with the same certificate settings as the Horizon REST API
+pub fn sink_connector(
+    cfg: &configuration::HttpSinkConfig,
+) -> Result<native_tls::TlsConnector, Box<dyn Error>> {
+    build(connector_builder(&Certificates::from(cfg))?)
+}
+
+// Split a PEM bundle into the certificates
+fn pem_certificates(data: &str) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut rest = data;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let end = match rest[start..].find(PEM_END) {
+            Some(v) => start + v + PEM_END.len(),
+            None => break,
+        };
+        result.push(rest[start..end].to_string());
+        rest = &rest[end..];
+    }
+    result
+}
+
+/// Read all certificates of a PEM file
+pub fn read_ca_bundle(ca_file: &str) -> Result<Vec<native_tls::Certificate>, Box<dyn Error>> {
+    let data = match fs::read_to_string(ca_file) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read CA file {}: {}", ca_file, e),
+    };
+
+    let mut result = Vec::new();
+    for pem in pem_certificates(&data) {
+        match native_tls::Certificate::from_pem(pem.as_bytes()) {
+            Ok(v) => result.push(v),
+            Err(e) => bail!("can't decode CA data of {} as PEM format: {}", ca_file, e),
+        };
+    }
+    if result.is_empty() {
+        bail!("no certificate found in CA file {}", ca_file);
+    }
+
+    debug!(
+        "tls.rs:read_ca_bundle: {} certificates in {}",
+        result.len(),
+        ca_file
+    );
+    Ok(result)
+}
+
+/// Read all certificates of the `.pem` and `.crt` files in a directory
+pub fn read_ca_dir(ca_dir: &str) -> Result<Vec<native_tls::Certificate>, Box<dyn Error>> {
+    let entries = match fs::read_dir(ca_dir) {
+        Ok(v) => v,
+        Err(e) => bail!("can't read CA directory {}: {}", ca_dir, e),
+    };
+
+    let mut files: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && matches!(
+                    p.extension().and_then(|e| e.to_str()),
+                    Some("pem") | Some("crt")
+                )
+        })
+        .collect();
+    files.sort();
+
+    let mut result = Vec::new();
+    for f in files {
+        result.append(&mut read_ca_bundle(&f.to_string_lossy())?);
+    }
+    if result.is_empty() {
+        bail!("no certificate found in CA directory {}", ca_dir);
+    }
+    Ok(result)
+}
+
+fn read_file(f: &str, what: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    match fs::read(Path::new(f)) {
+        Ok(v) => Ok(v),
+        Err(e) => bail!("can't read {} {}: {}", what, f, e),
+    }
+}
+
+/// Client certificate for mutual TLS, either a PEM certificate and PKCS#8 key or PKCS#12
+pub fn read_identity(certs: &Certificates) -> Result<Option<native_tls::Identity>, Box<dyn Error>> {
+    if let Some(p12) = certs.client_pkcs12 {
+        let der = read_file(p12, "PKCS#12 file")?;
+        let password = certs.client_pkcs12_password.unwrap_or_default();
+        return match native_tls::Identity::from_pkcs12(&der, password) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => bail!("can't decode PKCS#12 file {}: {}", p12, e),
+        };
+    }
+
+    if let (Some(cert), Some(key)) = (certs.client_cert, certs.client_key) {
+        let cert_pem = read_file(cert, "client certificate")?;
+        let key_pem = read_file(key, "client key")?;
+        return match native_tls::Identity::from_pkcs8(&cert_pem, &key_pem) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => bail!(
+                "can't decode client certificate {} and PKCS#8 key {}: {}",
+                cert,
+                key,
+                e
+            ),
+        };
+    }
+
+    Ok(None)
+}
+
+pub fn parse_tls_version(v: &str) -> Result<native_tls::Protocol, Box<dyn Error>> {
+    match v {
+        "1.0" => Ok(native_tls::Protocol::Tlsv10),
+        "1.1" => Ok(native_tls::Protocol::Tlsv11),
+        "1.2" => Ok(native_tls::Protocol::Tlsv12),
+        "1.3" => Ok(native_tls::Protocol::Tlsv13),
+        _ => bail!(
+            "unsupported TLS version {}, supported versions are 1.0, 1.1, 1.2 and 1.3",
+            v
+        ),
+    }
+}
+
+/// Normalise a SHA-256 fingerprint to lower case hex digits without separators
+pub fn parse_fingerprint(v: &str) -> Result<String, Box<dyn Error>> {
+    let fp: String = v
+        .chars()
+        .filter(|c| *c != ':')
+        .collect::<String>()
+        .to_lowercase();
+    if fp.len() != 64 || !fp.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!(
+            "invalid SHA-256 fingerprint {}, expecting 64 hex digits optionally separated by colons",
+            v
+        );
+    }
+    Ok(fp)
+}
+
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Fail if the server certificate of a response doesn't match one of the pinned fingerprints
+pub fn verify_pin(
+    response: &reqwest::blocking::Response,
+    pins: &[String],
+) -> Result<(), Box<dyn Error>> {
+    if pins.is_empty() {
+        return Ok(());
+    }
+
+    let der = match response
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|t| t.peer_certificate())
+    {
+        Some(v) => v,
+        None => bail!(
+            "no server certificate received from {} to verify the pinned certificates",
+            response.url()
+        ),
+    };
+
+    let fp = fingerprint(der);
+    if !pins.contains(&fp) {
+        bail!(
+            "server certificate of {} with SHA-256 fingerprint {} is not pinned",
+            response.url(),
+            fp
+        );
+    }
+    Ok(())
+}
+
+/// Modification times of the certificate and key files, the HTTP clients are rebuilt if
+/// one of them changes
+pub fn file_stamp(cfg: &configuration::HorizonAPIConfig) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files: Vec<PathBuf> = [
+        &cfg.ca_file,
+        &cfg.client_cert,
+        &cfg.client_key,
+        &cfg.client_pkcs12,
+    ]
+    .into_iter()
+    .flatten()
+    .map(PathBuf::from)
+    .collect();
+
+    if let Some(d) = &cfg.ca_dir {
+        files.push(PathBuf::from(d));
+        if let Ok(entries) = fs::read_dir(d) {
+            let mut certs: Vec<PathBuf> =
+                entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            certs.sort();
+            files.append(&mut certs);
+        }
+    }
+
+    files
+        .into_iter()
+        .map(|f| {
+            let mtime = fs::metadata(&f).and_then(|m| m.modified()).ok();
+            (f, mtime)
+        })
+        .collect()
+}
+
+/// Address to connect to if the TLS server name differs from the host of the URL
+///
+/// Returns the URL with the TLS server name as host and the socket address of the original
+/// host. reqwest uses the host of the URL for SNI and the certificate validation.
+pub fn server_name_override(
+    url: &str,
+    server_name: &str,
+) -> Result<(String, Option<SocketAddr>), Box<dyn Error>> {
+    let mut parsed = reqwest::Url::parse(url)?;
+    let host = match parsed.host_str() {
+        Some(v) => v.to_string(),
+        None => bail!("URL {} contains no host", url),
+    };
+    if host.eq_ignore_ascii_case(server_name) {
+        return Ok((url.to_string(), None));
+    }
+
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addr = match (host.trim_matches(|c| c == '[' || c == ']'), port)
+        .to_socket_addrs()?
+        .next()
+    {
+        Some(v) => v,
+        None => bail!("can't resolve address of {}", host),
+    };
+    parsed.set_host(Some(server_name))?;
+
+    debug!(
+        "tls.rs:server_name_override: connecting to {} for {}",
+        addr, parsed
+    );
+    Ok((
+        parsed.as_str().trim_end_matches('/').to_string(),
+        Some(addr),
+    ))
+}
@@ -17,7 +17,8 @@ Public License Version 3. (http://www.gnu.org/copyleft/gpl.html)
 
 pub fn show_usage() {
     show_version();
-    println!("Usage: {} [-D|--debug] [-V|--version] [-Q|--quiet] -c <cfg>|--config=<cfg> [-h|--help] [-l <addr>|--listen=<addr>]
+    println!("Usage: {} [-D|--debug] [-V|--version] [-Q|--quiet] [-c <cfg>|--config=<cfg>] [-h|--help] [-l <addr>|--listen=<addr>] [--enable-reload] [--check-config [--online]] [--once [--output=<file>]] [--record=<dir>|--replay=<dir>] [--log-format=text|json] [--log-target=<target>] [--log-level=<levels>] [--log-max-size=<size>] [--log-max-files=<count>]
+       {} [-c <cfg>|--config=<cfg>] [--format=csv|json] [--output=<file>] report
 
     -D              Enable debug output
     --debug
@@ -28,13 +29,65 @@ pub fn show_usage() {
     -Q              Only log warning and error messages
     --quiet
 
-    -c <cfg>        Path to configuration file
-    --config=<cfg>
+    --check-config  Parse and validate the configuration, report unknown
+                    keys and exit
+
+    --online        Together with --check-config: login to Horizon and
+                    list the pools that would be exported
+
+    --once          Fetch metrics once, print them and exit. The exit code
+                    is 1 if the login or one of the collectors failed
+
+    --output=<file> Together with --once or report: write metrics or the
+                    report to <file> instead of standard output
+
+    --format=<fmt>  Together with report: format of the report, csv or json
+                    Default: csv
+
+    --record=<dir>  Save responses of the Horizon REST API to <dir>,
+                    tokens and passwords are redacted
+
+    --replay=<dir>  Use responses saved by --record in <dir> instead of
+                    sending requests to the Horizon REST API
+
+    --log-format=<fmt>
+                    Format of log messages, text or json
+                    Default: text
+
+    --log-target=<target>
+                    Write log messages to stdout, stderr, syslog, journald or
+                    file:<path>. Default: stdout, stderr together with --once
+                    or report
+
+    --log-level=<levels>
+                    Comma separated list of log levels, either a level or
+                    <module>=<level>, e.g. info,http=debug
+
+    --log-max-size=<size>
+                    Together with --log-target=file:<path>: rotate the log
+                    file after <size> MiB, 0 disables the rotation
+                    Default: {}
+
+    --log-max-files=<count>
+                    Together with --log-target=file:<path>: number of rotated
+                    log files to keep. Default: {}
+
+    -c <cfg>        Path to configuration file, optional if all mandatory
+    --config=<cfg>  settings are set by environment variables
 
     -h              Show help text
     --help
 
-    -l <addr>       Listen on <addr> for metric scrapes
-    --listen=<addr> Default: {}
-", constants::NAME, constants::DEFAULT_LISTEN_ADDR);
+    -l <addr>       Listen on <addr> for metric scrapes, overrides the
+    --listen=<addr> listen setting of the configuration
+                    Default: {}
+
+    --enable-reload Allow reloading the configuration by a POST request
+                    to /-/reload, SIGHUP always reloads the configuration
+
+Commands:
+    report          Login to Horizon and write a report of all machines of
+                    the exported pools with pool, state, operating system,
+                    agent version, assigned users and session state
+", constants::NAME, constants::NAME, constants::DEFAULT_LOG_MAX_SIZE, constants::DEFAULT_LOG_MAX_FILES, constants::DEFAULT_LISTEN_ADDR);
 }
@@ -0,0 +1,658 @@
+// In-process mock of the Horizon REST API used by the integration tests
+#![allow(dead_code)]
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+pub const USER: &str = "exporter";
+pub const DOMAIN: &str = "example";
+pub const PASSWORD: &str = "secret";
+const ACCESS_TOKEN: &str = "mock-access-token";
+const REFRESH_TOKEN: &str = "mock-refresh-token";
+
+#[derive(Clone, Debug, Default)]
+pub struct HorizonData {
+    pub pools: Vec<Value>,
+    pub machines: Vec<Value>,
+    pub sessions: Vec<Value>,
+    // Endpoints (paths without query) answering with an error response
+    pub failing: HashMap<String, u16>,
+    // Endpoints answering the first n requests with an error response, e.g. (503, 2)
+    pub flaky: HashMap<String, (u16, usize)>,
+    // Endpoints answering after a delay
+    pub delayed: HashMap<String, std::time::Duration>,
+    // Version reported by the environment properties, 8.10.0 if not set
+    pub horizon_version: Option<String>,
+    // Highest version of the inventory endpoints, e.g. "machines" -> 2, 1 if not set
+    pub api_versions: HashMap<String, u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Calls {
+    pub logins: usize,
+    pub logouts: usize,
+    pub refreshes: usize,
+    pub session_filters: Vec<String>,
+    // Paths of the requests to the inventory endpoints
+    pub inventory_paths: Vec<String>,
+    // Number of requests per path
+    pub requests: HashMap<String, usize>,
+    // Requests received as HTTP proxy and the last Proxy-Authorization header
+    pub proxied: usize,
+    pub proxy_authorization: Option<String>,
+}
+
+pub struct MockHorizon {
+    server: Arc<tiny_http::Server>,
+    pub url: String,
+    pub calls: Arc<Mutex<Calls>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for MockHorizon {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(h) = self.handle.take() {
+            let _ = h.join();
+        }
+    }
+}
+
+// Pools, machines and sessions shared by the integration tests
+pub fn inventory() -> HorizonData {
+    HorizonData {
+        pools: vec![pool("pool-1", "Pool 1"), pool("pool-2", "Pool 2")],
+        machines: vec![
+            machine("m1", "pool-1", "AVAILABLE", "WINDOWS_10", "BIT_64", &[]),
+            machine(
+                "m2",
+                "pool-1",
+                "CONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-1"],
+            ),
+            machine(
+                "m3",
+                "pool-1",
+                "DISCONNECTED",
+                "WINDOWS_11",
+                "BIT_64",
+                &["S-2"],
+            ),
+            machine("m4", "pool-2", "MAINTENANCE", "LINUX_UBUNTU", "BIT_64", &[]),
+            machine(
+                "m5",
+                "pool-2",
+                "AGENT_UNREACHABLE",
+                "WINDOWS_10",
+                "BIT_32",
+                &[],
+            ),
+        ],
+        sessions: vec![
+            session("s1", "S-1", "m2", "pool-1", "CONNECTED", "BLAST"),
+            session("s2", "S-2", "m3", "pool-1", "DISCONNECTED", "PCOIP"),
+        ],
+        ..Default::default()
+    }
+}
+
+pub fn pool(id: &str, name: &str) -> Value {
+    json!({ "id": id, "name": name, "enabled": true })
+}
+
+pub fn machine(id: &str, pool: &str, state: &str, os: &str, arch: &str, users: &[&str]) -> Value {
+    json!({
+        "id": id,
+        "name": id,
+        "desktop_pool_id": pool,
+        "operating_system": os,
+        "operating_system_architecture": arch,
+        "state": state,
+        "type": "MANAGED_VIRTUAL_MACHINE",
+        "user_ids": users,
+    })
+}
+
+pub fn session(
+    id: &str,
+    user: &str,
+    machine: &str,
+    pool: &str,
+    state: &str,
+    protocol: &str,
+) -> Value {
+    json!({
+        "id": id,
+        "user_id": user,
+        "machine_id": machine,
+        "desktop_pool_id": pool,
+        "agent_version": "8.10.0",
+        "session_type": "DESKTOP",
+        "session_state": state,
+        "session_protocol": protocol,
+    })
+}
+
+// Error in the shape of data::ErrorResponse
+fn error_response(status: &str, key: &str, message: &str) -> Value {
+    json!({
+        "status": status,
+        "timestamp": 1_700_000_000_000_i64,
+        "errors": [ { "error_key": key, "error_message": message } ],
+    })
+}
+
+fn machine_id_filter(query: &str) -> Option<String> {
+    let raw = query.split('&').find_map(|q| q.strip_prefix("filter="))?;
+    let decoded = urlencoding::decode(raw).ok()?;
+    let filter: Value = serde_json::from_str(&decoded).ok()?;
+    filter["filters"]
+        .as_array()?
+        .iter()
+        .find(|f| f["name"] == "machine_id")
+        .and_then(|f| f["value"].as_str())
+        .map(|s| s.to_string())
+}
+
+// Map a supported version of an inventory endpoint to the v1 path
+fn inventory_path(data: &HorizonData, calls: &Mutex<Calls>, path: &str) -> Option<String> {
+    let rest = path.strip_prefix("/rest/inventory/v")?;
+    let (version, resource) = rest.split_once('/')?;
+    let version: u32 = version.parse().ok()?;
+    calls.lock().unwrap().inventory_paths.push(path.to_string());
+    if version > *data.api_versions.get(resource).unwrap_or(&1) {
+        return None;
+    }
+    Some(format!("/rest/inventory/v1/{}", resource))
+}
+
+fn handle(data: &HorizonData, calls: &Mutex<Calls>, mut request: tiny_http::Request) {
+    let mut url = request.url().to_string();
+    // the mock is also used as HTTP proxy, proxied requests contain the absolute URL
+    if let Some(rest) = url.strip_prefix("http://") {
+        let mut c = calls.lock().unwrap();
+        c.proxied += 1;
+        c.proxy_authorization = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Proxy-Authorization"))
+            .map(|h| h.value.to_string());
+        url = rest
+            .find('/')
+            .map(|i| rest[i..].to_string())
+            .unwrap_or_default();
+    }
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let inventory = inventory_path(data, calls, path);
+    let mut body = String::new();
+    let _ = request.as_reader().read_to_string(&mut body);
+    let authorized = request.headers().iter().any(|h| {
+        h.field.equiv("Authorization") && h.value.as_str() == format!("Bearer {}", ACCESS_TOKEN)
+    });
+
+    let count = {
+        let mut c = calls.lock().unwrap();
+        let n = c.requests.entry(path.to_string()).or_default();
+        *n += 1;
+        *n
+    };
+    if let Some(d) = data.delayed.get(path) {
+        thread::sleep(*d);
+    }
+    let injected = match data.flaky.get(path) {
+        Some((code, n)) if count <= *n => Some(*code),
+        _ => data.failing.get(path).copied(),
+    };
+
+    let (status, reply): (u16, Value) = if let Some(code) = injected {
+        (
+            code,
+            error_response("INTERNAL_SERVER_ERROR", "mock.failure", "injected failure"),
+        )
+    } else {
+        match (request.method(), path) {
+            (tiny_http::Method::Post, "/rest/login") => {
+                calls.lock().unwrap().logins += 1;
+                let login: Value = serde_json::from_str(&body).unwrap_or_default();
+                if login["username"] == USER
+                    && login["domain"] == DOMAIN
+                    && login["password"] == PASSWORD
+                {
+                    (
+                        200,
+                        json!({ "access_token": ACCESS_TOKEN, "refresh_token": REFRESH_TOKEN }),
+                    )
+                } else {
+                    (
+                        400,
+                        error_response(
+                            "BAD_REQUEST",
+                            "com.vmware.vdi.rest.authentication.invalid.credentials",
+                            "Invalid user credentials",
+                        ),
+                    )
+                }
+            }
+            (tiny_http::Method::Post, "/rest/logout") => {
+                calls.lock().unwrap().logouts += 1;
+                (200, json!({}))
+            }
+            (tiny_http::Method::Post, "/rest/refresh") => {
+                calls.lock().unwrap().refreshes += 1;
+                (200, json!({ "access_token": ACCESS_TOKEN }))
+            }
+            (tiny_http::Method::Get, _) if !authorized => (
+                401,
+                error_response("UNAUTHORIZED", "unauthorized", "Missing or invalid token"),
+            ),
+            (tiny_http::Method::Get, "/rest/config/v1/environment-properties") => (
+                200,
+                json!({
+                    "local_connection_server_version":
+                        data.horizon_version.as_deref().unwrap_or("8.10.0"),
+                    "local_connection_server_build": "21964631",
+                }),
+            ),
+            (tiny_http::Method::Get, _)
+                if inventory.is_none() && path.starts_with("/rest/inventory/") =>
+            {
+                (
+                    404,
+                    error_response("NOT_FOUND", "not.found", "Unknown endpoint"),
+                )
+            }
+            (tiny_http::Method::Get, _)
+                if inventory.as_deref() == Some("/rest/inventory/v1/desktop-pools") =>
+            {
+                (200, Value::Array(data.pools.clone()))
+            }
+            (tiny_http::Method::Get, _)
+                if inventory.as_deref() == Some("/rest/inventory/v1/machines") =>
+            {
+                (200, Value::Array(data.machines.clone()))
+            }
+            (tiny_http::Method::Get, _)
+                if inventory.as_deref() == Some("/rest/inventory/v1/sessions") =>
+            {
+                match machine_id_filter(query) {
+                    Some(m) => {
+                        calls.lock().unwrap().session_filters.push(m.clone());
+                        let sessions: Vec<Value> = data
+                            .sessions
+                            .iter()
+                            .filter(|s| s["machine_id"] == m.as_str())
+                            .cloned()
+                            .collect();
+                        (200, Value::Array(sessions))
+                    }
+                    None => (200, Value::Array(data.sessions.clone())),
+                }
+            }
+            _ => (
+                404,
+                error_response("NOT_FOUND", "not.found", "Unknown endpoint"),
+            ),
+        }
+    };
+
+    let header =
+        tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let mut response = tiny_http::Response::from_string(reply.to_string())
+        .with_status_code(status)
+        .with_header(header);
+    if status == 429 {
+        response.add_header(tiny_http::Header::from_bytes(&b"Retry-After"[..], &b"1"[..]).unwrap());
+    }
+    let _ = request.respond(response);
+}
+
+impl MockHorizon {
+    pub fn start(data: HorizonData) -> MockHorizon {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let url = format!("http://{}", server.server_addr());
+        let calls = Arc::new(Mutex::new(Calls::default()));
+
+        let srv = server.clone();
+        let c = calls.clone();
+        let handle = thread::spawn(move || {
+            for request in srv.incoming_requests() {
+                handle(&data, &c, request);
+            }
+        });
+
+        MockHorizon {
+            server,
+            url,
+            calls,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn calls(&self) -> Calls {
+        self.calls.lock().unwrap().clone()
+    }
+}
+
+// Test certificates in tests/data/tls, valid for localhost and signed by ca.pem
+pub fn tls_file(name: &str) -> String {
+    format!("{}/tests/data/tls/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
+
+// SHA-256 fingerprint of tests/data/tls/server.pem
+pub const SERVER_FINGERPRINT: &str =
+    "04:96:E6:C2:50:AC:80:07:D1:84:E9:79:39:B8:13:C0:FA:35:45:DC:82:A3:17:03:6F:DB:8B:81:A9:FD:DF:AD";
+
+// TLS terminating proxy in front of the mock, optionally requiring a client certificate
+// signed by ca.pem
+pub struct TlsProxy {
+    pub port: u16,
+    pub connections: Arc<AtomicUsize>,
+}
+
+impl TlsProxy {
+    pub fn start(target: &str, client_auth: bool) -> TlsProxy {
+        use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor
+            .set_private_key_file(tls_file("server.key"), SslFiletype::PEM)
+            .unwrap();
+        acceptor
+            .set_certificate_chain_file(tls_file("server.pem"))
+            .unwrap();
+        if client_auth {
+            acceptor.set_ca_file(tls_file("ca.pem")).unwrap();
+            acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+        }
+        let acceptor = Arc::new(acceptor.build());
+
+        let connections = Arc::new(AtomicUsize::new(0));
+        let count = connections.clone();
+        let target = target.trim_start_matches("http://").to_string();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                count.fetch_add(1, Ordering::SeqCst);
+                let acceptor = acceptor.clone();
+                let target = target.clone();
+                thread::spawn(move || {
+                    if let Ok(s) = acceptor.accept(stream) {
+                        let _ = forward(BufReader::new(s), &target);
+                    }
+                });
+            }
+        });
+
+        TlsProxy { port, connections }
+    }
+}
+
+// Forward the requests of a connection one by one to the mock, the connection to the client
+// is kept open
+fn forward<S: Read + Write>(mut client: BufReader<S>, target: &str) -> std::io::Result<()> {
+    loop {
+        let mut head = String::new();
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if client.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if line == "\r\n" {
+                break;
+            }
+            let lower = line.to_lowercase();
+            if let Some(v) = lower.strip_prefix("content-length:") {
+                length = v.trim().parse().unwrap_or(0);
+            }
+            if !lower.starts_with("connection:") {
+                head.push_str(&line);
+            }
+        }
+        let mut body = vec![0; length];
+        client.read_exact(&mut body)?;
+
+        let mut upstream = TcpStream::connect(target)?;
+        upstream.write_all(head.as_bytes())?;
+        upstream.write_all(b"Connection: close\r\n\r\n")?;
+        upstream.write_all(&body)?;
+        let mut response = Vec::new();
+        upstream.read_to_end(&mut response)?;
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .unwrap_or(response.len());
+        let head: String = String::from_utf8_lossy(&response[..split])
+            .split("\r\n")
+            .filter(|l| !l.to_lowercase().starts_with("connection:"))
+            .map(|l| format!("{}\r\n", l))
+            .collect();
+        client.get_mut().write_all(head.as_bytes())?;
+        client.get_mut().write_all(&response[split + 2..])?;
+    }
+}
+
+// Minimal SOCKS5 proxy without authentication
+pub struct Socks5Proxy {
+    pub url: String,
+    pub connections: Arc<AtomicUsize>,
+}
+
+impl Socks5Proxy {
+    pub fn start() -> Socks5Proxy {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("socks5://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+
+        let count = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                count.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let _ = socks5_connect(stream);
+                });
+            }
+        });
+
+        Socks5Proxy { url, connections }
+    }
+}
+
+fn socks5_connect(mut client: TcpStream) -> std::io::Result<()> {
+    let mut buf = [0_u8; 2];
+    client.read_exact(&mut buf)?;
+    let mut methods = vec![0; buf[1] as usize];
+    client.read_exact(&mut methods)?;
+    client.write_all(&[5, 0])?;
+
+    let mut request = [0_u8; 4];
+    client.read_exact(&mut request)?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0_u8; 4];
+            client.read_exact(&mut ip)?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut len = [0_u8; 1];
+            client.read_exact(&mut len)?;
+            let mut name = vec![0; len[0] as usize];
+            client.read_exact(&mut name)?;
+            String::from_utf8_lossy(&name).to_string()
+        }
+        _ => return Ok(()),
+    };
+    let mut port = [0_u8; 2];
+    client.read_exact(&mut port)?;
+
+    let upstream = TcpStream::connect((host.as_str(), u16::from_be_bytes(port)))?;
+    client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+
+    let mut client_read = client.try_clone()?;
+    let mut upstream_write = upstream.try_clone()?;
+    thread::spawn(move || std::io::copy(&mut client_read, &mut upstream_write));
+    let mut upstream_read = upstream;
+    std::io::copy(&mut upstream_read, &mut client)?;
+    Ok(())
+}
+
+pub struct Output {
+    pub success: bool,
+    pub metrics: String,
+    pub log: String,
+}
+
+// Write a configuration file for the mock, extra configuration is appended to the horizon_api
+// dictionary
+pub fn write_config(url: &str, password: &str, horizon_api_extra: &str) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let config = format!(
+        "---\nhorizon_api:\n    url: '{}'\n    user: '{}'\n    domain: '{}'\n    password: '{}'\n{}",
+        url, USER, DOMAIN, password, horizon_api_extra
+    );
+    let file = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-test-{}-{}.yaml",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::SeqCst)
+    ));
+    std::fs::write(&file, config).unwrap();
+    file
+}
+
+// Run the exporter in one-shot mode against the mock
+pub fn run_once(url: &str, password: &str, horizon_api_extra: &str) -> Output {
+    run_once_with_env(url, password, horizon_api_extra, &[])
+}
+
+pub fn run_once_with_env(
+    url: &str,
+    password: &str,
+    horizon_api_extra: &str,
+    env: &[(&str, &str)],
+) -> Output {
+    run_exporter(url, password, horizon_api_extra, env, &[])
+}
+
+// Run the exporter in one-shot mode with additional command line options
+pub fn run_once_with_args(
+    url: &str,
+    password: &str,
+    horizon_api_extra: &str,
+    args: &[&str],
+) -> Output {
+    run_exporter(url, password, horizon_api_extra, &[], args)
+}
+
+fn run_exporter(
+    url: &str,
+    password: &str,
+    horizon_api_extra: &str,
+    env: &[(&str, &str)],
+    args: &[&str],
+) -> Output {
+    let file = write_config(url, password, horizon_api_extra);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_vmware_horizon-exporter"))
+        .env_clear()
+        .envs(env.iter().copied())
+        .arg("--once")
+        .arg("--config")
+        .arg(&file)
+        .args(args)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&file);
+
+    Output {
+        success: output.status.success(),
+        metrics: String::from_utf8(output.stdout).unwrap(),
+        log: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+// Value of a sample, e.g. sample(m, "horizon_sessions{pool=\"p1\",state=\"connected\"}")
+pub fn sample(metrics: &str, name: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|l| l.strip_prefix(name)?.strip_prefix(' '))
+        .map(|v| v.parse().unwrap())
+}
+
+// Exporter running the HTTP server against the mock, killed on drop
+pub struct Exporter {
+    child: std::process::Child,
+    config: std::path::PathBuf,
+    pub url: String,
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_file(&self.config);
+    }
+}
+
+impl Exporter {
+    // Extra configuration is appended to the horizon_api dictionary, other dictionaries can be
+    // added by starting them in the first column
+    pub fn start(url: &str, horizon_api_extra: &str, args: &[&str]) -> Exporter {
+        let config = write_config(url, PASSWORD, horizon_api_extra);
+        let listen = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let child = std::process::Command::new(env!("CARGO_BIN_EXE_vmware_horizon-exporter"))
+            .env_clear()
+            .arg("--config")
+            .arg(&config)
+            .arg("--listen")
+            .arg(listen.to_string())
+            .args(args)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let exporter = Exporter {
+            child,
+            config,
+            url: format!("http://{}", listen),
+        };
+
+        for _ in 0..100 {
+            if TcpStream::connect(listen).is_ok() {
+                return exporter;
+            }
+            thread::sleep(std::time::Duration::from_millis(50));
+        }
+        panic!("exporter doesn't listen on {}", listen);
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    // Exit status if the exporter terminated within the timeout
+    pub fn wait_exit(&mut self, timeout: std::time::Duration) -> Option<std::process::ExitStatus> {
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(s) = self.child.try_wait().unwrap() {
+                return Some(s);
+            }
+            thread::sleep(std::time::Duration::from_millis(20));
+        }
+        None
+    }
+}
@@ -0,0 +1,799 @@
+mod common;
+
+use common::{inventory, machine, pool, sample, session, HorizonData, MockHorizon};
+
+#[test]
+fn exposition_output() {
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once(&mock.url, common::PASSWORD, "");
+
+    assert!(out.success, "{}", out.log);
+    // the port of the mock changes with every run, the durations with every run
+    let metrics: String = out
+        .metrics
+        .replace(&mock.url, "http://horizon.mock")
+        .lines()
+        .filter(|l| !l.starts_with("horizon_exporter_collector_duration_seconds{"))
+        .map(|l| format!("{}\n", l))
+        .collect();
+    assert_eq!(metrics, include_str!("data/exposition.prom"));
+    assert!(out
+        .metrics
+        .contains("horizon_exporter_collector_duration_seconds{collector=\"machines\"}"));
+
+    let calls = mock.calls();
+    assert_eq!(calls.logins, 1);
+    assert_eq!(calls.logouts, 1);
+}
+
+#[test]
+fn only_pools() {
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once(
+        &mock.url,
+        common::PASSWORD,
+        "    only_pools:\n        - 'pool-2'\n",
+    );
+
+    assert!(out.success, "{}", out.log);
+    assert!(!out.metrics.contains("pool=\"pool-1\""));
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_machine_states{pool=\"pool-2\",state=\"maintenance\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_machine_states{pool=\"pool-2\",state=\"agent_unreachable\"}"
+        ),
+        Some(1.0)
+    );
+}
+
+#[test]
+fn skip_pools() {
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once(
+        &mock.url,
+        common::PASSWORD,
+        "    skip_pools:\n        - 'pool-2'\n",
+    );
+
+    assert!(out.success, "{}", out.log);
+    assert!(!out.metrics.contains("pool=\"pool-2\""));
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_sessions{pool=\"pool-1\",state=\"connected\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_sessions{pool=\"pool-1\",state=\"disconnected\"}"
+        ),
+        Some(1.0)
+    );
+}
+
+#[test]
+fn pool_uuid_map() {
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once(
+        &mock.url,
+        common::PASSWORD,
+        "    pool_uuid_map:\n        pool-1: 'Berlin'\n",
+    );
+
+    assert!(out.success, "{}", out.log);
+    assert!(!out.metrics.contains("pool=\"pool-1\""));
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_machine_states{pool=\"Berlin\",state=\"available\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_session_protocols{pool=\"Berlin\",protocol=\"blast\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_machine_states{pool=\"pool-2\",state=\"maintenance\"}"
+        ),
+        Some(1.0)
+    );
+}
+
+#[test]
+fn unassigned_user_reclassification() {
+    let data = HorizonData {
+        pools: vec![pool("pool-1", "Pool 1")],
+        machines: vec![
+            // session user is assigned to the machine
+            machine(
+                "m1",
+                "pool-1",
+                "UNASSIGNED_USER_CONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-1"],
+            ),
+            machine(
+                "m2",
+                "pool-1",
+                "UNASSIGNED_USER_DISCONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-2"],
+            ),
+            // session user is not assigned to the machine
+            machine(
+                "m3",
+                "pool-1",
+                "UNASSIGNED_USER_CONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-9"],
+            ),
+            // no session for the machine
+            machine(
+                "m4",
+                "pool-1",
+                "UNASSIGNED_USER_DISCONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-4"],
+            ),
+        ],
+        sessions: vec![
+            session("s1", "S-1", "m1", "pool-1", "CONNECTED", "BLAST"),
+            session("s2", "S-2", "m2", "pool-1", "DISCONNECTED", "BLAST"),
+            session("s3", "S-3", "m3", "pool-1", "CONNECTED", "RDP"),
+        ],
+        ..Default::default()
+    };
+    let mock = MockHorizon::start(data);
+    let out = common::run_once(&mock.url, common::PASSWORD, "");
+
+    assert!(out.success, "{}", out.log);
+    let state = |s: &str| {
+        sample(
+            &out.metrics,
+            &format!("horizon_machine_states{{pool=\"pool-1\",state=\"{}\"}}", s),
+        )
+    };
+    assert_eq!(state("connected"), Some(1.0));
+    assert_eq!(state("disconnected"), Some(1.0));
+    assert_eq!(state("unassigned_user_connected"), Some(1.0));
+    assert_eq!(state("unassigned_user_disconnected"), Some(1.0));
+
+    let mut filters = mock.calls().session_filters;
+    filters.sort();
+    assert_eq!(filters, vec!["m1", "m2", "m3", "m4"]);
+}
+
+#[test]
+fn machines_api_filters_effective_state() {
+    let data = HorizonData {
+        pools: vec![pool("pool-1", "Pool 1")],
+        machines: vec![
+            machine(
+                "m1",
+                "pool-1",
+                "UNASSIGNED_USER_CONNECTED",
+                "WINDOWS_10",
+                "BIT_64",
+                &["S-1"],
+            ),
+            machine("m2", "pool-1", "AVAILABLE", "WINDOWS_10", "BIT_64", &[]),
+        ],
+        sessions: vec![session("s1", "S-1", "m1", "pool-1", "CONNECTED", "BLAST")],
+        ..Default::default()
+    };
+    let mock = MockHorizon::start(data);
+    let exporter = common::Exporter::start(&mock.url, "", &[]);
+    reqwest::blocking::get(format!("{}/metrics", exporter.url)).unwrap();
+
+    let machines = |state: &str| -> Vec<String> {
+        let body =
+            reqwest::blocking::get(format!("{}/api/v1/machines?state={}", exporter.url, state))
+                .unwrap()
+                .text()
+                .unwrap();
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        body["machines"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["id"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(machines("connected"), vec!["m1"]);
+    assert!(machines("unassigned_user_connected").is_empty());
+    assert_eq!(machines("available"), vec!["m2"]);
+}
+
+#[test]
+fn unknown_enum_values() {
+    let data = HorizonData {
+        pools: vec![pool("pool-1", "Pool 1")],
+        machines: vec![
+            machine("m1", "pool-1", "HIBERNATED", "WINDOWS_12", "BIT_64", &[]),
+            machine("m2", "pool-1", "HIBERNATED", "WINDOWS_10", "ARM_64", &[]),
+        ],
+        sessions: vec![session("s1", "S-1", "m3", "pool-1", "IDLE", "BLAST")],
+        ..Default::default()
+    };
+    let mock = MockHorizon::start(data);
+    let out = common::run_once(&mock.url, common::PASSWORD, "");
+
+    assert!(out.success, "{}", out.log);
+    let unknown = |field: &str, value: &str| {
+        sample(
+            &out.metrics,
+            &format!(
+                "horizon_unknown_enum_values_total{{field=\"{}\",value=\"{}\"}}",
+                field, value
+            ),
+        )
+    };
+    assert_eq!(unknown("state", "HIBERNATED"), Some(2.0));
+    assert_eq!(unknown("operating_system", "WINDOWS_12"), Some(1.0));
+    assert_eq!(
+        unknown("operating_system_architecture", "ARM_64"),
+        Some(1.0)
+    );
+    assert_eq!(unknown("session_state", "IDLE"), Some(1.0));
+    assert_eq!(unknown("session_protocol", "BLAST"), None);
+
+    // unknown values are still counted
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_machine_states{pool=\"pool-1\",state=\"hibernated\"}"
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_sessions{pool=\"pool-1\",state=\"idle\"}"
+        ),
+        Some(1.0)
+    );
+}
+
+#[test]
+fn login_failure() {
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once(&mock.url, "wrong", "");
+
+    assert!(!out.success);
+    assert!(
+        out.log.contains("Invalid user credentials"),
+        "error message of the ErrorResponse not logged: {}",
+        out.log
+    );
+    assert!(!out.metrics.contains("horizon_sessions{"));
+    assert_eq!(mock.calls().logouts, 0);
+}
+
+#[test]
+fn collector_failure() {
+    let mut data = inventory();
+    data.failing
+        .insert("/rest/inventory/v1/machines".to_string(), 500);
+    let mock = MockHorizon::start(data);
+    let out = common::run_once(&mock.url, common::PASSWORD, "    min_backoff: 0\n");
+
+    assert!(!out.success);
+    assert!(out.log.contains("injected failure"), "{}", out.log);
+    // sessions are collected independently of the machines
+    assert_eq!(
+        sample(
+            &out.metrics,
+            "horizon_sessions{pool=\"pool-1\",state=\"connected\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(mock.calls().logouts, 1);
+    // GET requests are retried
+    assert_eq!(mock.calls().requests["/rest/inventory/v1/machines"], 3);
+}
+
+#[test]
+fn proxy_disabled() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = format!(
+        "http://{}",
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    );
+    let env = [("http_proxy", proxy.as_str())];
+    let extra = "    retries: 0\n";
+
+    let out = common::run_once_with_env(&mock.url, common::PASSWORD, extra, &env);
+    assert!(!out.success);
+    assert_eq!(mock.calls().logins, 0);
+
+    let extra = "    retries: 0\n    proxy:\n        disable: true\n";
+    let out = common::run_once_with_env(&mock.url, common::PASSWORD, extra, &env);
+    assert!(out.success, "{}", out.log);
+    assert_eq!(mock.calls().logins, 1);
+}
+
+#[test]
+fn json_logging() {
+    let mut data = inventory();
+    data.failing
+        .insert("/rest/inventory/v1/machines".to_string(), 500);
+    let mock = MockHorizon::start(data);
+    let out = common::run_once_with_args(
+        &mock.url,
+        common::PASSWORD,
+        "    retries: 0\n",
+        &["--log-format=json", "--log-level=warn,http=debug"],
+    );
+    assert!(!out.success);
+
+    let lines: Vec<serde_json::Value> = out
+        .log
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap_or_else(|e| panic!("{}: {}", e, l)))
+        .collect();
+
+    let failure = lines
+        .iter()
+        .find(|l| l["collector"] == "machines")
+        .expect("no log message of the machines collector");
+    assert_eq!(failure["level"], "ERROR");
+    assert_eq!(failure["module"], "collector");
+    assert!(failure["duration_ms"].is_u64());
+
+    // debug messages are only enabled for the http module
+    assert!(lines
+        .iter()
+        .any(|l| l["module"] == "http" && l["http_status"] == 500));
+    assert!(lines
+        .iter()
+        .all(|l| l["level"] != "DEBUG" || l["module"] == "http"));
+}
+
+#[test]
+fn invalid_log_options() {
+    let mock = MockHorizon::start(inventory());
+    for args in [
+        ["--log-format=xml"],
+        ["--log-target=printer"],
+        ["--log-level=http=loud"],
+    ] {
+        let out = common::run_once_with_args(&mock.url, common::PASSWORD, "", &args);
+        assert!(!out.success);
+        assert!(out.log.contains("Invalid logging options"), "{}", out.log);
+    }
+    assert_eq!(mock.calls().logins, 0);
+}
+
+#[test]
+fn openmetrics_negotiation() {
+    let mock = MockHorizon::start(inventory());
+    let exporter = common::Exporter::start(&mock.url, "", &[]);
+    let client = reqwest::blocking::Client::new();
+    let metrics = |accept: &str| {
+        let response = client
+            .get(format!("{}/metrics", exporter.url))
+            .header("Accept", accept)
+            .send()
+            .unwrap();
+        let content_type = response.headers()["Content-Type"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        (content_type, response.text().unwrap())
+    };
+
+    // Accept header of Prometheus
+    let (content_type, body) = metrics("application/openmetrics-text;version=1.0.0;q=0.5,application/openmetrics-text;version=0.0.1;q=0.4,text/plain;version=0.0.4;q=0.3,*/*;q=0.2");
+    assert!(content_type.starts_with("application/openmetrics-text"));
+    assert!(body.ends_with("# EOF\n"));
+    // _info gauges are info metrics, the counts are separate gauges
+    assert!(body.contains("# TYPE horizon_agent_version info\n"));
+    assert_eq!(
+        sample(
+            &body,
+            "horizon_agent_version_info{pool=\"pool-1\",version=\"8.10.0\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &body,
+            "horizon_agent_versions{pool=\"pool-1\",version=\"8.10.0\"}"
+        ),
+        Some(2.0)
+    );
+    assert!(body.contains("# TYPE horizon_machine_os info\n"));
+    assert!(!body.contains("horizon_machine_os_info{operating_system=\"windows_7\""));
+    assert_eq!(
+        sample(
+            &body,
+            "horizon_machine_os_architectures{architecture=\"bit_64\",pool=\"pool-1\"}"
+        ),
+        Some(3.0)
+    );
+    // units are taken from the suffix of the name
+    assert!(body.contains("# UNIT horizon_exporter_collector_duration_seconds seconds\n"));
+
+    for accept in [
+        "application/openmetrics-text;q=0,text/plain",
+        "text/plain;q=0.9,application/openmetrics-text;q=0.5",
+        "*/*",
+    ] {
+        let (content_type, body) = metrics(accept);
+        assert!(content_type.starts_with("text/plain"), "{}", accept);
+        assert!(!body.contains("# EOF"), "{}", accept);
+    }
+}
+
+#[test]
+fn gzip_compression() {
+    use std::io::Read;
+
+    let mock = MockHorizon::start(inventory());
+    let exporter = common::Exporter::start(&mock.url, "", &[]);
+    let client = reqwest::blocking::Client::new();
+    let get = |encoding: &str| {
+        client
+            .get(format!("{}/metrics", exporter.url))
+            .header("Accept-Encoding", encoding)
+            .send()
+            .unwrap()
+    };
+
+    let response = get("br, gzip;q=0.8");
+    assert_eq!(response.headers()["Content-Encoding"], "gzip");
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(response.bytes().unwrap().as_ref())
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(
+        sample(
+            &body,
+            "horizon_sessions{pool=\"pool-1\",state=\"connected\"}"
+        ),
+        Some(1.0)
+    );
+
+    for encoding in ["gzip;q=0", "identity", "*;q=0"] {
+        let response = get(encoding);
+        assert!(
+            response.headers().get("Content-Encoding").is_none(),
+            "{}",
+            encoding
+        );
+        assert!(response.text().unwrap().contains("horizon_sessions{"));
+    }
+}
+
+#[test]
+fn scrape_wait_timeout() {
+    let mut data = inventory();
+    data.delayed.insert(
+        "/rest/inventory/v1/machines".to_string(),
+        std::time::Duration::from_secs(4),
+    );
+    let mock = MockHorizon::start(data);
+    let exporter = common::Exporter::start(
+        &mock.url,
+        "exporter:\n    http_threads: 2\n    scrape_wait_timeout: 1\n",
+        &[],
+    );
+
+    // the refresh at startup is still running, the scrape gives up waiting for it
+    std::thread::sleep(std::time::Duration::from_millis(500));
+    let start = std::time::Instant::now();
+    let scrape = reqwest::blocking::get(format!("{}/metrics", exporter.url)).unwrap();
+    assert!(scrape.status().is_success());
+    assert!(start.elapsed() < std::time::Duration::from_secs(3));
+
+    // the health check is answered during the refresh
+    let health = reqwest::blocking::get(format!("{}/-/healthy", exporter.url)).unwrap();
+    assert!(health.status().is_success());
+
+    // the exporter becomes ready once the refresh at startup finished
+    let mut ready = false;
+    for _ in 0..100 {
+        let response = reqwest::blocking::get(format!("{}/-/ready", exporter.url)).unwrap();
+        if response.status().is_success() {
+            ready = true;
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(ready);
+    assert_eq!(mock.calls().logins, 1);
+}
+
+#[test]
+fn ready_without_scrape() {
+    let mock = MockHorizon::start(inventory());
+    let exporter = common::Exporter::start(&mock.url, "", &[]);
+
+    let mut ready = false;
+    for _ in 0..50 {
+        let response = reqwest::blocking::get(format!("{}/-/ready", exporter.url)).unwrap();
+        if response.status().is_success() {
+            ready = true;
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    assert!(ready);
+    assert_eq!(mock.calls().logins, 1);
+}
+
+#[test]
+fn reload_endpoint_opt_in() {
+    let mock = MockHorizon::start(inventory());
+    let client = reqwest::blocking::Client::new();
+
+    let exporter = common::Exporter::start(&mock.url, "", &[]);
+    let response = client
+        .post(format!("{}/-/reload", exporter.url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 403);
+
+    let exporter = common::Exporter::start(&mock.url, "", &["--enable-reload"]);
+    let response = client
+        .post(format!("{}/-/reload", exporter.url))
+        .send()
+        .unwrap();
+    assert_eq!(response.status(), 200);
+}
+
+#[test]
+fn second_signal_exits_immediately() {
+    let mut data = inventory();
+    data.delayed.insert(
+        "/rest/inventory/v1/machines".to_string(),
+        std::time::Duration::from_secs(10),
+    );
+    let mock = MockHorizon::start(data);
+    let mut exporter = common::Exporter::start(&mock.url, "", &[]);
+    let kill = |pid: u32| {
+        std::process::Command::new("kill")
+            .arg("-TERM")
+            .arg(pid.to_string())
+            .status()
+            .unwrap()
+    };
+
+    // the scrape waits for the slow refresh, the shutdown waits for the scrape
+    let url = exporter.url.clone();
+    std::thread::spawn(move || reqwest::blocking::get(format!("{}/metrics", url)));
+    std::thread::sleep(std::time::Duration::from_millis(500));
+
+    assert!(kill(exporter.pid()).success());
+    assert!(exporter
+        .wait_exit(std::time::Duration::from_secs(2))
+        .is_none());
+
+    assert!(kill(exporter.pid()).success());
+    assert!(exporter
+        .wait_exit(std::time::Duration::from_secs(2))
+        .is_some());
+}
+
+#[test]
+fn graphite_empty_label_value() {
+    use std::io::Read;
+
+    let mut data = inventory();
+    data.machines.push(common::machine(
+        "m6",
+        "",
+        "AVAILABLE",
+        "WINDOWS_10",
+        "BIT_64",
+        &[],
+    ));
+    let mock = MockHorizon::start(data);
+    let graphite = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let _exporter = common::Exporter::start(
+        &mock.url,
+        &format!(
+            "graphite:\n    address: {}\n    interval: 1\n",
+            graphite.local_addr().unwrap()
+        ),
+        &[],
+    );
+
+    let (mut stream, _) = graphite.accept().unwrap();
+    let mut lines = String::new();
+    stream.read_to_string(&mut lines).unwrap();
+
+    assert!(lines.lines().all(|l| !l.contains("..")));
+    assert!(lines.contains("horizon.horizon_machine_states.none.available 1 "));
+    assert!(lines.contains("horizon.horizon_machine_states.pool-1.available 1 "));
+}
+
+#[test]
+fn graphite_counts_sessions_and_machines() {
+    use std::io::Read;
+
+    let mock = MockHorizon::start(inventory());
+    let graphite = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let _exporter = common::Exporter::start(
+        &mock.url,
+        &format!(
+            "graphite:\n    address: {}\n    interval: 1\n    tagged: true\n",
+            graphite.local_addr().unwrap()
+        ),
+        &[],
+    );
+
+    let (mut stream, _) = graphite.accept().unwrap();
+    let mut lines = String::new();
+    stream.read_to_string(&mut lines).unwrap();
+
+    assert!(lines.contains("horizon.horizon_sessions;pool=pool-1;state=connected 1 "));
+    assert!(lines.contains("horizon.horizon_sessions;pool=pool-2;state=pending 0 "));
+    assert!(lines.contains("horizon.horizon_agent_versions;pool=pool-1;version="));
+    assert!(lines.contains(
+        "horizon.horizon_machine_operating_systems;pool=pool-1;operating_system=windows_10 2 "
+    ));
+    // metrics of the exporter itself are not counts of the collection
+    assert!(!lines.contains("horizon_exporter_"), "{}", lines);
+    assert!(!lines.contains("_info"), "{}", lines);
+}
+
+#[test]
+fn unknown_environment_variables() {
+    let mock = MockHorizon::start(inventory());
+    let env = [
+        ("HORIZON_EXPORTER_HORIZON_API__TIMEOUTS", "10"),
+        ("HORIZON_EXPORTER_VERSION", "1.2.3"),
+        ("HORIZON_EXPORTER_HORIZON_API__TIMEOUT", "10"),
+    ];
+
+    let out = common::run_once_with_env(&mock.url, common::PASSWORD, "", &env);
+    assert!(out.success, "{}", out.log);
+    assert!(out
+        .log
+        .contains("ignoring environment variable HORIZON_EXPORTER_HORIZON_API__TIMEOUTS"));
+    assert!(out
+        .log
+        .contains("ignoring environment variable HORIZON_EXPORTER_VERSION"));
+
+    // unknown keys of the configuration file are still rejected
+    let out = common::run_once_with_env(&mock.url, common::PASSWORD, "    timeouts: 10\n", &[]);
+    assert!(!out.success);
+    assert!(out.log.contains("horizon_api.timeouts"), "{}", out.log);
+    assert!(out.log.contains("line 7"), "{}", out.log);
+}
+
+#[test]
+fn once_keeps_output_file_on_failure() {
+    let output = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-once-{}.prom",
+        std::process::id()
+    ));
+    let output_arg = format!("--output={}", output.to_string_lossy());
+
+    let mock = MockHorizon::start(inventory());
+    let out = common::run_once_with_args(&mock.url, common::PASSWORD, "", &[&output_arg]);
+    assert!(out.success, "{}", out.log);
+    let good = std::fs::read_to_string(&output).unwrap();
+    assert!(good.contains("horizon_machine_states{"));
+
+    let mut data = inventory();
+    data.failing
+        .insert("/rest/inventory/v1/machines".to_string(), 500);
+    let failing = MockHorizon::start(data);
+    let out = common::run_once_with_args(
+        &failing.url,
+        common::PASSWORD,
+        "    retries: 0\n",
+        &[&output_arg],
+    );
+    assert!(!out.success);
+    assert_eq!(std::fs::read_to_string(&output).unwrap(), good);
+
+    let _ = std::fs::remove_file(&output);
+}
+
+// Remote write endpoint failing with 503 for the first seconds, returns the bodies of the
+// failed requests and the accepted requests in order
+fn remote_write_outage(
+    queue_size: usize,
+    outage: std::time::Duration,
+) -> (Vec<Vec<u8>>, Vec<Vec<u8>>, f64) {
+    let mock = MockHorizon::start(inventory());
+    let server = std::sync::Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+    let exporter = common::Exporter::start(
+        &mock.url,
+        &format!(
+            "remote_write:\n    url: 'http://{}/push'\n    interval: 1\n    queue_size: {}\n    min_backoff: 1\n    max_backoff: 1\n",
+            server.server_addr(),
+            queue_size
+        ),
+        &[],
+    );
+
+    let mut failed = Vec::new();
+    let mut accepted = Vec::new();
+    let mut first: Option<std::time::Instant> = None;
+    while accepted.len() < 2 {
+        let mut request = server
+            .recv_timeout(std::time::Duration::from_secs(10))
+            .unwrap()
+            .expect("no remote_write request");
+        let mut body = Vec::new();
+        request.as_reader().read_to_end(&mut body).unwrap();
+        let start = *first.get_or_insert_with(std::time::Instant::now);
+        let status = if start.elapsed() < outage {
+            failed.push(body);
+            503
+        } else {
+            accepted.push(body);
+            204
+        };
+        request.respond(tiny_http::Response::empty(status)).unwrap();
+    }
+
+    let metrics = reqwest::blocking::get(format!("{}/metrics", exporter.url))
+        .unwrap()
+        .text()
+        .unwrap();
+    let dropped = sample(
+        &metrics,
+        "horizon_exporter_remote_write_dropped_samples_total",
+    )
+    .unwrap();
+    (failed, accepted, dropped)
+}
+
+#[test]
+fn remote_write_retries_until_recovery() {
+    // longer than the retries of the former default max_retries
+    let (failed, accepted, dropped) = remote_write_outage(100, std::time::Duration::from_secs(8));
+
+    assert!(failed.len() > 6, "{} failed requests", failed.len());
+    // the oldest data is retried and sent first after the outage
+    assert_eq!(accepted[0], failed[0]);
+    assert_eq!(dropped, 0.0);
+}
+
+#[test]
+fn remote_write_queue_drops_oldest() {
+    let (failed, accepted, dropped) = remote_write_outage(1, std::time::Duration::from_secs(4));
+
+    assert_ne!(accepted[0], failed[0]);
+    assert!(dropped > 0.0);
+}
+
+#[test]
+fn debug_log_redacts_password() {
+    let mock = MockHorizon::start(inventory());
+    let password = "d3bug-l0g-pa55word";
+    let out = common::run_once_with_args(&mock.url, password, "", &["--debug"]);
+
+    // the login fails with the wrong password, but the request was logged
+    assert!(!out.success);
+    assert!(out.log.contains("sending login data"), "{}", out.log);
+    assert!(out.log.contains(r#"password: Some("REDACTED")"#));
+    assert!(!out.log.contains(password), "{}", out.log);
+}
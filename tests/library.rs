@@ -0,0 +1,764 @@
+mod common;
+
+use common::{inventory, sample, MockHorizon};
+use std::collections::HashMap;
+use vmware_horizon_exporter::{configuration, Collector, HorizonClient, RecorderMode};
+
+fn config(url: &str, password: &str) -> configuration::Configuration {
+    config_with(url, password, "")
+}
+
+fn config_with(url: &str, password: &str, extra: &str) -> configuration::Configuration {
+    let file = common::write_config(url, password, extra);
+    let cfg = configuration::parse_config_file(Some(&file.to_string_lossy())).unwrap();
+    let _ = std::fs::remove_file(&file);
+    cfg
+}
+
+#[test]
+fn client_list_calls() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config(&mock.url, common::PASSWORD);
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    assert!(client.desktop_pools().is_err());
+
+    client.login().unwrap();
+    assert!(client.is_logged_in());
+    assert_eq!(client.desktop_pools().unwrap().len(), 2);
+    assert_eq!(client.machines().unwrap().len(), 5);
+    assert_eq!(client.sessions().unwrap().len(), 2);
+    assert_eq!(
+        client.session_for_machine_id("m2").unwrap().unwrap().id,
+        "s1"
+    );
+    assert!(client.session_for_machine_id("m1").unwrap().is_none());
+
+    client.refresh().unwrap();
+    assert_eq!(client.machines().unwrap().len(), 5);
+
+    client.logout().unwrap();
+    assert!(!client.is_logged_in());
+
+    let calls = mock.calls();
+    assert_eq!(calls.logins, 1);
+    assert_eq!(calls.refreshes, 1);
+    assert_eq!(calls.logouts, 1);
+}
+
+#[test]
+fn client_login_failure() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config(&mock.url, "wrong");
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    let err = client.login().unwrap_err();
+    assert!(
+        err.to_string().contains("Invalid user credentials"),
+        "{}",
+        err
+    );
+    assert!(!client.is_logged_in());
+}
+
+#[test]
+fn collector_with_own_registry() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config(&mock.url, common::PASSWORD);
+    let registry = prometheus::Registry::new();
+    let collector = Collector::new(&registry).unwrap();
+
+    assert!(collector.collect(&cfg));
+
+    let metrics = prometheus::TextEncoder::new()
+        .encode_to_string(&registry.gather())
+        .unwrap();
+    assert_eq!(
+        sample(
+            &metrics,
+            "horizon_sessions{pool=\"pool-1\",state=\"connected\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            "horizon_machine_states{pool=\"pool-1\",state=\"available\"}"
+        ),
+        Some(1.0)
+    );
+    assert_eq!(collector.desktop_pools().len(), 2);
+    assert_eq!(collector.machines().len(), 5);
+    assert_eq!(collector.sessions().len(), 2);
+    assert_eq!(
+        sample(
+            &metrics,
+            "horizon_build_info{build=\"21964631\",version=\"8.10.0\"}"
+        ),
+        Some(1.0)
+    );
+
+    // a second collector can't register the same metrics in the registry
+    assert!(Collector::new(&registry).is_err());
+}
+
+#[test]
+fn api_version_selection() {
+    let mut data = inventory();
+    data.horizon_version = Some("8.12.1".to_string());
+    data.api_versions = HashMap::from([
+        ("desktop-pools".to_string(), 3),
+        ("machines".to_string(), 2),
+        ("sessions".to_string(), 1),
+    ]);
+    let mock = MockHorizon::start(data);
+    let cfg = config(&mock.url, common::PASSWORD);
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    client.login().unwrap();
+    client.environment_properties().unwrap();
+    assert_eq!(client.desktop_pools().unwrap().len(), 2);
+    assert_eq!(client.machines().unwrap().len(), 5);
+    // sessions v2 is expected on 8.12 but not provided, v1 is used instead
+    assert_eq!(client.sessions().unwrap().len(), 2);
+    assert_eq!(client.sessions().unwrap().len(), 2);
+    client.logout().unwrap();
+
+    assert_eq!(
+        mock.calls().inventory_paths,
+        vec![
+            "/rest/inventory/v3/desktop-pools",
+            "/rest/inventory/v2/machines",
+            "/rest/inventory/v2/sessions",
+            "/rest/inventory/v1/sessions",
+            "/rest/inventory/v1/sessions",
+        ]
+    );
+}
+
+#[test]
+fn api_version_without_horizon_version() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config(&mock.url, common::PASSWORD);
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    client.login().unwrap();
+    client.machines().unwrap();
+    client.logout().unwrap();
+
+    assert_eq!(
+        mock.calls().inventory_paths,
+        vec!["/rest/inventory/v1/machines"]
+    );
+}
+
+#[test]
+fn retry_idempotent_requests() {
+    let mut data = inventory();
+    data.flaky
+        .insert("/rest/inventory/v1/machines".to_string(), (503, 2));
+    data.flaky.insert("/rest/logout".to_string(), (503, 1));
+    let mock = MockHorizon::start(data);
+    let cfg = config_with(&mock.url, common::PASSWORD, "    min_backoff: 0\n");
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    client.login().unwrap();
+    assert_eq!(client.machines().unwrap().len(), 5);
+    // POST requests are not retried on server errors
+    assert!(client.logout().is_err());
+
+    let calls = mock.calls();
+    assert_eq!(calls.requests["/rest/inventory/v1/machines"], 3);
+    assert_eq!(calls.requests["/rest/logout"], 1);
+}
+
+#[test]
+fn retry_after_too_many_requests() {
+    let mut data = inventory();
+    data.flaky
+        .insert("/rest/inventory/v1/desktop-pools".to_string(), (429, 1));
+    let mock = MockHorizon::start(data);
+    let cfg = config_with(&mock.url, common::PASSWORD, "    min_backoff: 0\n");
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    client.login().unwrap();
+    let start = std::time::Instant::now();
+    assert_eq!(client.desktop_pools().unwrap().len(), 2);
+    assert!(start.elapsed() >= std::time::Duration::from_secs(1));
+    assert_eq!(mock.calls().requests["/rest/inventory/v1/desktop-pools"], 2);
+}
+
+#[test]
+fn login_suspended_after_failures() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config_with(&mock.url, "wrong", "    max_login_failures: 2\n");
+    let mut client = HorizonClient::new(&cfg).unwrap();
+
+    assert!(client.login().is_err());
+    assert!(client.login().is_err());
+    let err = client.login().unwrap_err();
+    assert!(err.to_string().contains("login suspended"), "{}", err);
+    assert_eq!(mock.calls().logins, 2);
+
+    // failures are counted per client
+    let mut other = HorizonClient::new(&cfg).unwrap();
+    assert!(!other
+        .login()
+        .unwrap_err()
+        .to_string()
+        .contains("login suspended"));
+    assert_eq!(mock.calls().logins, 3);
+}
+
+// URL of a port nobody listens on
+fn unused_url() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}", listener.local_addr().unwrap())
+}
+
+#[test]
+fn failover_to_next_server() {
+    let dead = unused_url();
+    let mut data = inventory();
+    data.failing.insert("/rest/login".to_string(), 503);
+    let failing = MockHorizon::start(data);
+    let mock = MockHorizon::start(inventory());
+    let cfg = config_with(
+        "",
+        common::PASSWORD,
+        &format!(
+            "    urls:\n        - '{}'\n        - '{}'\n        - '{}'\n    min_backoff: 0\n",
+            dead, failing.url, mock.url
+        ),
+    );
+    let registry = prometheus::Registry::new();
+    let collector = Collector::new(&registry).unwrap();
+
+    assert!(collector.collect(&cfg));
+    // failed servers are tried last, no further failover
+    assert!(collector.collect(&cfg));
+
+    let metrics = prometheus::TextEncoder::new()
+        .encode_to_string(&registry.gather())
+        .unwrap();
+    let active = |url: &str| {
+        sample(
+            &metrics,
+            &format!("horizon_exporter_active_endpoint{{url=\"{}\"}}", url),
+        )
+    };
+    assert_eq!(active(&mock.url), Some(1.0));
+    assert_eq!(active(&failing.url), Some(0.0));
+    assert_eq!(active(&dead), Some(0.0));
+    assert_eq!(
+        sample(&metrics, "horizon_exporter_endpoint_failovers_total"),
+        Some(2.0)
+    );
+    assert_eq!(mock.calls().logins, 2);
+    assert_eq!(failing.calls().requests["/rest/login"], 1);
+}
+
+#[test]
+fn removed_endpoint_not_active() {
+    let first = MockHorizon::start(inventory());
+    let second = MockHorizon::start(inventory());
+    let registry = prometheus::Registry::new();
+    let collector = Collector::new(&registry).unwrap();
+
+    assert!(collector.collect(&config(&first.url, common::PASSWORD)));
+    // configuration reload replacing the connection server
+    assert!(collector.collect(&config(&second.url, common::PASSWORD)));
+
+    let metrics = prometheus::TextEncoder::new()
+        .encode_to_string(&registry.gather())
+        .unwrap();
+    assert!(!metrics.contains(&first.url));
+    assert_eq!(
+        sample(
+            &metrics,
+            &format!("horizon_exporter_active_endpoint{{url=\"{}\"}}", second.url),
+        ),
+        Some(1.0)
+    );
+}
+
+#[test]
+fn failover_round_robin() {
+    let first = MockHorizon::start(inventory());
+    let second = MockHorizon::start(inventory());
+    let cfg = config_with(
+        "",
+        common::PASSWORD,
+        &format!(
+            "    urls:\n        - '{}'\n        - '{}'\n    failover: round_robin\n",
+            first.url, second.url
+        ),
+    );
+
+    let mut endpoints = Vec::new();
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    for _ in 0..2 {
+        client.login().unwrap();
+        assert_eq!(client.failovers(), 0);
+        endpoints.push(client.endpoint().to_string());
+        client.logout().unwrap();
+    }
+
+    assert_eq!(endpoints, vec![first.url.clone(), second.url.clone()]);
+    assert_eq!(first.calls().logins, 1);
+    assert_eq!(second.calls().logins, 1);
+
+    // the position is kept per client
+    let mut other = HorizonClient::new(&cfg).unwrap();
+    other.login().unwrap();
+    assert_eq!(other.endpoint(), first.url);
+}
+
+fn tls_config(url: &str, extra: &str) -> configuration::Configuration {
+    config_with(
+        url,
+        common::PASSWORD,
+        &format!(
+            "    ca_file: '{}'\n    min_backoff: 0\n    retries: 0\n{}",
+            common::tls_file("ca.pem"),
+            extra
+        ),
+    )
+}
+
+#[test]
+fn tls_client_certificate() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, true);
+    let url = format!("https://localhost:{}", proxy.port);
+
+    let pem = tls_config(
+        &url,
+        &format!(
+            "    client_cert: '{}'\n    client_key: '{}'\n",
+            common::tls_file("client.pem"),
+            common::tls_file("client.key")
+        ),
+    );
+    let mut client = HorizonClient::new(&pem).unwrap();
+    client.login().unwrap();
+    assert_eq!(client.desktop_pools().unwrap().len(), 2);
+    client.logout().unwrap();
+
+    let pkcs12 = tls_config(
+        &url,
+        &format!(
+            "    client_pkcs12: '{}'\n    client_pkcs12_password: 'secret'\n    min_tls_version: '1.3'\n",
+            common::tls_file("client.p12")
+        ),
+    );
+    let mut client = HorizonClient::new(&pkcs12).unwrap();
+    client.login().unwrap();
+    client.logout().unwrap();
+
+    // the proxy rejects connections without client certificate
+    let mut client = HorizonClient::new(&tls_config(&url, "")).unwrap();
+    assert!(client.login().is_err());
+    assert_eq!(mock.calls().logins, 2);
+}
+
+#[test]
+fn output_client_certificate() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, true);
+    let url = format!(
+        "https://localhost:{}/rest/monitor/v1/connection-servers",
+        proxy.port
+    );
+    let ca_dir = common::tls_file("ca.pem");
+    let ca_dir = std::path::Path::new(&ca_dir).parent().unwrap();
+
+    let mut cfg = configuration::HttpSinkConfig {
+        ca_dir: Some(ca_dir.to_string_lossy().to_string()),
+        client_pkcs12: Some(common::tls_file("client.p12")),
+        client_pkcs12_password: Some("secret".to_string()),
+        ..Default::default()
+    };
+    let client = vmware_horizon_exporter::http::build_client(&cfg).unwrap();
+    assert!(client.get(&url).send().is_ok());
+    assert!(!format!("{:?}", cfg).contains("secret"));
+
+    // the proxy rejects connections without client certificate
+    cfg.client_pkcs12 = None;
+    cfg.client_pkcs12_password = None;
+    let client = vmware_horizon_exporter::http::build_client(&cfg).unwrap();
+    assert!(client.get(&url).send().is_err());
+}
+#[test]
+fn tls_pinned_certificate() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, false);
+    let url = format!("https://localhost:{}", proxy.port);
+
+    let cfg = tls_config(
+        &url,
+        &format!(
+            "    pinned_certificates:\n        - '{}'\n",
+            common::SERVER_FINGERPRINT
+        ),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    client.login().unwrap();
+    assert_eq!(client.machines().unwrap().len(), 5);
+    client.logout().unwrap();
+
+    let cfg = tls_config(
+        &url,
+        &format!(
+            "    pinned_certificates:\n        - '{}'\n",
+            "ab".repeat(32)
+        ),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    let err = client.login().unwrap_err();
+    assert!(err.to_string().contains("is not pinned"), "{}", err);
+    // no credentials are sent to a server with an unknown certificate
+    assert_eq!(mock.calls().logins, 1);
+}
+
+#[test]
+fn tls_server_name() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, false);
+    // the server certificate is only valid for localhost
+    let url = format!("https://127.0.0.1:{}", proxy.port);
+
+    let mut client = HorizonClient::new(&tls_config(&url, "")).unwrap();
+    assert!(client.login().is_err());
+
+    let cfg = tls_config(&url, "    tls_server_name: 'localhost'\n");
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    client.login().unwrap();
+    assert_eq!(client.endpoint(), url);
+    client.logout().unwrap();
+}
+
+#[test]
+fn tls_ca_dir() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, false);
+    let dir =
+        std::env::temp_dir().join(format!("vmware_horizon-exporter-ca-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy(common::tls_file("ca.pem"), dir.join("test-ca.crt")).unwrap();
+
+    let cfg = config_with(
+        &format!("https://localhost:{}", proxy.port),
+        common::PASSWORD,
+        &format!("    ca_dir: '{}'\n", dir.to_string_lossy()),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+    client.login().unwrap();
+    client.logout().unwrap();
+}
+
+#[test]
+fn tls_invalid_settings() {
+    for (extra, error) in [
+        ("    min_tls_version: '1.4'\n", "unsupported TLS version"),
+        (
+            "    pinned_certificates:\n        - 'abc'\n",
+            "invalid SHA-256 fingerprint",
+        ),
+        (
+            "    client_key: '/nonexistent'\n",
+            "client_cert and client_key",
+        ),
+    ] {
+        let file = common::write_config("https://localhost", common::PASSWORD, extra);
+        let err = configuration::parse_config_file(Some(&file.to_string_lossy())).unwrap_err();
+        let _ = std::fs::remove_file(&file);
+        assert!(err.to_string().contains(error), "{}", err);
+    }
+}
+
+#[test]
+fn proxy_with_authentication() {
+    let mock = MockHorizon::start(inventory());
+    let password_file = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-proxy-{}",
+        std::process::id()
+    ));
+    std::fs::write(&password_file, "proxy-secret\n").unwrap();
+
+    // the Horizon server is only reachable through the proxy
+    let cfg = config_with(
+        "http://horizon.invalid",
+        common::PASSWORD,
+        &format!(
+            "    proxy:\n        url: '{}'\n        auth:\n            user: 'proxy-user'\n            password_file: '{}'\n",
+            mock.url,
+            password_file.to_string_lossy()
+        ),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    let _ = std::fs::remove_file(&password_file);
+    client.login().unwrap();
+    assert_eq!(client.desktop_pools().unwrap().len(), 2);
+    client.logout().unwrap();
+
+    let calls = mock.calls();
+    assert_eq!(calls.proxied, 3);
+    assert_eq!(
+        calls.proxy_authorization.as_deref(),
+        // proxy-user:proxy-secret
+        Some("Basic cHJveHktdXNlcjpwcm94eS1zZWNyZXQ=")
+    );
+}
+
+#[test]
+fn proxy_socks5_and_no_proxy() {
+    let mock = MockHorizon::start(inventory());
+    let socks = common::Socks5Proxy::start();
+
+    let cfg = config_with(
+        &mock.url,
+        common::PASSWORD,
+        &format!("    proxy:\n        url: '{}'\n", socks.url),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    client.login().unwrap();
+    client.logout().unwrap();
+    assert!(socks.connections.load(std::sync::atomic::Ordering::SeqCst) > 0);
+    assert_eq!(mock.calls().proxied, 0);
+
+    let cfg = config_with(
+        &mock.url,
+        common::PASSWORD,
+        &format!(
+            "    proxy:\n        url: '{}'\n        no_proxy:\n            - '127.0.0.1'\n",
+            unused_url()
+        ),
+    );
+    let mut client = HorizonClient::new(&cfg).unwrap();
+    client.login().unwrap();
+    client.logout().unwrap();
+}
+
+#[test]
+fn connections_reused_across_collections() {
+    let mock = MockHorizon::start(inventory());
+    let proxy = common::TlsProxy::start(&mock.url, false);
+    let dir = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-pool-{}",
+        std::process::id()
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    let ca_file = dir.join("ca.pem");
+    std::fs::copy(common::tls_file("ca.pem"), &ca_file).unwrap();
+
+    let cfg = config_with(
+        &format!("https://localhost:{}", proxy.port),
+        common::PASSWORD,
+        &format!("    ca_file: '{}'\n", ca_file.to_string_lossy()),
+    );
+    let registry = prometheus::Registry::new();
+    let collector = Collector::new(&registry).unwrap();
+    let connections = || {
+        let metrics = prometheus::TextEncoder::new()
+            .encode_to_string(&registry.gather())
+            .unwrap();
+        sample(&metrics, "horizon_exporter_new_connections_total")
+    };
+
+    assert!(collector.collect(&cfg));
+    assert!(collector.collect(&cfg));
+    assert_eq!(connections(), Some(1.0));
+    assert_eq!(
+        proxy.connections.load(std::sync::atomic::Ordering::SeqCst),
+        1
+    );
+    assert_eq!(mock.calls().logins, 2);
+
+    // a changed CA file creates a new client
+    let file = std::fs::File::options().write(true).open(&ca_file).unwrap();
+    file.set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+        .unwrap();
+    assert!(collector.collect(&cfg));
+    assert_eq!(connections(), Some(2.0));
+
+    // so does a changed configuration
+    let mut reloaded = cfg.clone();
+    reloaded.horizon_api.timeout = Some(30);
+    assert!(collector.collect(&reloaded));
+    assert_eq!(connections(), Some(3.0));
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn collectors_keep_their_own_state() {
+    let mock = MockHorizon::start(inventory());
+    let good = config(&mock.url, common::PASSWORD);
+    let bad = config(&mock.url, "wrong");
+    let first = Collector::new(&prometheus::Registry::new()).unwrap();
+    let second = Collector::new(&prometheus::Registry::new()).unwrap();
+
+    assert!(first.collect(&good));
+    assert!(!second.collect(&bad));
+
+    let status = first.status();
+    assert!(status.last_login.is_some());
+    assert_eq!(status.horizon_version.as_deref(), Some("8.10.0"));
+    assert!(status.collectors["machines"].last_success.is_some());
+
+    let status = second.status();
+    assert!(status.last_login.is_none());
+    assert!(status.last_refresh.is_none());
+    assert!(status.last_attempt.is_some());
+    assert!(status.collectors["login"].last_error.is_some());
+
+    // a later success clears the error
+    assert!(second.collect(&good));
+    let status = second.status();
+    assert!(status.last_refresh.is_some());
+    assert!(status.collectors["login"].last_error.is_none());
+    assert!(status.collectors["login"].last_error_time.is_none());
+}
+
+#[test]
+fn record_and_replay_per_client() {
+    let mock = MockHorizon::start(inventory());
+    let cfg = config(&mock.url, common::PASSWORD);
+    let dir = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-recording-{}",
+        std::process::id()
+    ));
+
+    let mut recording = HorizonClient::new(&cfg).unwrap();
+    recording
+        .set_recorder(RecorderMode::Record(dir.clone()))
+        .unwrap();
+    recording.login().unwrap();
+    assert_eq!(recording.machines().unwrap().len(), 5);
+    recording.logout().unwrap();
+
+    // another client without recorder still talks to Horizon
+    let mut plain = HorizonClient::new(&cfg).unwrap();
+    plain.login().unwrap();
+    plain.logout().unwrap();
+    assert_eq!(mock.calls().logins, 2);
+
+    let replay_cfg = config(&unused_url(), common::PASSWORD);
+    let mut replay = HorizonClient::new(&replay_cfg).unwrap();
+    replay
+        .set_recorder(RecorderMode::Replay(dir.clone()))
+        .unwrap();
+    replay.login().unwrap();
+    assert_eq!(replay.machines().unwrap().len(), 5);
+    assert_eq!(mock.calls().logins, 2);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn output_http_settings() {
+    let cfg = config_with(
+        "https://localhost",
+        common::PASSWORD,
+        "pushgateway:\n    url: 'http://localhost:9091'\n    auth:\n        user: 'push'\n        password: 'secret'\n    http:\n        timeout: '5'\n        headers:\n            X-Scope-OrgID: 'horizon'\n",
+    );
+    let pgw = cfg.pushgateway.unwrap();
+    assert_eq!(pgw.auth.user.as_deref(), Some("push"));
+    assert_eq!(pgw.auth.get_password().unwrap().as_deref(), Some("secret"));
+    assert_eq!(pgw.http.timeout, Some(5));
+    assert_eq!(pgw.http.headers.unwrap()["X-Scope-OrgID"], "horizon");
+
+    // errors name the key of the missing file
+    let cfg = config_with(
+        "https://localhost",
+        common::PASSWORD,
+        "influxdb:\n    url: 'http://localhost:8086'\n    api_version: 2\n    org: 'horizon'\n    bucket: 'horizon'\n    token_file: '/nonexistent/influxdb.token'\n",
+    );
+    let err = cfg.influxdb.unwrap().get_token().unwrap_err().to_string();
+    assert!(err.contains("can't read token_file"), "{}", err);
+
+    for (extra, error) in [
+        (
+            "influxdb:\n    url: 'http://localhost:8086'\n    database: 'horizon'\n    auth:\n        password: 'secret'\n",
+            "Missing user for basic authentication",
+        ),
+        (
+            "otlp:\n    url: 'http://localhost:4318'\n    auth:\n        password: 'secret'\n        password_file: '/secret'\n",
+            "Only one of password or password_file",
+        ),
+        (
+            "remote_write:\n    url: 'http://localhost:9090'\n    http:\n        timeout: 0\n",
+            "timeout must be greater than 0",
+        ),
+    ] {
+        let file = common::write_config("https://localhost", common::PASSWORD, extra);
+        let err = configuration::parse_config_file(Some(&file.to_string_lossy())).unwrap_err();
+        let _ = std::fs::remove_file(&file);
+        assert!(err.to_string().contains(error), "{}", err);
+    }
+
+    // unknown keys of the shared settings are reported with their name and line
+    let file = common::write_config(
+        "https://localhost",
+        common::PASSWORD,
+        "pushgateway:\n    url: 'http://localhost:9091'\n    user: 'push'\n    auth:\n        usr: 'push'\n",
+    );
+    let unknown = configuration::find_unknown_keys(Some(&file.to_string_lossy())).unwrap();
+    let _ = std::fs::remove_file(&file);
+    assert_eq!(unknown.len(), 2);
+    assert!(unknown[0].contains("pushgateway.user"), "{}", unknown[0]);
+    assert!(unknown[0].contains("line 9"), "{}", unknown[0]);
+    assert!(
+        unknown[1].contains("pushgateway.auth.usr"),
+        "{}",
+        unknown[1]
+    );
+    assert!(unknown[1].contains("line 11"), "{}", unknown[1]);
+}
+
+#[test]
+fn configuration_error_lines() {
+    for (extra, error) in [
+        ("    timeout: 'abc'\n", "line 7"),
+        (
+            "    only_pools:\n        - 'pool-1'\n    skip_pools:\n        - 'pool-1'\n",
+            "line 8",
+        ),
+        (
+            "exporter:\n    scrape_interval: 60\n    ready_intervals: 3\n    http_threads: 0\n",
+            "line 10",
+        ),
+    ] {
+        let file = common::write_config("https://localhost", common::PASSWORD, extra);
+        let err = configuration::parse_config_file(Some(&file.to_string_lossy())).unwrap_err();
+        let _ = std::fs::remove_file(&file);
+        assert!(err.to_string().contains(error), "{}", err);
+    }
+}
+
+#[test]
+fn empty_password_sources() {
+    let cfg = config("https://localhost", common::PASSWORD);
+    let password_file = std::env::temp_dir().join(format!(
+        "vmware_horizon-exporter-empty-password-{}",
+        std::process::id()
+    ));
+    std::fs::write(&password_file, "\n").unwrap();
+
+    let mut api = cfg.horizon_api.clone();
+    api.password = None;
+    api.password_file = Some(password_file.to_string_lossy().to_string());
+    let err = api.get_password().unwrap_err();
+    assert!(err.to_string().contains("empty password"), "{}", err);
+    let _ = std::fs::remove_file(&password_file);
+
+    let mut api = cfg.horizon_api.clone();
+    api.password = None;
+    api.password_command = Some("printf '\\r\\n'".to_string());
+    let err = api.get_password().unwrap_err();
+    assert!(err.to_string().contains("password command"), "{}", err);
+
+    api.password_command = Some("echo secret".to_string());
+    assert_eq!(api.get_password().unwrap(), "secret");
+}